@@ -0,0 +1,368 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    db::{DbChannelTx, DbCommand, query},
+    hub::SampleHub,
+    types::{Anomaly, Metric, Step, UsageSample},
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AnomalyConfig {
+    pub enabled: bool,
+    /// Age at which a sample weighs half as much in the baseline as the newest one
+    pub half_life: Step,
+    /// Samples deviating from the baseline by more than this many standard deviations are
+    /// anomalies
+    pub sigmas: f64,
+    /// Lower bound of the standard deviation in percentage points, keeps flat series from
+    /// flagging every small change
+    pub min_stddev: f64,
+    /// History the baseline of a series is seeded from when it is first seen, and the gap
+    /// after which a series starts over
+    pub warmup: Step,
+    /// Samples a baseline needs before samples are checked against it
+    pub min_samples: u64,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            half_life: "1h".parse().unwrap(),
+            sigmas: 3.0,
+            min_stddev: 1.0,
+            warmup: "6h".parse().unwrap(),
+            min_samples: 60,
+        }
+    }
+}
+
+/// Exponentially weighted mean and variance of a series. The weight of a sample depends on the
+/// time since the previous one, so gaps in the collection do not distort the baseline.
+#[derive(Debug)]
+struct Baseline {
+    mean: f64,
+    variance: f64,
+    count: u64,
+    updated: DateTime<Utc>,
+}
+
+impl Baseline {
+    fn new(value: f64, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            mean: value,
+            variance: 0.0,
+            count: 1,
+            updated: timestamp,
+        }
+    }
+
+    fn update(&mut self, value: f64, timestamp: DateTime<Utc>, half_life: Duration) {
+        let elapsed = (timestamp - self.updated).num_milliseconds().max(0) as f64;
+        let alpha = 1.0 - 0.5f64.powf(elapsed / half_life.num_milliseconds().max(1) as f64);
+
+        let diff = value - self.mean;
+        let increment = alpha * diff;
+        self.mean += increment;
+        self.variance = (1.0 - alpha) * (self.variance + diff * increment);
+        self.count += 1;
+        self.updated = timestamp;
+    }
+}
+
+/// Keeps a rolling baseline of the CPU and memory percentage of the host and every container,
+/// and stores the samples deviating from it. Never returns when disabled.
+pub async fn start(config: &AnomalyConfig, db_tx: DbChannelTx, hub: SampleHub) -> Result<()> {
+    if !config.enabled {
+        return std::future::pending().await;
+    }
+
+    let mut detector = Detector {
+        db_tx,
+        half_life: config.half_life.to_duration(),
+        warmup: config.warmup.to_duration(),
+        sigmas: config.sigmas,
+        min_stddev: config.min_stddev,
+        min_samples: config.min_samples,
+        baselines: HashMap::new(),
+    };
+    let mut samples = hub.subscribe();
+
+    loop {
+        match samples.recv().await {
+            Ok(sample) => detector.check(sample).await,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Anomaly detection fell behind, skipped {missed} samples");
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+struct Detector {
+    db_tx: DbChannelTx,
+    half_life: Duration,
+    warmup: Duration,
+    sigmas: f64,
+    min_stddev: f64,
+    min_samples: u64,
+    baselines: HashMap<(Option<String>, Metric), Baseline>,
+}
+
+impl Detector {
+    /// Failures are logged, detection carries on with the next sample
+    async fn check(&mut self, sample: UsageSample) {
+        // rounded like the stored samples the baselines are seeded from
+        let values = [
+            (Metric::Cpu, sample.cpu_usage.percentage.round()),
+            (Metric::Memory, sample.memory_usage.percentage.round()),
+        ];
+
+        for (metric, value) in values {
+            let key = (sample.container.clone(), metric);
+            let stale = self
+                .baselines
+                .get(&key)
+                .is_none_or(|baseline| sample.timestamp - baseline.updated > self.warmup);
+            if stale {
+                // drops the baselines of removed containers along the way
+                let warmup = self.warmup;
+                self.baselines
+                    .retain(|_, baseline| sample.timestamp - baseline.updated <= warmup);
+
+                match self.seed(&sample.container, metric, sample.timestamp).await {
+                    Ok(Some(baseline)) => {
+                        self.baselines.insert(key.clone(), baseline);
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Failed to load the baseline of a series: {e}"),
+                }
+            }
+
+            let Some(baseline) = self.baselines.get_mut(&key) else {
+                self.baselines
+                    .insert(key, Baseline::new(value, sample.timestamp));
+                continue;
+            };
+
+            // samples already part of the seeded history
+            if sample.timestamp <= baseline.updated {
+                continue;
+            }
+
+            let stddev = baseline.variance.sqrt().max(self.min_stddev);
+            let score = (value - baseline.mean) / stddev;
+            let anomaly =
+                (baseline.count >= self.min_samples && score.abs() > self.sigmas).then(|| {
+                    Anomaly {
+                        container: sample.container.clone(),
+                        metric,
+                        timestamp: sample.timestamp,
+                        value,
+                        expected: baseline.mean,
+                        score,
+                    }
+                });
+            baseline.update(value, sample.timestamp, self.half_life);
+
+            if let Some(anomaly) = anomaly {
+                log::info!(
+                    "Anomalous {} of {}: {value:.1}, expected {:.1}",
+                    metric.as_str(),
+                    anomaly.container.as_deref().unwrap_or("host"),
+                    anomaly.expected
+                );
+                if let Err(e) = self.db_tx.send(DbCommand::InsertAnomaly { anomaly }).await {
+                    log::error!("Failed to store an anomaly: {e}");
+                }
+            }
+        }
+    }
+
+    /// Baseline of the stored samples within the warmup before `until`, `None` without any
+    async fn seed(
+        &self,
+        container: &Option<String>,
+        metric: Metric,
+        until: DateTime<Utc>,
+    ) -> Result<Option<Baseline>> {
+        let from = Some(until - self.warmup);
+        let to = Some(until);
+        let container = container.clone();
+
+        let points: Vec<(DateTime<Utc>, f64)> = match metric {
+            Metric::Cpu => query(&self.db_tx, |respond_to| DbCommand::GetCpuUsageHistory {
+                from,
+                to,
+                limit: None,
+                container,
+                respond_to,
+            })
+            .await?
+            .into_iter()
+            .map(|point| (point.timestamp, point.percentage))
+            .collect(),
+            Metric::Memory => query(&self.db_tx, |respond_to| DbCommand::GetMemoryUsageHistory {
+                from,
+                to,
+                limit: None,
+                container,
+                respond_to,
+            })
+            .await?
+            .into_iter()
+            .map(|point| (point.timestamp, point.percentage))
+            .collect(),
+        };
+
+        let mut points = points.into_iter();
+        let Some((timestamp, value)) = points.next() else {
+            return Ok(None);
+        };
+
+        let mut baseline = Baseline::new(value, timestamp);
+        for (timestamp, value) in points {
+            baseline.update(value, timestamp, self.half_life);
+        }
+
+        Ok(Some(baseline))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DatabaseConfig,
+        db::{self, OverflowPolicy, StorageBackend, create_command_channel},
+        types::{AnomalyQuery, CpuUsage, MemoryUsage},
+    };
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn sample(seconds: i64, cpu: f64) -> UsageSample {
+        UsageSample {
+            timestamp: at(seconds),
+            container: None,
+            cpu_usage: CpuUsage { percentage: cpu },
+            memory_usage: MemoryUsage {
+                total: 100,
+                used: 50,
+                percentage: 50.0,
+            },
+        }
+    }
+
+    /// A detector on an in-memory database
+    fn detector(min_samples: u64, min_stddev: f64) -> Detector {
+        let (db_tx, db_rx) = create_command_channel(1024, OverflowPolicy::Block);
+        let config = DatabaseConfig {
+            backend: StorageBackend::Memory,
+            ..Default::default()
+        };
+        db::start(&config, db_rx);
+
+        Detector {
+            db_tx,
+            half_life: Duration::minutes(10),
+            warmup: Duration::hours(1),
+            sigmas: 3.0,
+            min_stddev,
+            min_samples,
+            baselines: HashMap::new(),
+        }
+    }
+
+    async fn anomalies(detector: &Detector) -> Vec<(DateTime<Utc>, f64)> {
+        let anomalies = query(&detector.db_tx, |respond_to| DbCommand::GetAnomalies {
+            query: AnomalyQuery {
+                from: at(-86400),
+                to: at(86400),
+                container: None,
+                metric: Some(Metric::Cpu),
+                limit: None,
+            },
+            respond_to,
+        })
+        .await
+        .unwrap();
+
+        let mut anomalies: Vec<_> = anomalies
+            .into_iter()
+            .map(|anomaly| (anomaly.timestamp, anomaly.value))
+            .collect();
+        anomalies.sort_by_key(|anomaly| anomaly.0);
+        anomalies
+    }
+
+    #[test]
+    fn baselines_follow_the_series_by_half_life() {
+        let half_life = Duration::minutes(10);
+        let mut baseline = Baseline::new(10.0, at(0));
+
+        baseline.update(10.0, at(60), half_life);
+        assert_eq!((baseline.mean, baseline.variance), (10.0, 0.0));
+
+        // a sample one half-life later moves the mean half the way
+        baseline.update(30.0, at(660), half_life);
+        assert_eq!(baseline.mean, 20.0);
+        assert_eq!(baseline.variance, 100.0);
+        assert_eq!(baseline.count, 3);
+
+        // samples out of order do not move it
+        baseline.update(50.0, at(600), half_life);
+        assert_eq!(baseline.mean, 20.0);
+    }
+
+    #[tokio::test]
+    async fn spikes_are_reported_after_the_warmup() {
+        let mut detector = detector(10, 1.0);
+
+        // a noisy series around 20%, with a spike before the baseline has enough samples
+        for i in 0..30 {
+            let cpu = match i {
+                5 => 90.0,
+                i if i % 2 == 0 => 19.0,
+                _ => 21.0,
+            };
+            detector.check(sample(i * 10, cpu)).await;
+        }
+        assert_eq!(anomalies(&detector).await, []);
+
+        detector.check(sample(300, 23.0)).await;
+        detector.check(sample(310, 90.0)).await;
+        detector.check(sample(320, 2.0)).await;
+
+        assert_eq!(anomalies(&detector).await, [(at(310), 90.0)]);
+    }
+
+    #[tokio::test]
+    async fn baselines_are_seeded_from_the_stored_samples() {
+        let mut detector = detector(5, 0.1);
+        for i in 0..10 {
+            let sample = sample(i * 10, 10.4);
+            let command = DbCommand::InsertResourceUsage {
+                timestamp: sample.timestamp,
+                cpu_usage: sample.cpu_usage,
+                memory_usage: sample.memory_usage,
+                container: None,
+            };
+            detector.db_tx.send(command).await.unwrap();
+        }
+
+        // stored as 10, the unrounded live value would be four deviations off
+        detector.check(sample(100, 10.4)).await;
+        detector.check(sample(110, 12.0)).await;
+
+        let baseline = &detector.baselines[&(None, Metric::Cpu)];
+        assert_eq!(baseline.count, 12);
+        assert_eq!(anomalies(&detector).await, [(at(110), 12.0)]);
+    }
+}
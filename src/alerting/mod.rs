@@ -0,0 +1,568 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    config::AlertingConfig,
+    db::{ContainerMatch, DbChannelTx, DbCommand, query},
+    forecast::{self, Confidence, ForecastMethod},
+    notifier::{Notification, Notifier},
+    types::{AlertEvent, AlertState, AnomalyQuery, ContainerInfo, Metric},
+};
+pub use anomaly::{AnomalyConfig, start as detect_anomalies};
+pub use rule::AlertRule;
+use rule::{ALL_CONTAINERS, RuleKind};
+pub use silence::{MaintenanceWindow, Silences};
+
+mod anomaly;
+mod rule;
+mod silence;
+
+/// A pending or firing alert, there is at most one per rule and container
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    /// Full container id, `None` for alerts of the host
+    pub container: Option<String>,
+    pub state: AlertState,
+    /// Value of the latest evaluation
+    pub value: Option<f64>,
+    pub threshold: f64,
+    /// Silenced alerts are not notified about
+    pub silenced: bool,
+    /// When the condition started to hold
+    pub active_since: DateTime<Utc>,
+    pub fired_at: Option<DateTime<Utc>>,
+}
+
+/// The active alerts of the latest evaluation, shared between the rule evaluation and the API.
+#[derive(Clone, Default)]
+pub struct Alerts {
+    active: Arc<RwLock<Vec<Alert>>>,
+}
+
+impl Alerts {
+    pub fn active(&self) -> Vec<Alert> {
+        self.active
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn set(&self, alerts: Vec<Alert>) {
+        *self.active.write().unwrap_or_else(|e| e.into_inner()) = alerts;
+    }
+}
+
+/// Evaluates every rule periodically, never returns when there are no rules.
+pub async fn start(
+    config: &AlertingConfig,
+    db_tx: DbChannelTx,
+    alerts: Alerts,
+    silences: Silences,
+    notifier: Notifier,
+) -> Result<()> {
+    if config.rules.is_empty() {
+        return std::future::pending().await;
+    }
+
+    let mut names = HashSet::new();
+    if let Some(rule) = config.rules.iter().find(|rule| !names.insert(&rule.name)) {
+        return Err(anyhow!("Alert rule name '{}' is not unique", rule.name));
+    }
+    if let Some(rule) = config
+        .rules
+        .iter()
+        .find(|rule| rule.kind == RuleKind::Forecast && rule.metric != Metric::Memory)
+    {
+        return Err(anyhow!(
+            "Alert rule '{}' forecasts memory exhaustion, its metric has to be memory",
+            rule.name
+        ));
+    }
+
+    let mut evaluator = Evaluator {
+        db_tx,
+        alerts,
+        silences,
+        notifier,
+        containers: HashMap::new(),
+        active: HashMap::new(),
+    };
+    let mut ticker = tokio::time::interval(config.interval.to_duration().to_std()?);
+
+    loop {
+        ticker.tick().await;
+
+        let now = Utc::now();
+        evaluator.containers.clear();
+        for rule in &config.rules {
+            if let Err(e) = evaluator.evaluate(rule, now).await {
+                log::error!("Failed to evaluate alert rule '{}': {e}", rule.name);
+            }
+        }
+        evaluator.publish();
+    }
+}
+
+struct Evaluator {
+    db_tx: DbChannelTx,
+    alerts: Alerts,
+    silences: Silences,
+    notifier: Notifier,
+    /// Containers of the current evaluation by id, for matching them against the silences
+    containers: HashMap<String, ContainerInfo>,
+    /// Keyed by rule name and container
+    active: HashMap<(String, Option<String>), Alert>,
+}
+
+impl Evaluator {
+    /// A target failing to evaluate is logged, the rule carries on with the next one
+    async fn evaluate(&mut self, rule: &AlertRule, now: DateTime<Utc>) -> Result<()> {
+        let targets = self.targets(rule, now).await?;
+
+        for container in &targets {
+            let target = container.as_deref().unwrap_or("host");
+            let value = match self.value(rule, container.clone(), now).await {
+                Ok(value) => value,
+                Err(e) => {
+                    log::error!(
+                        "Failed to evaluate alert rule '{}' for {target}: {e}",
+                        rule.name
+                    );
+                    continue;
+                }
+            };
+            self.update(rule, container.clone(), value, now).await?;
+        }
+
+        // containers without samples in the window anymore resolve their alerts
+        let gone: Vec<_> = self
+            .active
+            .keys()
+            .filter(|(name, container)| *name == rule.name && !targets.contains(container))
+            .map(|(_, container)| container.clone())
+            .collect();
+        for container in gone {
+            self.update(rule, container, None, now).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The host (`None`) or the containers the rule is evaluated for
+    async fn targets(
+        &mut self,
+        rule: &AlertRule,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Option<String>>> {
+        match rule.container.as_deref() {
+            None => Ok(vec![None]),
+            Some(ALL_CONTAINERS) => {
+                let samples = query(&self.db_tx, |respond_to| DbCommand::GetLatestSamples {
+                    since: now - rule.window.to_duration(),
+                    respond_to,
+                })
+                .await?;
+
+                Ok(samples
+                    .into_iter()
+                    .filter_map(|sample| sample.container)
+                    .map(|container| {
+                        let id = container.id.clone();
+                        self.containers.insert(id.clone(), container);
+                        Some(id)
+                    })
+                    .collect())
+            }
+            Some(container) => {
+                let result = query(&self.db_tx, |respond_to| DbCommand::ResolveContainer {
+                    container: container.to_string(),
+                    respond_to,
+                })
+                .await?;
+
+                match result {
+                    ContainerMatch::Found(id) => {
+                        if !self.containers.contains_key(&id) {
+                            let overview =
+                                query(&self.db_tx, |respond_to| DbCommand::GetContainer {
+                                    id: id.clone(),
+                                    respond_to,
+                                })
+                                .await?;
+                            if let Some(overview) = overview {
+                                self.containers.insert(id.clone(), overview.info);
+                            }
+                        }
+
+                        Ok(vec![Some(id)])
+                    }
+                    // not seen yet, or removed from the database
+                    ContainerMatch::NotFound => Ok(vec![]),
+                    ContainerMatch::Ambiguous(ids) => Err(anyhow!(
+                        "'{container}' is ambiguous, it matches containers {}",
+                        ids.join(", ")
+                    )),
+                }
+            }
+        }
+    }
+
+    /// The value compared to the threshold of the rule, `None` without samples
+    async fn value(
+        &self,
+        rule: &AlertRule,
+        container: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f64>> {
+        match rule.kind {
+            RuleKind::Threshold => self.aggregate(rule, container, now).await,
+            RuleKind::Anomaly => self.anomalies(rule, container, now).await,
+            RuleKind::Forecast => self.hours_until_exhaustion(rule, container, now).await,
+        }
+    }
+
+    /// Aggregated percentage of the samples in the window of the rule
+    async fn aggregate(
+        &self,
+        rule: &AlertRule,
+        container: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f64>> {
+        let from = Some(now - rule.window.to_duration());
+        let to = Some(now);
+
+        let values: Vec<f64> = match rule.metric {
+            Metric::Cpu => query(&self.db_tx, |respond_to| DbCommand::GetCpuUsageHistory {
+                from,
+                to,
+                limit: None,
+                container,
+                respond_to,
+            })
+            .await?
+            .into_iter()
+            .map(|point| point.percentage)
+            .collect(),
+            Metric::Memory => query(&self.db_tx, |respond_to| DbCommand::GetMemoryUsageHistory {
+                from,
+                to,
+                limit: None,
+                container,
+                respond_to,
+            })
+            .await?
+            .into_iter()
+            .map(|point| point.percentage)
+            .collect(),
+        };
+
+        Ok(rule.agg.apply(&values))
+    }
+
+    /// Number of anomalies in the window of the rule
+    async fn anomalies(
+        &self,
+        rule: &AlertRule,
+        container: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f64>> {
+        let anomalies = query(&self.db_tx, |respond_to| DbCommand::GetAnomalies {
+            query: AnomalyQuery {
+                from: now - rule.window.to_duration(),
+                to: now,
+                container: Some(container),
+                metric: Some(rule.metric),
+                limit: None,
+            },
+            respond_to,
+        })
+        .await?;
+
+        Ok(Some(anomalies.len() as f64))
+    }
+
+    /// Hours until the memory runs out at the trend of the window, `None` while it does not grow
+    /// or the trend is unclear
+    async fn hours_until_exhaustion(
+        &self,
+        rule: &AlertRule,
+        container: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f64>> {
+        let points = query(&self.db_tx, |respond_to| DbCommand::GetMemoryUsageHistory {
+            from: Some(now - rule.window.to_duration()),
+            to: Some(now),
+            limit: None,
+            container,
+            respond_to,
+        })
+        .await?;
+
+        Ok(forecast::memory(&points, ForecastMethod::Robust)
+            .filter(|forecast| forecast.confidence != Confidence::Low)
+            .and_then(|forecast| forecast.hours_until_exhaustion))
+    }
+
+    /// Moves the alert of the rule and container to its next state, recording the transition
+    async fn update(
+        &mut self,
+        rule: &AlertRule,
+        container: Option<String>,
+        value: Option<f64>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let holds = value.is_some_and(|value| rule.matches(value));
+        let info = container.as_ref().and_then(|id| self.containers.get(id));
+        let silenced = self
+            .silences
+            .is_silenced(info, Some(rule.metric), Some(&rule.name), now);
+
+        let state = match (
+            self.active.entry((rule.name.clone(), container.clone())),
+            holds,
+        ) {
+            (Entry::Vacant(_), false) => return Ok(()),
+            (Entry::Vacant(entry), true) => {
+                let state = match rule.pending_for {
+                    Some(_) => AlertState::Pending,
+                    None => AlertState::Firing,
+                };
+                entry.insert(Alert {
+                    rule: rule.name.clone(),
+                    container: container.clone(),
+                    state,
+                    value,
+                    threshold: rule.threshold,
+                    silenced,
+                    active_since: now,
+                    fired_at: (state == AlertState::Firing).then_some(now),
+                });
+
+                state
+            }
+            (Entry::Occupied(mut entry), true) => {
+                let alert = entry.get_mut();
+                alert.value = value;
+                alert.silenced = silenced;
+
+                let due = rule.pending_for.is_none_or(|pending_for| {
+                    now - alert.active_since >= pending_for.to_duration()
+                });
+                if alert.state != AlertState::Pending || !due {
+                    return Ok(());
+                }
+
+                alert.state = AlertState::Firing;
+                alert.fired_at = Some(now);
+
+                AlertState::Firing
+            }
+            (Entry::Occupied(entry), false) => match entry.remove().state {
+                AlertState::Firing => AlertState::Resolved,
+                _ => AlertState::Inactive,
+            },
+        };
+
+        let event = AlertEvent {
+            rule: rule.name.clone(),
+            container,
+            state,
+            value,
+            threshold: rule.threshold,
+            timestamp: now,
+        };
+
+        let target = event.container.as_deref().unwrap_or("host");
+        match state {
+            AlertState::Firing => log::warn!("Alert '{}' is firing for {target}", rule.name),
+            AlertState::Resolved => log::info!("Alert '{}' resolved for {target}", rule.name),
+            _ => {}
+        }
+        // pending alerts may never fire, only firing and resolving is worth a notification
+        if matches!(state, AlertState::Firing | AlertState::Resolved) && !silenced {
+            self.notifier.notify(Notification::Alert(event.clone()));
+        }
+
+        self.db_tx
+            .send(DbCommand::InsertAlertEvent { event })
+            .await?;
+
+        Ok(())
+    }
+
+    fn publish(&self) {
+        let mut alerts: Vec<_> = self.active.values().cloned().collect();
+        alerts.sort_by(|a, b| (&a.rule, &a.container).cmp(&(&b.rule, &b.container)));
+
+        self.alerts.set(alerts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::db::{DbChannelRx, OverflowPolicy, create_command_channel};
+
+    fn rule(pending_for: Option<&str>) -> AlertRule {
+        let pending_for = pending_for.map_or(String::new(), |step| format!("for = '{step}'"));
+        toml::from_str(&format!(
+            "name = 'cpu'\nmetric = 'cpu'\nop = '>'\nthreshold = 90\n{pending_for}"
+        ))
+        .unwrap()
+    }
+
+    fn evaluator() -> (Evaluator, DbChannelRx, mpsc::Receiver<Notification>) {
+        let (db_tx, db_rx) = create_command_channel(16, OverflowPolicy::Block);
+        let silences = Silences::new(vec![]);
+        let (notifier, notifications) = crate::notifier::create_channel(silences.clone());
+        let evaluator = Evaluator {
+            db_tx,
+            alerts: Alerts::default(),
+            silences,
+            notifier,
+            containers: HashMap::new(),
+            active: HashMap::new(),
+        };
+
+        (evaluator, db_rx, notifications)
+    }
+
+    /// Evaluates `value` at `seconds`, returns the recorded transition and whether it was
+    /// notified
+    async fn step(
+        (evaluator, db_rx, notifications): &mut (
+            Evaluator,
+            DbChannelRx,
+            mpsc::Receiver<Notification>,
+        ),
+        rule: &AlertRule,
+        seconds: i64,
+        value: Option<f64>,
+    ) -> Option<(AlertState, bool)> {
+        let now = DateTime::from_timestamp(seconds, 0).unwrap();
+        evaluator.update(rule, None, value, now).await.unwrap();
+
+        if evaluator.db_tx.stats().depth == 0 {
+            assert!(notifications.try_recv().is_err());
+            return None;
+        }
+        let Some(DbCommand::InsertAlertEvent { event }) = db_rx.blocking_recv() else {
+            panic!("expected an alert event");
+        };
+
+        Some((event.state, notifications.try_recv().is_ok()))
+    }
+
+    fn state(evaluator: &Evaluator) -> Option<AlertState> {
+        let alert = evaluator.active.get(&("cpu".to_string(), None))?;
+        Some(alert.state)
+    }
+
+    #[tokio::test]
+    async fn fires_at_once_without_for() {
+        let rule = rule(None);
+        let mut evaluator = evaluator();
+
+        assert_eq!(step(&mut evaluator, &rule, 0, Some(50.0)).await, None);
+        assert_eq!(
+            step(&mut evaluator, &rule, 10, Some(95.0)).await,
+            Some((AlertState::Firing, true))
+        );
+        assert_eq!(state(&evaluator.0), Some(AlertState::Firing));
+        assert_eq!(step(&mut evaluator, &rule, 20, Some(99.0)).await, None);
+        assert_eq!(
+            step(&mut evaluator, &rule, 30, Some(90.0)).await,
+            Some((AlertState::Resolved, true))
+        );
+        assert_eq!(state(&evaluator.0), None);
+    }
+
+    #[tokio::test]
+    async fn fires_once_the_condition_held_for_long_enough() {
+        let rule = rule(Some("1m"));
+        let mut evaluator = evaluator();
+
+        assert_eq!(
+            step(&mut evaluator, &rule, 0, Some(95.0)).await,
+            Some((AlertState::Pending, false))
+        );
+        assert_eq!(step(&mut evaluator, &rule, 59, Some(95.0)).await, None);
+        assert_eq!(state(&evaluator.0), Some(AlertState::Pending));
+        assert_eq!(
+            step(&mut evaluator, &rule, 60, Some(95.0)).await,
+            Some((AlertState::Firing, true))
+        );
+
+        let alert = &evaluator.0.active[&("cpu".to_string(), None)];
+        assert_eq!(alert.active_since, DateTime::from_timestamp(0, 0).unwrap());
+        assert_eq!(alert.fired_at, DateTime::from_timestamp(60, 0));
+    }
+
+    #[tokio::test]
+    async fn pending_alerts_go_back_to_inactive() {
+        let rule = rule(Some("1m"));
+        let mut evaluator = evaluator();
+
+        step(&mut evaluator, &rule, 0, Some(95.0)).await;
+        assert_eq!(
+            step(&mut evaluator, &rule, 30, Some(10.0)).await,
+            Some((AlertState::Inactive, false))
+        );
+
+        // the pending time starts over
+        step(&mut evaluator, &rule, 40, Some(95.0)).await;
+        assert_eq!(step(&mut evaluator, &rule, 90, Some(95.0)).await, None);
+        assert_eq!(
+            step(&mut evaluator, &rule, 100, Some(95.0)).await,
+            Some((AlertState::Firing, true))
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_samples_resolve_firing_alerts() {
+        let rule = rule(None);
+        let mut evaluator = evaluator();
+
+        step(&mut evaluator, &rule, 0, Some(95.0)).await;
+        assert_eq!(
+            step(&mut evaluator, &rule, 10, None).await,
+            Some((AlertState::Resolved, true))
+        );
+        assert_eq!(step(&mut evaluator, &rule, 20, None).await, None);
+    }
+
+    #[tokio::test]
+    async fn active_alerts_are_published_in_order() {
+        let rule = rule(None);
+        let (mut evaluator, _db_rx, _notifications) = evaluator();
+        let now = Utc::now();
+
+        for container in [Some("b"), None, Some("a")] {
+            let container = container.map(str::to_string);
+            evaluator
+                .update(&rule, container, Some(95.0), now)
+                .await
+                .unwrap();
+        }
+        evaluator.publish();
+
+        let containers: Vec<_> = evaluator
+            .alerts
+            .active()
+            .into_iter()
+            .map(|alert| alert.container)
+            .collect();
+        assert_eq!(
+            containers,
+            [None, Some("a".to_string()), Some("b".to_string())]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Aggregation, Metric, Step};
+
+/// Matches every container with samples in the window of a rule
+pub const ALL_CONTAINERS: &str = "*";
+
+/// A threshold on the percentage of a metric, e.g. "host cpu avg over 5m > 90":
+///
+/// ```toml
+/// [[alerting.rules]]
+/// name = "host-cpu"
+/// metric = "cpu"
+/// agg = "avg"
+/// window = "5m"
+/// op = ">"
+/// threshold = 90
+/// ```
+///
+/// Rules of `type = "anomaly"` compare the number of anomalous samples in the window instead,
+/// e.g. `op = ">="` and `threshold = 3` for at least three anomalies. Rules of
+/// `type = "forecast"` compare the hours until the memory runs out at the trend of the window,
+/// e.g. `metric = "memory"`, `window = "6h"`, `op = "<"` and `threshold = 24`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// Unique among the rules, identifies the rule in the alerts and the alert history
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: RuleKind,
+    /// Container name, id or id prefix, `*` evaluates the rule for every container on its own.
+    /// The rule applies to the host when missing.
+    pub container: Option<String>,
+    pub metric: Metric,
+    /// Ignored by anomaly and forecast rules
+    #[serde(default)]
+    pub agg: Aggregation,
+    /// Samples of this window are aggregated
+    #[serde(default = "default_window")]
+    pub window: Step,
+    pub op: Comparison,
+    /// Compared to the CPU or memory percentage, the number of anomalies, or hours
+    pub threshold: f64,
+    /// How long the condition has to hold before the alert fires, immediately when missing
+    #[serde(default, rename = "for")]
+    pub pending_for: Option<Step>,
+}
+
+fn default_window() -> Step {
+    "1m".parse().unwrap()
+}
+
+impl AlertRule {
+    /// Whether `value` violates the threshold of the rule
+    pub fn matches(&self, value: f64) -> bool {
+        self.op.compare(value, self.threshold)
+    }
+}
+
+/// What a rule compares to its threshold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// The aggregated percentage of the samples in the window
+    #[default]
+    Threshold,
+    /// The number of samples in the window flagged by the anomaly detection
+    Anomaly,
+    /// Hours until the used memory reaches the limit at the robust trend of the window, see
+    /// `/{container}/memory/forecast`. Only forecasts of medium or high confidence count.
+    Forecast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AboveOrEqual,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    BelowOrEqual,
+}
+
+impl Comparison {
+    fn compare(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AboveOrEqual => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::BelowOrEqual => value <= threshold,
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Days, NaiveTime, Utc, Weekday};
+use serde::Deserialize;
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    db::{DbChannelTx, DbCommand, query},
+    types::{ContainerInfo, Metric, Silence, SilenceMatcher, Step},
+};
+
+/// A silence recurring on a schedule, e.g. for weekly deploys:
+///
+/// ```toml
+/// [[alerting.maintenance_windows]]
+/// name = "deploys"
+/// days = ["tue", "thu"]
+/// start = "22:00"
+/// duration = "2h"
+/// label = "com.docker.compose.project=shop"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceWindow {
+    pub name: String,
+    /// Days the window starts on, every day when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Time of day in UTC
+    pub start: TimeOfDay,
+    pub duration: Step,
+    #[serde(flatten)]
+    pub matcher: SilenceMatcher,
+}
+
+impl MaintenanceWindow {
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let duration = self.duration.to_duration();
+        let today = now.date_naive();
+
+        // windows longer than a day can have started several days ago
+        (0..=duration.num_days() as u64 + 1)
+            .filter_map(|days_ago| today.checked_sub_days(Days::new(days_ago)))
+            .filter(|day| self.days.is_empty() || self.days.contains(&day.weekday()))
+            .map(|day| day.and_time(self.start.0).and_utc())
+            .any(|start| start <= now && now < start + duration)
+    }
+}
+
+/// `HH:MM` or `HH:MM:SS`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(NaiveTime);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(&value, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(&value, "%H:%M:%S"))
+            .map(Self)
+            .map_err(|_| format!("Invalid time of day '{value}', expected HH:MM"))
+    }
+}
+
+/// The silences of the database together with the maintenance windows of the config, shared
+/// between the rule evaluation, the notifier and the API.
+#[derive(Clone)]
+pub struct Silences {
+    /// Copy of the silences that have not ended yet when they were last loaded
+    silences: Arc<RwLock<Vec<Silence>>>,
+    windows: Arc<Vec<MaintenanceWindow>>,
+}
+
+impl Silences {
+    pub fn new(windows: Vec<MaintenanceWindow>) -> Self {
+        Self {
+            silences: Default::default(),
+            windows: Arc::new(windows),
+        }
+    }
+
+    /// Has to be called whenever the silences in the database change
+    pub async fn reload(&self, db_tx: &DbChannelTx) -> Result<()> {
+        let silences = query(db_tx, |respond_to| DbCommand::GetSilences {
+            ended_after: Utc::now(),
+            respond_to,
+        })
+        .await?;
+
+        *self.silences.write().unwrap_or_else(|e| e.into_inner()) = silences;
+
+        Ok(())
+    }
+
+    /// `container` is `None` for the host, `metric` and `rule` are `None` for container events
+    pub fn is_silenced(
+        &self,
+        container: Option<&ContainerInfo>,
+        metric: Option<Metric>,
+        rule: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let silenced = self
+            .silences
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .any(|silence| {
+                silence.is_active(now) && silence.matcher.matches(container, metric, rule)
+            });
+
+        if silenced {
+            return true;
+        }
+
+        let window = self.windows.iter().find(|window| {
+            window.is_active(now) && window.matcher.matches(container, metric, rule)
+        });
+        if let Some(window) = window {
+            log::debug!("Silenced by maintenance window '{}'", window.name);
+        }
+
+        window.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    fn window(days: &str, start: &str, duration: &str) -> MaintenanceWindow {
+        toml::from_str(&format!(
+            "name = 'deploys'\ndays = [{days}]\nstart = '{start}'\nduration = '{duration}'"
+        ))
+        .unwrap()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn daily_windows_cross_midnight() {
+        let window = window("", "22:00", "4h");
+
+        assert!(!window.is_active(at("2024-01-05 21:59")));
+        assert!(window.is_active(at("2024-01-05 22:00")));
+        assert!(window.is_active(at("2024-01-06 01:59")));
+        assert!(!window.is_active(at("2024-01-06 02:00")));
+        assert!(!window.is_active(at("2024-01-06 12:00")));
+    }
+
+    #[test]
+    fn windows_start_on_their_days_only() {
+        // 2024-01-05 is a Friday
+        let window = window("'fri'", "22:00", "4h");
+
+        assert!(window.is_active(at("2024-01-05 23:00")));
+        assert!(window.is_active(at("2024-01-06 01:00")));
+        assert!(!window.is_active(at("2024-01-06 23:00")));
+        assert!(!window.is_active(at("2024-01-04 23:00")));
+        assert!(window.is_active(at("2024-01-12 22:30")));
+    }
+
+    #[test]
+    fn windows_cross_the_end_of_the_week() {
+        // 2024-01-07 is a Sunday
+        let window = window("'sun'", "23:00", "2h");
+
+        assert!(window.is_active(at("2024-01-07 23:30")));
+        assert!(window.is_active(at("2024-01-08 00:59")));
+        assert!(!window.is_active(at("2024-01-08 01:00")));
+        assert!(!window.is_active(at("2024-01-08 23:30")));
+    }
+
+    #[test]
+    fn windows_longer_than_a_day() {
+        // 2024-01-01 is a Monday
+        let window = window("'mon', 'fri'", "00:00", "3d");
+
+        assert!(window.is_active(at("2024-01-03 23:59")));
+        assert!(!window.is_active(at("2024-01-04 00:00")));
+        assert!(window.is_active(at("2024-01-07 12:00")));
+        // the friday window ends when the next monday one starts
+        assert!(window.is_active(at("2024-01-08 00:00")));
+        assert!(!window.is_active(at("2024-01-11 00:00")));
+    }
+
+    #[test]
+    fn silences_and_windows_apply_to_matching_targets() {
+        let mut window = window("", "02:00", "1h");
+        window.matcher.metric = Some(Metric::Memory);
+        let silences = Silences::new(vec![window]);
+        *silences.silences.write().unwrap() = vec![Silence {
+            id: 1,
+            matcher: SilenceMatcher {
+                rule: Some("busy".to_string()),
+                ..Default::default()
+            },
+            comment: None,
+            starts_at: at("2024-01-05 10:00"),
+            ends_at: at("2024-01-05 11:00"),
+            created_at: at("2024-01-05 09:00"),
+        }];
+        let silenced =
+            |metric, rule, time| silences.is_silenced(None, Some(metric), Some(rule), at(time));
+
+        assert!(silenced(Metric::Cpu, "busy", "2024-01-05 10:30"));
+        assert!(!silenced(Metric::Cpu, "busy", "2024-01-05 11:00"));
+        assert!(!silenced(Metric::Cpu, "idle", "2024-01-05 10:30"));
+        assert!(silenced(Metric::Memory, "idle", "2024-01-05 02:30"));
+        assert!(!silenced(Metric::Cpu, "idle", "2024-01-05 02:30"));
+    }
+}
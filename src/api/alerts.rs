@@ -0,0 +1,74 @@
+use axum::{
+    Json,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+
+use super::{ApiError, export::ExportFormat, extract::ApiQuery, query};
+use crate::{
+    alerting::Alerts,
+    db::{DbChannelTx, DbCommand},
+    types::{AlertState, TimeSpec},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct AlertsQueryParams {
+    /// `pending` or `firing`, both when missing
+    state: Option<AlertState>,
+}
+
+/// Pending and firing alerts as of the latest evaluation of the rules
+pub async fn list(
+    State(alerts): State<Alerts>,
+    ApiQuery(params): ApiQuery<AlertsQueryParams>,
+) -> impl IntoResponse {
+    let mut alerts = alerts.active();
+    if let Some(state) = params.state {
+        alerts.retain(|alert| alert.state == state);
+    }
+
+    Json(alerts)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertHistoryQueryParams {
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+/// State transitions of all alerts, newest first
+pub async fn history(
+    State(tx): State<DbChannelTx>,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<AlertHistoryQueryParams>,
+) -> Result<Response, ApiError> {
+    let now = Utc::now();
+    let from = params
+        .from
+        .map_or(Ok(Utc.timestamp_opt(0, 0).unwrap()), |from| {
+            from.resolve(now)
+        })
+        .map_err(ApiError::BadRequest)?;
+    let to = params
+        .to
+        .map_or(Ok(now), |to| to.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+
+    let events = query(&tx, |respond_to| DbCommand::GetAlertHistory {
+        from,
+        to,
+        limit: params.limit,
+        respond_to,
+    })
+    .await?;
+
+    Ok(format.respond(events))
+}
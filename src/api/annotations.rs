@@ -0,0 +1,140 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+
+use super::{
+    ApiError,
+    export::ExportFormat,
+    extract::{ApiJson, ApiPath, ApiQuery, resolve_container},
+    query,
+};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::{Annotation, AnnotationQuery, TimeSpec},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationsQueryParams {
+    /// Defaults to 24 hours before `to`
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    tag: Option<String>,
+    /// Container name, id or id prefix, the annotations without a container are included.
+    /// `host` only matches those.
+    container: Option<String>,
+}
+
+/// Annotations overlapping the time range, by timestamp
+pub async fn list(
+    State(tx): State<DbChannelTx>,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<AnnotationsQueryParams>,
+) -> Result<Response, ApiError> {
+    let now = Utc::now();
+    let to = params
+        .to
+        .map_or(Ok(now), |to| to.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+    let from = TimeSpec::resolve_or_before(params.from, now, to, Duration::hours(24))
+        .map_err(ApiError::BadRequest)?;
+
+    let container = match params.container.as_deref() {
+        None => None,
+        Some("host") => Some(None),
+        Some(container) => Some(Some(resolve_container(&tx, container).await?)),
+    };
+
+    let annotations = query(&tx, |respond_to| DbCommand::GetAnnotations {
+        query: AnnotationQuery {
+            from: Some(from),
+            to: Some(to),
+            container,
+            tag: params.tag,
+        },
+        respond_to,
+    })
+    .await?;
+
+    Ok(format.respond(annotations))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAnnotationRequest {
+    /// Defaults to now
+    timestamp: Option<TimeSpec>,
+    /// End of a range, a point in time when missing
+    ends_at: Option<TimeSpec>,
+    text: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Container name, id or id prefix, the annotation shows up for every series when missing
+    container: Option<String>,
+}
+
+/// Marks a deploy, an incident or anything else on the timeline
+pub async fn create(
+    State(tx): State<DbChannelTx>,
+    ApiJson(request): ApiJson<CreateAnnotationRequest>,
+) -> Result<Response, ApiError> {
+    let now = Utc::now();
+    let timestamp = request
+        .timestamp
+        .map_or(Ok(now), |timestamp| timestamp.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+    let ends_at = request
+        .ends_at
+        .map(|ends_at| ends_at.resolve(now))
+        .transpose()
+        .map_err(ApiError::BadRequest)?;
+
+    if request.text.trim().is_empty() {
+        return Err(ApiError::BadRequest("'text' must not be empty".to_string()));
+    }
+    if ends_at.is_some_and(|ends_at| ends_at < timestamp) {
+        return Err(ApiError::BadRequest(
+            "'ends_at' has to be after 'timestamp'".to_string(),
+        ));
+    }
+
+    let container = match request.container {
+        Some(container) => Some(resolve_container(&tx, &container).await?),
+        None => None,
+    };
+
+    let annotation = Annotation {
+        id: 0,
+        container,
+        timestamp,
+        ends_at,
+        text: request.text,
+        tags: request.tags,
+    };
+    let annotation = query(&tx, |respond_to| DbCommand::InsertAnnotation {
+        annotation,
+        respond_to,
+    })
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(annotation)).into_response())
+}
+
+pub async fn delete(
+    State(tx): State<DbChannelTx>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<StatusCode, ApiError> {
+    let deleted = query(&tx, |respond_to| DbCommand::DeleteAnnotation {
+        id,
+        respond_to,
+    })
+    .await?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("No annotation with id {id}")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
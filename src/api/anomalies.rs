@@ -0,0 +1,65 @@
+use axum::{extract::State, response::Response};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+
+use super::{
+    ApiError,
+    export::ExportFormat,
+    extract::{ApiQuery, resolve_container},
+    query,
+};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::{AnomalyQuery, Metric, TimeSpec},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct AnomaliesQueryParams {
+    /// Defaults to 24 hours before `to`
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    /// Container name, id or id prefix, or `host`. All series when missing.
+    container: Option<String>,
+    metric: Option<Metric>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+/// Samples that deviated from the baseline of their series, newest first
+pub async fn list(
+    State(tx): State<DbChannelTx>,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<AnomaliesQueryParams>,
+) -> Result<Response, ApiError> {
+    let now = Utc::now();
+    let to = params
+        .to
+        .map_or(Ok(now), |to| to.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+    let from = TimeSpec::resolve_or_before(params.from, now, to, Duration::hours(24))
+        .map_err(ApiError::BadRequest)?;
+
+    let container = match params.container.as_deref() {
+        None => None,
+        Some("host") => Some(None),
+        Some(container) => Some(Some(resolve_container(&tx, container).await?)),
+    };
+
+    let anomalies = query(&tx, |respond_to| DbCommand::GetAnomalies {
+        query: AnomalyQuery {
+            from,
+            to,
+            container,
+            metric: params.metric,
+            limit: Some(params.limit),
+        },
+        respond_to,
+    })
+    .await?;
+
+    Ok(format.respond(anomalies))
+}
@@ -0,0 +1,189 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use super::{
+    ApiError,
+    extract::{ApiPath, ApiQuery, resolve_container},
+    metrics::STALENESS,
+    query,
+};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::{Aggregation, ContainerOverview, Metric, Step, TimeSpec},
+};
+
+/// Status of containers that disappeared from docker, the last reported status is stale for them
+const REMOVED: &str = "removed";
+
+/// Upper bound of the containers ranked in a single request
+const MAX_TOP_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ContainersQueryParams {
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    /// Comma separated `key` or `key=value` pairs, a container has to match all of them
+    label: Option<String>,
+    /// Image name, with or without a tag
+    image: Option<String>,
+    /// Comma separated statuses, e.g. `running,exited`
+    status: Option<String>,
+}
+
+impl ContainersQueryParams {
+    fn matches(&self, container: &ContainerOverview) -> bool {
+        let info = &container.info;
+
+        let labels_match = self.label.as_deref().is_none_or(|labels| {
+            labels
+                .split(',')
+                .map(str::trim)
+                .all(|label| info.has_label(label))
+        });
+
+        let image_match = self.image.as_deref().is_none_or(|image| {
+            info.image
+                .strip_prefix(image)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with([':', '@']))
+        });
+
+        let status_match = self.status.as_deref().is_none_or(|statuses| {
+            statuses
+                .split(',')
+                .map(str::trim)
+                .any(|status| status == info.status)
+        });
+
+        labels_match && image_match && status_match
+    }
+}
+
+/// Containers the collector has not seen recently are not known to docker anymore
+fn update_status(container: &mut ContainerOverview, now: DateTime<Utc>) {
+    if container.last_seen < now - STALENESS {
+        container.info.status = REMOVED.to_string();
+    }
+}
+
+pub async fn list(
+    State(tx): State<DbChannelTx>,
+    ApiQuery(params): ApiQuery<ContainersQueryParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let now = Utc::now();
+    let from = params
+        .from
+        .map_or(Ok(Utc.timestamp_opt(0, 0).unwrap()), |from| {
+            from.resolve(now)
+        })
+        .map_err(ApiError::BadRequest)?;
+    let to = params
+        .to
+        .map_or(Ok(now), |to| to.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+
+    let mut containers = query(&tx, |respond_to| DbCommand::GetContainers {
+        from,
+        to,
+        respond_to,
+    })
+    .await?;
+
+    for container in &mut containers {
+        update_status(container, now);
+    }
+    containers.retain(|container| params.matches(container));
+
+    Ok(Json(containers))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopQueryParams {
+    metric: Metric,
+    /// How far back the samples are aggregated, e.g. `5m` or `1h`
+    #[serde(default = "default_window")]
+    window: Step,
+    #[serde(default)]
+    agg: Aggregation,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_window() -> Step {
+    "1h".parse().unwrap()
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+/// Ranks the containers with a single grouped query, highest value first
+pub async fn top(
+    State(tx): State<DbChannelTx>,
+    ApiQuery(params): ApiQuery<TopQueryParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    if params.agg.to_sql_function().is_none() {
+        return Err(ApiError::BadRequest(
+            "Containers can only be ranked by avg, min, max or count".to_string(),
+        ));
+    }
+    if params.limit > MAX_TOP_LIMIT {
+        return Err(ApiError::BadRequest(format!(
+            "At most {MAX_TOP_LIMIT} containers can be ranked at once"
+        )));
+    }
+
+    let since = Utc::now()
+        .checked_sub_signed(params.window.to_duration())
+        .ok_or_else(|| ApiError::BadRequest("'window' is out of range".to_string()))?;
+    let ranked = query(&tx, |respond_to| DbCommand::GetTopContainers {
+        metric: params.metric,
+        aggregation: params.agg,
+        since,
+        limit: params.limit,
+        respond_to,
+    })
+    .await?;
+
+    Ok(Json(ranked))
+}
+
+pub async fn get(
+    State(tx): State<DbChannelTx>,
+    ApiPath(container): ApiPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = resolve_container(&tx, &container).await?;
+    let overview = query(&tx, |respond_to| DbCommand::GetContainer { id, respond_to }).await?;
+
+    match overview {
+        Some(mut overview) => {
+            update_status(&mut overview, Utc::now());
+            Ok(Json(overview))
+        }
+        None => Err(ApiError::NotFound(format!(
+            "No container matches '{container}'"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{OverflowPolicy, create_command_channel};
+
+    #[tokio::test]
+    async fn top_limit_is_bounded() {
+        let (tx, _rx) = create_command_channel(1, OverflowPolicy::Block);
+        let params = TopQueryParams {
+            metric: Metric::Cpu,
+            window: default_window(),
+            agg: Aggregation::default(),
+            limit: MAX_TOP_LIMIT + 1,
+        };
+
+        let Err(ApiError::BadRequest(message)) = top(State(tx), ApiQuery(params)).await else {
+            panic!("a limit above {MAX_TOP_LIMIT} was accepted");
+        };
+        assert_eq!(message, "At most 1000 containers can be ranked at once");
+    }
+}
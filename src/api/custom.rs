@@ -0,0 +1,90 @@
+use axum::{
+    Json,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+
+use super::{
+    AggregationQueryParams, ApiError, IntervalRouteParams, RangeQueryParams,
+    export::ExportFormat,
+    extract::{ApiPath, ApiQuery, ContainerId, SeriesKey},
+    query,
+};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::CustomDataPoint,
+};
+
+/// Every custom series of the host and the containers, by series key
+pub async fn list(
+    State(tx): State<DbChannelTx>,
+    format: ExportFormat,
+) -> Result<Response, ApiError> {
+    let series = query(&tx, |respond_to| DbCommand::GetCustomSeries { respond_to }).await?;
+
+    Ok(format.respond(series))
+}
+
+pub async fn last(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    SeriesKey(series): SeriesKey,
+) -> Result<impl IntoResponse, ApiError> {
+    let result = query(&tx, |respond_to| DbCommand::GetLastCustomMetric {
+        series,
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(Json(result))
+}
+
+/// Without `?agg=` the plain averaged data points are returned, like for CPU and memory
+pub async fn interval(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    SeriesKey(series): SeriesKey,
+    ApiPath(IntervalRouteParams { interval }): ApiPath<IntervalRouteParams>,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<AggregationQueryParams>,
+) -> Result<Response, ApiError> {
+    let aggregations = params.aggregations();
+    let points = query(&tx, |respond_to| DbCommand::GetIntervalCustomMetric {
+        series,
+        interval,
+        aggregations,
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(match params.agg {
+        Some(_) => format.respond(points),
+        None => format.respond(
+            points
+                .into_iter()
+                .map(CustomDataPoint::from)
+                .collect::<Vec<_>>(),
+        ),
+    })
+}
+
+pub async fn range(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    SeriesKey(series): SeriesKey,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<RangeQueryParams>,
+) -> Result<Response, ApiError> {
+    let range = params.into_range()?;
+    let points = query(&tx, |respond_to| DbCommand::GetRangeCustomMetric {
+        series,
+        range,
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(format.respond(points))
+}
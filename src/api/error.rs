@@ -0,0 +1,125 @@
+use axum::{
+    Json,
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// Malformed path segment or query parameter, e.g. an unknown interval or a bad timestamp
+    BadRequest(String),
+    NotFound(String),
+    /// The database task is not running anymore
+    DbUnavailable,
+    Internal(anyhow::Error),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::DbUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The database is not available".to_string(),
+            ),
+            // the details, like SQL errors, only end up in the log
+            ApiError::Internal(e) => {
+                log::error!("Error while handling request: {e}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+
+        (status, Json(ErrorBody { error })).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(e)
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        if rejection.status().is_client_error() {
+            ApiError::BadRequest(rejection.body_text())
+        } else {
+            ApiError::Internal(anyhow::anyhow!(rejection.body_text()))
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn respond(error: ApiError) -> (StatusCode, String) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_are_answered_as_json() {
+        assert_eq!(
+            respond(ApiError::BadRequest("Unknown interval 'x'".to_string())).await,
+            (
+                StatusCode::BAD_REQUEST,
+                r#"{"error":"Unknown interval 'x'"}"#.to_string()
+            )
+        );
+        assert_eq!(
+            respond(ApiError::NotFound("No such container".to_string()))
+                .await
+                .0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            respond(ApiError::DbUnavailable).await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                r#"{"error":"The database is not available"}"#.to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn internal_errors_hide_their_details() {
+        let error = anyhow::anyhow!("no such table: usage");
+
+        assert_eq!(
+            respond(error.into()).await,
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                r#"{"error":"Internal server error"}"#.to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{FromRequestParts, Query},
+    http::{HeaderMap, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use futures_util::{Stream, StreamExt, future, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::Infallible;
+use tokio::sync::mpsc;
+
+use super::ApiError;
+
+/// Response format chosen by `?format=`, or by the `Accept` header when that is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatParams {
+    format: Option<ExportFormat>,
+}
+
+impl ExportFormat {
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let accepts = |mime: &str| {
+            headers
+                .get_all(header::ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| value.contains(mime))
+        };
+
+        if accepts("text/csv") {
+            ExportFormat::Csv
+        } else if accepts("application/x-ndjson") {
+            ExportFormat::Ndjson
+        } else {
+            ExportFormat::Json
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Serializes all `rows` at once
+    pub fn respond<T: Serialize>(self, rows: Vec<T>) -> Response {
+        if self == ExportFormat::Json {
+            return Json(rows).into_response();
+        }
+
+        let mut encoder = Encoder::new(self);
+        let body: String = rows.iter().map(|row| encoder.encode(row)).collect();
+
+        ([(header::CONTENT_TYPE, self.content_type())], body).into_response()
+    }
+
+    /// Serializes the rows as they arrive, a failing row ends the response early
+    pub fn respond_stream<T: Serialize + Send + 'static>(
+        self,
+        rows: impl Stream<Item = anyhow::Result<T>> + Send + 'static,
+    ) -> Response {
+        let mut encoder = Encoder::new(self);
+        let body = rows
+            .take_while(|row| {
+                if let Err(e) = row {
+                    log::error!("Failed to export row: {e}");
+                }
+                future::ready(row.is_ok())
+            })
+            .filter_map(move |row| future::ready(row.ok().map(|row| encoder.encode(&row))))
+            .map(Ok::<_, Infallible>);
+
+        (
+            [(header::CONTENT_TYPE, self.content_type())],
+            Body::from_stream(body),
+        )
+            .into_response()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ExportFormat {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<FormatParams>::from_request_parts(parts, state).await?;
+
+        Ok(params
+            .format
+            .unwrap_or_else(|| ExportFormat::negotiate(&parts.headers)))
+    }
+}
+
+/// Turns rows into CSV or NDJSON lines. CSV columns are the flattened fields of the first row,
+/// nested objects like aggregations become `percentage_avg`, `percentage_max`, ...
+struct Encoder {
+    format: ExportFormat,
+    header_written: bool,
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Self {
+        Self {
+            format,
+            header_written: false,
+        }
+    }
+
+    fn encode<T: Serialize>(&mut self, row: &T) -> String {
+        let value = serde_json::to_value(row).unwrap_or_default();
+
+        match self.format {
+            ExportFormat::Json | ExportFormat::Ndjson => format!("{value}\n"),
+            ExportFormat::Csv => {
+                let mut columns = vec![];
+                flatten(String::new(), value, &mut columns);
+
+                let mut out = String::new();
+                if !self.header_written {
+                    self.header_written = true;
+                    out += &csv_line(columns.iter().map(|(key, _)| key.clone()));
+                }
+                out += &csv_line(columns.into_iter().map(|(_, value)| match value {
+                    Value::Null => String::new(),
+                    Value::String(value) => value,
+                    value => value.to_string(),
+                }));
+
+                out
+            }
+        }
+    }
+}
+
+fn flatten(prefix: String, value: Value, columns: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{prefix}_{key}")
+                };
+                flatten(key, value, columns);
+            }
+        }
+        value => columns.push((prefix, value)),
+    }
+}
+
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let fields: Vec<_> = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+
+    fields.join(",") + "\n"
+}
+
+/// Rows of an mpsc receiver as a stream
+pub fn receiver_stream<T: Send + 'static>(
+    rx: mpsc::Receiver<T>,
+) -> impl Stream<Item = T> + Send + 'static {
+    stream::unfold(
+        rx,
+        |mut rx| async move { rx.recv().await.map(|row| (row, rx)) },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    fn encode(format: ExportFormat, rows: &[Value]) -> String {
+        let mut encoder = Encoder::new(format);
+        rows.iter().map(|row| encoder.encode(row)).collect()
+    }
+
+    #[test]
+    fn csv_has_a_single_header() {
+        let rows = [json!({"a": 1, "b": "x"}), json!({"a": 2.5, "b": null})];
+
+        assert_eq!(encode(ExportFormat::Csv, &rows), "a,b\n1,x\n2.5,\n");
+    }
+
+    #[test]
+    fn csv_flattens_nested_objects() {
+        let rows = [json!({
+            "timestamp": "2024-01-01T00:00:00Z",
+            "percentage": {"avg": 12.5, "max": 40},
+            "labels": {"job": {"name": "api"}},
+        })];
+
+        assert_eq!(
+            encode(ExportFormat::Csv, &rows),
+            "timestamp,percentage_avg,percentage_max,labels_job_name\n\
+             2024-01-01T00:00:00Z,12.5,40,api\n"
+        );
+    }
+
+    #[test]
+    fn csv_quotes_special_characters() {
+        let rows = [json!({"plain": "a b", "comma": "a,b", "quote": "say \"hi\"", "line": "a\nb"})];
+
+        assert_eq!(
+            encode(ExportFormat::Csv, &rows),
+            "plain,comma,quote,line\na b,\"a,b\",\"say \"\"hi\"\"\",\"a\nb\"\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_has_a_line_per_row() {
+        let rows = [json!({"a": {"b": 1}}), json!({"a": null})];
+
+        assert_eq!(
+            encode(ExportFormat::Ndjson, &rows),
+            "{\"a\":{\"b\":1}}\n{\"a\":null}\n"
+        );
+    }
+
+    #[test]
+    fn format_is_negotiated_from_accept() {
+        let negotiate = |accept: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            ExportFormat::negotiate(&headers)
+        };
+
+        assert_eq!(negotiate("text/csv"), ExportFormat::Csv);
+        assert_eq!(negotiate("application/x-ndjson"), ExportFormat::Ndjson);
+        assert_eq!(negotiate("application/json"), ExportFormat::Json);
+        assert_eq!(negotiate("*/*"), ExportFormat::Json);
+        assert_eq!(
+            ExportFormat::negotiate(&HeaderMap::new()),
+            ExportFormat::Json
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use axum::{
+    Json,
+    extract::{FromRef, FromRequest, FromRequestParts, Path, Query},
+    http::request::Parts,
+};
+use std::collections::{BTreeMap, HashMap};
+
+use super::{ApiError, query};
+use crate::{
+    db::{ContainerMatch, DbChannelTx, DbCommand},
+    types::series_key,
+};
+
+/// [`Path`] that rejects with an [`ApiError`]
+#[derive(FromRequestParts)]
+#[from_request(via(Path), rejection(ApiError))]
+pub struct ApiPath<T>(pub T);
+
+/// [`Query`] that rejects with an [`ApiError`]
+#[derive(FromRequestParts)]
+#[from_request(via(Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
+
+/// [`Json`] body that rejects with an [`ApiError`]
+#[derive(FromRequest)]
+#[from_request(via(Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// The `{container}` path segment resolved to a full container id, `None` on host routes.
+pub struct ContainerId(pub Option<String>);
+
+impl<S> FromRequestParts<S> for ContainerId
+where
+    S: Send + Sync,
+    DbChannelTx: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ApiPath(params) =
+            ApiPath::<HashMap<String, String>>::from_request_parts(parts, state).await?;
+
+        match params.get("container") {
+            Some(container) => {
+                let id = resolve_container(&DbChannelTx::from_ref(state), container).await?;
+                Ok(Self(Some(id)))
+            }
+            None => Ok(Self(None)),
+        }
+    }
+}
+
+/// The `{name}` path segment of a custom series together with its `?labels=` as the series key.
+/// Labels are given as `key=value` pairs separated by commas, e.g. `?labels=region=eu,shop=web`.
+pub struct SeriesKey(pub String);
+
+impl<S> FromRequestParts<S> for SeriesKey
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ApiPath(params) =
+            ApiPath::<HashMap<String, String>>::from_request_parts(parts, state).await?;
+        let ApiQuery(query) =
+            ApiQuery::<HashMap<String, String>>::from_request_parts(parts, state).await?;
+
+        let name = params.get("name").map_or("", String::as_str);
+        let mut labels = BTreeMap::new();
+        for pair in query
+            .get("labels")
+            .into_iter()
+            .flat_map(|labels| labels.split(','))
+        {
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (key, value) = pair.split_once('=').ok_or_else(|| {
+                ApiError::BadRequest(format!("Label '{pair}' is not of the form key=value"))
+            })?;
+            labels.insert(key.to_string(), value.to_string());
+        }
+
+        Ok(Self(series_key(name, &labels)))
+    }
+}
+
+/// Resolves a container id prefix or name to a full container id
+pub async fn resolve_container(db_tx: &DbChannelTx, container: &str) -> Result<String, ApiError> {
+    let result = query(db_tx, |respond_to| DbCommand::ResolveContainer {
+        container: container.to_string(),
+        respond_to,
+    })
+    .await?;
+
+    match result {
+        ContainerMatch::Found(id) => Ok(id),
+        ContainerMatch::NotFound => Err(ApiError::NotFound(format!(
+            "No container matches '{container}'"
+        ))),
+        ContainerMatch::Ambiguous(ids) => Err(ApiError::BadRequest(format!(
+            "'{container}' is ambiguous, it matches containers {}",
+            ids.join(", ")
+        ))),
+    }
+}
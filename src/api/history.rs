@@ -0,0 +1,460 @@
+use axum::{
+    Json,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ApiError,
+    export::{ExportFormat, receiver_stream},
+    extract::{ApiQuery, ContainerId, SeriesKey},
+    query,
+};
+use crate::{
+    db::{DbChannelTx, DbCommand, HistoryReader},
+    downsample::lttb,
+    types::{Annotation, AnnotationQuery, TimeSpec},
+};
+
+/// Opaque position after the last point of a page, `<nanoseconds>.<seen>` in hex. Points sharing
+/// a timestamp are returned in insertion order, `seen` of them were on the previous pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+struct Cursor {
+    timestamp: DateTime<Utc>,
+    seen: usize,
+}
+
+impl Cursor {
+    /// Position after the last of `points`, which follow the points before `previous`
+    fn after<T>(
+        points: &[T],
+        previous: Option<Cursor>,
+        timestamp: impl Fn(&T) -> DateTime<Utc>,
+    ) -> Option<Self> {
+        let last = timestamp(points.last()?);
+        let on_page = points
+            .iter()
+            .rev()
+            .take_while(|point| timestamp(point) == last)
+            .count();
+        let before = previous
+            .filter(|previous| previous.timestamp == last && on_page == points.len())
+            .map_or(0, |previous| previous.seen);
+
+        Some(Self {
+            timestamp: last,
+            seen: before + on_page,
+        })
+    }
+
+    fn encode(self) -> String {
+        format!(
+            "{:x}.{:x}",
+            self.timestamp.timestamp_nanos_opt().unwrap_or_default(),
+            self.seen
+        )
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = || {
+            let (nanos, seen) = value.split_once('.')?;
+            Some(Cursor {
+                timestamp: DateTime::from_timestamp_nanos(i64::from_str_radix(nanos, 16).ok()?),
+                seen: usize::from_str_radix(seen, 16).ok()?,
+            })
+        };
+
+        parse().ok_or_else(|| format!("Invalid cursor '{value}'"))
+    }
+}
+
+/// Header carrying the cursor of the next page in CSV and NDJSON responses
+const NEXT_CURSOR: &str = "x-next-cursor";
+
+#[derive(Debug, Serialize)]
+struct Page<T> {
+    points: Vec<T>,
+    /// `None` on the last page
+    next_cursor: Option<String>,
+    /// Only with `?annotations=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<Vec<Annotation>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQueryParams {
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    /// Downsamples the points with LTTB when there are more of them
+    max_points: Option<usize>,
+    /// Maximum number of points per page
+    limit: Option<usize>,
+    /// `next_cursor` of the previous page
+    cursor: Option<Cursor>,
+    /// Includes the annotations of the time range, like OOM kills, turns the response into a page
+    #[serde(default)]
+    annotations: bool,
+}
+
+/// `from` and `to` of a history query, unbounded when missing
+type Bounds = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+impl HistoryQueryParams {
+    fn validate(&self, format: ExportFormat) -> Result<(), ApiError> {
+        if self.annotations && format != ExportFormat::Json {
+            return Err(ApiError::BadRequest(
+                "'annotations' is only supported by JSON responses".to_string(),
+            ));
+        }
+
+        if self.max_points.is_some_and(|max_points| max_points < 3) {
+            return Err(ApiError::BadRequest(
+                "'max_points' must be at least 3".to_string(),
+            ));
+        }
+
+        if self.limit == Some(0) {
+            return Err(ApiError::BadRequest("'limit' must be positive".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// `from` and `to` resolved against the current time, `from` moved up to the cursor
+    fn bounds(&self) -> Result<Bounds, ApiError> {
+        let now = Utc::now();
+        let resolve = |time: Option<TimeSpec>| {
+            time.map(|time| time.resolve(now))
+                .transpose()
+                .map_err(ApiError::BadRequest)
+        };
+        let (from, to) = (resolve(self.from)?, resolve(self.to)?);
+
+        let from = match self.cursor {
+            Some(cursor) => Some(from.map_or(cursor.timestamp, |from| from.max(cursor.timestamp))),
+            None => from,
+        };
+
+        Ok((from, to))
+    }
+
+    /// One point more than `limit` is fetched, to know whether there is a next page, plus the
+    /// points of the cursor timestamp the previous pages returned
+    fn fetch_limit(&self) -> Option<usize> {
+        let seen = self.cursor.map_or(0, |cursor| cursor.seen);
+        self.limit.map(|limit| limit + 1 + seen)
+    }
+
+    /// Drops the points of the cursor timestamp that were on the previous pages
+    fn skip_seen<T>(&self, mut points: Vec<T>, timestamp: impl Fn(&T) -> DateTime<Utc>) -> Vec<T> {
+        if let Some(cursor) = self.cursor {
+            let seen = points
+                .iter()
+                .take(cursor.seen)
+                .take_while(|point| timestamp(point) == cursor.timestamp)
+                .count();
+            points.drain(..seen);
+        }
+
+        points
+    }
+
+    /// The annotations without a container and those of `container` up to the last point of the
+    /// page, so that consecutive pages do not repeat them. `None` unless requested.
+    async fn annotations<T>(
+        &self,
+        tx: &DbChannelTx,
+        container: Option<String>,
+        (from, to): Bounds,
+        points: &[T],
+        timestamp: impl Fn(&T) -> DateTime<Utc>,
+    ) -> Result<Option<Vec<Annotation>>, ApiError> {
+        if !self.annotations {
+            return Ok(None);
+        }
+
+        let to = match self.limit {
+            Some(limit) if points.len() > limit => Some(timestamp(&points[limit - 1])),
+            _ => to,
+        };
+        // the previous page included the annotations at the cursor
+        let from = match self.cursor {
+            Some(cursor) => from.map(|from| from.max(cursor.timestamp + Duration::nanoseconds(1))),
+            None => from,
+        };
+        let annotations = query(tx, |respond_to| DbCommand::GetAnnotations {
+            query: AnnotationQuery {
+                from,
+                to,
+                container: Some(container),
+                tag: None,
+            },
+            respond_to,
+        })
+        .await?;
+
+        Ok(Some(annotations))
+    }
+
+    /// Unpaginated and full resolution exports are streamed row by row
+    fn is_streamed(&self, format: ExportFormat) -> bool {
+        format != ExportFormat::Json
+            && self.limit.is_none()
+            && self.cursor.is_none()
+            && self.max_points.is_none()
+    }
+
+    /// Paginated or annotated JSON requests get a [`Page`], the others the bare points as before
+    /// pagination existed, CSV and NDJSON carry the next cursor in a header instead. Downsampling
+    /// is applied to the page, the cursor still points after its last raw point.
+    fn respond<T: Serialize>(
+        &self,
+        format: ExportFormat,
+        mut points: Vec<T>,
+        annotations: Option<Vec<Annotation>>,
+        timestamp: impl Fn(&T) -> DateTime<Utc>,
+        value: impl Fn(&T) -> f64,
+    ) -> Response {
+        let next_cursor = match self.limit {
+            Some(limit) if points.len() > limit => {
+                points.truncate(limit);
+                Cursor::after(&points, self.cursor, &timestamp).map(Cursor::encode)
+            }
+            _ => None,
+        };
+
+        let points = match self.max_points {
+            Some(max_points) => lttb(
+                points,
+                max_points,
+                |point| timestamp(point).timestamp_millis() as f64,
+                value,
+            ),
+            None => points,
+        };
+
+        let paginated = self.limit.is_some() || self.cursor.is_some();
+        match format {
+            ExportFormat::Json if paginated || annotations.is_some() => Json(Page {
+                points,
+                next_cursor,
+                annotations,
+            })
+            .into_response(),
+            _ => {
+                let mut response = format.respond(points);
+                if let Some(cursor) = next_cursor.and_then(|cursor| cursor.parse().ok()) {
+                    response.headers_mut().insert(NEXT_CURSOR, cursor);
+                }
+                response
+            }
+        }
+    }
+}
+
+pub async fn cpu_history(
+    State(tx): State<DbChannelTx>,
+    State(reader): State<HistoryReader>,
+    ContainerId(container): ContainerId,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<HistoryQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate(format)?;
+
+    let (from, to) = params.bounds()?;
+    if params.is_streamed(format)
+        && let Some(rows) = reader.cpu_history(from, to, container.clone())
+    {
+        return Ok(format.respond_stream(receiver_stream(rows)));
+    }
+
+    let points = query(&tx, |respond_to| DbCommand::GetCpuUsageHistory {
+        from,
+        to,
+        limit: params.fetch_limit(),
+        container: container.clone(),
+        respond_to,
+    })
+    .await?;
+    let points = params.skip_seen(points, |p| p.timestamp);
+    let annotations = params
+        .annotations(&tx, container, (from, to), &points, |p| p.timestamp)
+        .await?;
+
+    Ok(params.respond(
+        format,
+        points,
+        annotations,
+        |p| p.timestamp,
+        |p| p.percentage,
+    ))
+}
+
+/// Downsampling keeps the shape of the used memory
+pub async fn memory_history(
+    State(tx): State<DbChannelTx>,
+    State(reader): State<HistoryReader>,
+    ContainerId(container): ContainerId,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<HistoryQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate(format)?;
+
+    let (from, to) = params.bounds()?;
+    if params.is_streamed(format)
+        && let Some(rows) = reader.memory_history(from, to, container.clone())
+    {
+        return Ok(format.respond_stream(receiver_stream(rows)));
+    }
+
+    let points = query(&tx, |respond_to| DbCommand::GetMemoryUsageHistory {
+        from,
+        to,
+        limit: params.fetch_limit(),
+        container: container.clone(),
+        respond_to,
+    })
+    .await?;
+    let points = params.skip_seen(points, |p| p.timestamp);
+    let annotations = params
+        .annotations(&tx, container, (from, to), &points, |p| p.timestamp)
+        .await?;
+
+    Ok(params.respond(
+        format,
+        points,
+        annotations,
+        |p| p.timestamp,
+        |p| p.used as f64,
+    ))
+}
+
+/// Custom series are not streamed, unpaginated exports are read in one go
+pub async fn custom_history(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    SeriesKey(series): SeriesKey,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<HistoryQueryParams>,
+) -> Result<Response, ApiError> {
+    params.validate(format)?;
+
+    let (from, to) = params.bounds()?;
+    let points = query(&tx, |respond_to| DbCommand::GetCustomMetricHistory {
+        series,
+        from,
+        to,
+        limit: params.fetch_limit(),
+        container: container.clone(),
+        respond_to,
+    })
+    .await?;
+    let points = params.skip_seen(points, |p| p.timestamp);
+    let annotations = params
+        .annotations(&tx, container, (from, to), &points, |p| p.timestamp)
+        .await?;
+
+    Ok(params.respond(format, points, annotations, |p| p.timestamp, |p| p.value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap()
+    }
+
+    fn params(limit: usize, cursor: Option<Cursor>) -> HistoryQueryParams {
+        HistoryQueryParams {
+            from: None,
+            to: None,
+            max_points: None,
+            limit: Some(limit),
+            cursor,
+            annotations: false,
+        }
+    }
+
+    /// Pages through `points` like the handlers do, the points are `(timestamp, id)`
+    fn paginate(points: &[(DateTime<Utc>, usize)], limit: usize) -> Vec<usize> {
+        let mut ids = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let params = params(limit, cursor);
+            let from = params.bounds().unwrap().0;
+            let page = points
+                .iter()
+                .filter(|(timestamp, _)| from.is_none_or(|from| *timestamp >= from))
+                .take(params.fetch_limit().unwrap())
+                .copied()
+                .collect();
+            let mut page = params.skip_seen(page, |point| point.0);
+
+            let more = page.len() > limit;
+            page.truncate(limit);
+            ids.extend(page.iter().map(|point| point.1));
+            if !more {
+                return ids;
+            }
+
+            let next = Cursor::after(&page, cursor, |point| point.0).unwrap();
+            cursor = Some(Cursor::try_from(next.encode()).unwrap());
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+            seen: 17,
+        };
+
+        assert_eq!(cursor.encode(), "17979cfe3d85cd15.11");
+        assert_eq!(Cursor::try_from(cursor.encode()), Ok(cursor));
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        for value in ["", "12", "x.1", "1.", "1.-1"] {
+            assert!(Cursor::try_from(value.to_string()).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn cursor_counts_points_of_the_last_timestamp() {
+        let points = [at(1), at(2), at(2)];
+        let cursor = Cursor::after(&points, None, |t| *t).unwrap();
+        assert_eq!((cursor.timestamp, cursor.seen), (at(2), 2));
+
+        // the whole page shares the timestamp of the previous cursor
+        let cursor = Cursor::after(&[at(2)], Some(cursor), |t| *t).unwrap();
+        assert_eq!((cursor.timestamp, cursor.seen), (at(2), 3));
+
+        assert_eq!(Cursor::after(&[] as &[DateTime<Utc>], None, |t| *t), None);
+    }
+
+    #[test]
+    fn pages_neither_skip_nor_repeat_points() {
+        let points: Vec<_> = [1, 2, 2, 2, 2, 3, 4, 4, 5]
+            .into_iter()
+            .enumerate()
+            .map(|(id, seconds)| (at(seconds), id))
+            .collect();
+
+        for limit in 1..=points.len() + 1 {
+            assert_eq!(
+                paginate(&points, limit),
+                (0..points.len()).collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    ApiError,
+    extract::{ApiQuery, resolve_container},
+};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::{CustomSample, MetricKind, TimeSpec},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct IngestSample {
+    name: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    kind: MetricKind,
+    value: f64,
+    /// Defaults to now
+    timestamp: Option<TimeSpec>,
+    /// Container name, id or id prefix, the series belongs to the host when missing
+    container: Option<String>,
+}
+
+/// Unit of the line protocol timestamps
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Precision {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl Precision {
+    fn to_timestamp(self, value: i64) -> Option<DateTime<Utc>> {
+        match self {
+            Precision::Ns => Some(DateTime::from_timestamp_nanos(value)),
+            Precision::Us => DateTime::from_timestamp_micros(value),
+            Precision::Ms => DateTime::from_timestamp_millis(value),
+            Precision::S => DateTime::from_timestamp(value, 0),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IngestQueryParams {
+    #[serde(default)]
+    precision: Precision,
+}
+
+/// Stores a batch of custom samples. A JSON body is an array of [`IngestSample`]s, any other
+/// body is read as InfluxDB line protocol, where every field becomes a gauge named
+/// `<measurement>_<field>` and the tags become its labels. A field called `value` keeps the
+/// plain measurement name. The batch is rejected as a whole when any sample is invalid.
+pub async fn ingest(
+    State(tx): State<DbChannelTx>,
+    ApiQuery(params): ApiQuery<IngestQueryParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let now = Utc::now();
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+
+    let samples = if is_json {
+        let samples: Vec<IngestSample> = serde_json::from_slice(&body)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid JSON body: {e}")))?;
+        from_json(&tx, samples, now).await?
+    } else {
+        let body = std::str::from_utf8(&body)
+            .map_err(|_| ApiError::BadRequest("Body is not valid UTF-8".to_string()))?;
+        parse_line_protocol(body, params.precision, now).map_err(ApiError::BadRequest)?
+    };
+
+    if let Some(e) = samples.iter().find_map(|sample| sample.validate().err()) {
+        return Err(ApiError::BadRequest(e));
+    }
+    if samples.is_empty() {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    tx.send(DbCommand::InsertCustomSamples { samples })
+        .await
+        .map_err(|_| ApiError::DbUnavailable)?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Resolves the containers of the samples, each distinct one once
+async fn from_json(
+    tx: &DbChannelTx,
+    samples: Vec<IngestSample>,
+    now: DateTime<Utc>,
+) -> Result<Vec<CustomSample>, ApiError> {
+    let mut containers: HashMap<String, String> = HashMap::new();
+    let mut result = Vec::with_capacity(samples.len());
+
+    for sample in samples {
+        let container = match sample.container {
+            Some(container) => match containers.get(&container) {
+                Some(id) => Some(id.clone()),
+                None => {
+                    let id = resolve_container(tx, &container).await?;
+                    containers.insert(container, id.clone());
+                    Some(id)
+                }
+            },
+            None => None,
+        };
+
+        result.push(CustomSample {
+            name: sample.name,
+            labels: sample.labels,
+            kind: sample.kind,
+            container,
+            value: sample.value,
+            timestamp: sample
+                .timestamp
+                .map_or(Ok(now), |timestamp| timestamp.resolve(now))
+                .map_err(ApiError::BadRequest)?,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Lines look like `measurement,tag=a field=1.5,other=2i 1700000000000000000`. String fields
+/// are skipped, booleans become 0 or 1. Lines without a timestamp are taken at `now`.
+fn parse_line_protocol(
+    body: &str,
+    precision: Precision,
+    now: DateTime<Utc>,
+) -> Result<Vec<CustomSample>, String> {
+    let mut samples = vec![];
+
+    for (number, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        parse_line(line, precision, now, &mut samples)
+            .map_err(|e| format!("Line {}: {e}", number + 1))?;
+    }
+
+    Ok(samples)
+}
+
+fn parse_line(
+    line: &str,
+    precision: Precision,
+    now: DateTime<Utc>,
+    samples: &mut Vec<CustomSample>,
+) -> Result<(), String> {
+    let sections: Vec<_> = split_unescaped(line, ' ')
+        .into_iter()
+        .filter(|section| !section.is_empty())
+        .collect();
+    let (key, fields, timestamp) = match sections.as_slice() {
+        [key, fields] => (key, fields, None),
+        [key, fields, timestamp] => (key, fields, Some(timestamp)),
+        _ => return Err("expected a measurement, fields and an optional timestamp".to_string()),
+    };
+
+    let timestamp = match timestamp {
+        Some(timestamp) => timestamp
+            .parse()
+            .ok()
+            .and_then(|value| precision.to_timestamp(value))
+            .ok_or_else(|| format!("invalid timestamp '{timestamp}'"))?,
+        None => now,
+    };
+
+    let mut key = split_unescaped(key, ',').into_iter();
+    let measurement = unescape(key.next().unwrap_or_default());
+    let mut labels = BTreeMap::new();
+    for tag in key {
+        let (name, value) = split_pair(tag).ok_or_else(|| format!("invalid tag '{tag}'"))?;
+        labels.insert(name, value);
+    }
+
+    for field in split_unescaped(fields, ',') {
+        let (name, value) = split_pair(field).ok_or_else(|| format!("invalid field '{field}'"))?;
+        let Some(value) = parse_field_value(&value)? else {
+            continue;
+        };
+
+        samples.push(CustomSample {
+            name: match name.as_str() {
+                "value" => measurement.clone(),
+                _ => format!("{measurement}_{name}"),
+            },
+            labels: labels.clone(),
+            kind: MetricKind::Gauge,
+            container: None,
+            value,
+            timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// `None` for string fields
+fn parse_field_value(value: &str) -> Result<Option<f64>, String> {
+    if value.starts_with('"') {
+        return Ok(None);
+    }
+
+    let number = match value {
+        "t" | "T" | "true" | "True" | "TRUE" => return Ok(Some(1.0)),
+        "f" | "F" | "false" | "False" | "FALSE" => return Ok(Some(0.0)),
+        _ => value
+            .strip_suffix(['i', 'u'])
+            .unwrap_or(value)
+            .parse::<f64>(),
+    };
+
+    number
+        .map(Some)
+        .map_err(|_| format!("invalid field value '{value}'"))
+}
+
+/// Splits `key=value` at the first unescaped `=`, both sides unescaped
+fn split_pair(pair: &str) -> Option<(String, String)> {
+    match split_unescaped(pair, '=').as_slice() {
+        [key, value] if !key.is_empty() => Some((unescape(key), unescape(value))),
+        _ => None,
+    }
+}
+
+/// Splits at every `delimiter` that is neither escaped by a backslash nor inside a quoted
+/// string field
+fn split_unescaped(value: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut start, mut escaped, mut quoted) = (0, false, false);
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ (',' | '=' | ' ' | '"' | '\\'))) => {
+                result.push(next);
+                chars.next();
+            }
+            (c, _) => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_800_000_000, 0).unwrap()
+    }
+
+    fn parse(body: &str) -> Vec<CustomSample> {
+        parse_line_protocol(body, Precision::Ns, now()).unwrap()
+    }
+
+    fn values(samples: &[CustomSample]) -> Vec<(&str, f64)> {
+        samples
+            .iter()
+            .map(|sample| (sample.name.as_str(), sample.value))
+            .collect()
+    }
+
+    #[test]
+    fn escaped_characters_are_kept() {
+        let samples = parse(r"disk\ io\,total,host=web\ 1,path=C:\\data,a\=b=c\,d value=1 1");
+
+        assert_eq!(values(&samples), [("disk io,total", 1.0)]);
+        assert_eq!(
+            samples[0].labels,
+            BTreeMap::from([
+                ("a=b".to_string(), "c,d".to_string()),
+                ("host".to_string(), "web 1".to_string()),
+                ("path".to_string(), r"C:\data".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn fields_become_gauges() {
+        let samples = parse(
+            r#"jobs,queue=mail value=3,note="a, b=c \"quoted\"",age=5i,size=7u,load=-1.5e3,up=t,down=FALSE 10"#,
+        );
+
+        assert_eq!(
+            values(&samples),
+            [
+                ("jobs", 3.0),
+                ("jobs_age", 5.0),
+                ("jobs_size", 7.0),
+                ("jobs_load", -1500.0),
+                ("jobs_up", 1.0),
+                ("jobs_down", 0.0),
+            ]
+        );
+        assert!(
+            samples
+                .iter()
+                .all(|sample| sample.kind == MetricKind::Gauge)
+        );
+        assert!(
+            samples
+                .iter()
+                .all(|sample| sample.labels["queue"] == "mail")
+        );
+    }
+
+    #[test]
+    fn timestamps_use_the_precision() {
+        let expected = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        for (precision, timestamp) in [
+            (Precision::S, "1700000000"),
+            (Precision::Ms, "1700000000000"),
+            (Precision::Us, "1700000000000000"),
+            (Precision::Ns, "1700000000000000000"),
+        ] {
+            let body = format!("load value=1 {timestamp}");
+            let samples = parse_line_protocol(&body, precision, now()).unwrap();
+            assert_eq!(samples[0].timestamp, expected);
+        }
+
+        assert_eq!(parse("load value=1")[0].timestamp, now());
+    }
+
+    #[test]
+    fn blank_lines_and_comments_are_skipped() {
+        let samples = parse("# comment\n\n  load value=1  \r\nload value=2\n");
+
+        assert_eq!(values(&samples), [("load", 1.0), ("load", 2.0)]);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |body: &str| parse_line_protocol(body, Precision::Ns, now()).unwrap_err();
+
+        assert_eq!(
+            error("load value=1\n\n# comment\nload value=x"),
+            "Line 4: invalid field value 'x'"
+        );
+        assert_eq!(
+            error("load"),
+            "Line 1: expected a measurement, fields and an optional timestamp"
+        );
+        assert_eq!(
+            error("load value=1 1 2"),
+            "Line 1: expected a measurement, fields and an optional timestamp"
+        );
+        assert_eq!(error("load,host value=1"), "Line 1: invalid tag 'host'");
+        assert_eq!(error("load value 1"), "Line 1: invalid field 'value'");
+        assert_eq!(error("load =1"), "Line 1: invalid field '=1'");
+        assert_eq!(
+            error("load value=1 soon"),
+            "Line 1: invalid timestamp 'soon'"
+        );
+        assert_eq!(
+            error("load value=1 99999999999999999999"),
+            "Line 1: invalid timestamp '99999999999999999999'"
+        );
+        assert_eq!(
+            parse_line_protocol("load value=1 99999999999999999", Precision::S, now()),
+            Err("Line 1: invalid timestamp '99999999999999999'".to_string())
+        );
+    }
+}
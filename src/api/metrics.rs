@@ -0,0 +1,316 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use std::fmt::Write;
+
+use super::{ApiError, query};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::LatestSample,
+};
+
+/// Containers without a sample in this window are considered gone and are not exported
+pub(super) const STALENESS: Duration = Duration::minutes(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Prometheus text exposition format 0.0.4
+    Text,
+    OpenMetrics,
+}
+
+impl Format {
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let accepts_open_metrics = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("application/openmetrics-text"));
+
+        if accepts_open_metrics {
+            Format::OpenMetrics
+        } else {
+            Format::Text
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => "text/plain; version=0.0.4; charset=utf-8",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+struct Exposition {
+    format: Format,
+    out: String,
+}
+
+impl Exposition {
+    fn new(format: Format) -> Self {
+        Self {
+            format,
+            out: String::new(),
+        }
+    }
+
+    fn gauge(&mut self, name: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} gauge");
+    }
+
+    /// `name` is without the `_total` suffix, which OpenMetrics leaves off the metadata lines
+    fn counter(&mut self, name: &str, help: &str) {
+        let name = match self.format {
+            Format::Text => format!("{name}_total"),
+            Format::OpenMetrics => name.to_string(),
+        };
+
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} counter");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.out.push_str(name);
+
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.out, "{{{labels}}}");
+        }
+
+        let _ = writeln!(self.out, " {}", format_value(value));
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == Format::OpenMetrics {
+            self.out.push_str("# EOF\n");
+        }
+
+        self.out
+    }
+}
+
+/// Rust spells infinity `inf`, the exposition formats `+Inf`
+fn format_value(value: f64) -> String {
+    match value {
+        value if value.is_nan() => "NaN".to_string(),
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+type SampleValue = fn(&LatestSample) -> f64;
+
+/// Container samples are labelled with the container metadata, host samples with
+/// `container_name="host"` and the other labels empty so every series has the same label names
+fn sample_labels(sample: &LatestSample) -> Vec<(&str, &str)> {
+    match &sample.container {
+        Some(container) => vec![
+            ("container_id", container.id.as_str()),
+            ("container_name", container.name.as_str()),
+            ("image", container.image.as_str()),
+            (
+                "compose_project",
+                container.compose_project().unwrap_or_default(),
+            ),
+        ],
+        None => vec![
+            ("container_id", ""),
+            ("container_name", "host"),
+            ("image", ""),
+            ("compose_project", ""),
+        ],
+    }
+}
+
+pub async fn metrics(
+    State(tx): State<DbChannelTx>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let samples = query(&tx, |respond_to| DbCommand::GetLatestSamples {
+        since: Utc::now() - STALENESS,
+        respond_to,
+    })
+    .await?;
+
+    let format = Format::negotiate(&headers);
+    let mut exposition = Exposition::new(format);
+
+    let gauges: [(&str, &str, SampleValue); 5] = [
+        (
+            "sentinel_cpu_usage_percent",
+            "CPU usage of the host (container_name=\"host\") or of a container.",
+            |s| s.cpu_usage.percentage,
+        ),
+        (
+            "sentinel_memory_total_bytes",
+            "Total memory of the host or memory limit of a container.",
+            |s| s.memory_usage.total as f64,
+        ),
+        (
+            "sentinel_memory_used_bytes",
+            "Used memory of the host or of a container.",
+            |s| s.memory_usage.used as f64,
+        ),
+        (
+            "sentinel_memory_usage_percent",
+            "Used memory relative to the total memory or limit.",
+            |s| s.memory_usage.percentage,
+        ),
+        (
+            "sentinel_sample_timestamp_seconds",
+            "Unix time at which the exported sample was collected.",
+            |s| s.timestamp.timestamp() as f64,
+        ),
+    ];
+
+    for (name, help, value) in gauges {
+        exposition.gauge(name, help);
+        for sample in &samples {
+            exposition.sample(name, &sample_labels(sample), value(sample));
+        }
+    }
+
+    let queue = tx.stats();
+    exposition.gauge(
+        "sentinel_db_queue_depth",
+        "Number of commands waiting for the database task.",
+    );
+    exposition.sample("sentinel_db_queue_depth", &[], queue.depth as f64);
+    exposition.gauge(
+        "sentinel_db_queue_capacity",
+        "Number of samples the database queue holds before dropping or blocking.",
+    );
+    exposition.sample("sentinel_db_queue_capacity", &[], queue.capacity as f64);
+    exposition.counter(
+        "sentinel_db_queue_dropped",
+        "Samples dropped because the database queue was full.",
+    );
+    exposition.sample(
+        "sentinel_db_queue_dropped_total",
+        &[("dropped", "oldest")],
+        queue.dropped_oldest as f64,
+    );
+    exposition.sample(
+        "sentinel_db_queue_dropped_total",
+        &[("dropped", "newest")],
+        queue.dropped_newest as f64,
+    );
+    exposition.counter(
+        "sentinel_db_failed_writes",
+        "Writes the database task failed to carry out.",
+    );
+    exposition.sample(
+        "sentinel_db_failed_writes_total",
+        &[],
+        queue.failed_writes as f64,
+    );
+
+    Ok((
+        [(header::CONTENT_TYPE, format.content_type())],
+        exposition.finish(),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::types::{ContainerInfo, CpuUsage, MemoryUsage};
+
+    #[test]
+    fn samples_are_formatted() {
+        let mut exposition = Exposition::new(Format::Text);
+        exposition.counter("requests", "Requests so far.");
+        exposition.sample("requests_total", &[], 3.0);
+        exposition.sample("requests_total", &[("path", "a\"b\\c\nd")], 0.5);
+        exposition.sample("ratio", &[("container", "host")], f64::NAN);
+        exposition.sample("ratio", &[], f64::INFINITY);
+        exposition.sample("ratio", &[], f64::NEG_INFINITY);
+
+        assert_eq!(
+            exposition.finish(),
+            "# HELP requests_total Requests so far.\n\
+             # TYPE requests_total counter\n\
+             requests_total 3\n\
+             requests_total{path=\"a\\\"b\\\\c\\nd\"} 0.5\n\
+             ratio{container=\"host\"} NaN\n\
+             ratio +Inf\n\
+             ratio -Inf\n"
+        );
+    }
+
+    #[test]
+    fn host_and_container_samples_have_the_same_labels() {
+        let sample = |container| LatestSample {
+            container,
+            timestamp: Utc::now(),
+            cpu_usage: CpuUsage { percentage: 1.0 },
+            memory_usage: MemoryUsage {
+                total: 100,
+                used: 1,
+                percentage: 1.0,
+            },
+        };
+        let host = sample(None);
+        let container = sample(Some(ContainerInfo {
+            id: "c1".to_string(),
+            name: "web".to_string(),
+            image: "nginx".to_string(),
+            labels: HashMap::from([("com.docker.compose.project".to_string(), "shop".to_string())]),
+            status: "running".to_string(),
+        }));
+
+        assert_eq!(
+            sample_labels(&host),
+            [
+                ("container_id", ""),
+                ("container_name", "host"),
+                ("image", ""),
+                ("compose_project", ""),
+            ]
+        );
+        assert_eq!(
+            sample_labels(&container),
+            [
+                ("container_id", "c1"),
+                ("container_name", "web"),
+                ("image", "nginx"),
+                ("compose_project", "shop"),
+            ]
+        );
+    }
+
+    #[test]
+    fn open_metrics_counters_leave_off_the_suffix() {
+        let mut exposition = Exposition::new(Format::OpenMetrics);
+        exposition.counter("requests", "Requests so far.");
+        exposition.sample("requests_total", &[], 1e21);
+
+        assert_eq!(
+            exposition.finish(),
+            "# HELP requests Requests so far.\n\
+             # TYPE requests counter\n\
+             requests_total 1000000000000000000000\n\
+             # EOF\n"
+        );
+    }
+}
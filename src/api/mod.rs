@@ -0,0 +1,377 @@
+use anyhow::Result;
+use axum::{
+    Json, Router,
+    extract::{FromRef, State},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::{
+    alerting::{Alerts, Silences},
+    config::Config,
+    db::{self, ChannelClosed, DbChannelTx, DbCommand, HistoryReader, QueueStats},
+    forecast::{self, ForecastMethod},
+    hub::SampleHub,
+    types::{
+        Aggregation, Aggregations, CpuUsageDataPoint, Interval, MemoryUsageDataPoint, RangeQuery,
+        Step, TimeSpec,
+    },
+};
+use error::ApiError;
+use export::ExportFormat;
+use extract::{ApiPath, ApiQuery, ContainerId};
+
+mod alerts;
+mod annotations;
+mod anomalies;
+mod containers;
+mod custom;
+mod error;
+mod export;
+mod extract;
+mod history;
+mod ingest;
+mod metrics;
+mod series;
+mod silences;
+mod stream;
+
+#[derive(Clone)]
+struct AppState {
+    db_tx: DbChannelTx,
+    reader: HistoryReader,
+    hub: SampleHub,
+    alerts: Alerts,
+    silences: Silences,
+    max_backfill: usize,
+}
+
+impl FromRef<AppState> for DbChannelTx {
+    fn from_ref(state: &AppState) -> Self {
+        state.db_tx.clone()
+    }
+}
+
+impl FromRef<AppState> for HistoryReader {
+    fn from_ref(state: &AppState) -> Self {
+        state.reader.clone()
+    }
+}
+
+impl FromRef<AppState> for Alerts {
+    fn from_ref(state: &AppState) -> Self {
+        state.alerts.clone()
+    }
+}
+
+impl FromRef<AppState> for Silences {
+    fn from_ref(state: &AppState) -> Self {
+        state.silences.clone()
+    }
+}
+
+pub async fn start(
+    config: &Config,
+    db_tx: DbChannelTx,
+    hub: SampleHub,
+    alerts: Alerts,
+    silences: Silences,
+) -> Result<()> {
+    let state = AppState {
+        db_tx,
+        reader: HistoryReader::new(&config.database),
+        hub,
+        alerts,
+        silences,
+        max_backfill: config.stream.max_backfill,
+    };
+
+    let app = Router::new()
+        .route("/diagnostics", get(diagnostics))
+        .route("/metrics", get(metrics::metrics))
+        .route("/containers", get(containers::list))
+        .route("/containers/top", get(containers::top))
+        .route("/containers/{container}", get(containers::get))
+        .route("/query", post(series::query_series))
+        .route("/alerts", get(alerts::list))
+        .route("/alerts/history", get(alerts::history))
+        .route("/anomalies", get(anomalies::list))
+        .route(
+            "/annotations",
+            get(annotations::list).post(annotations::create),
+        )
+        .route("/annotations/{id}", delete(annotations::delete))
+        .route("/ingest", post(ingest::ingest))
+        .route("/custom", get(custom::list))
+        .route("/silences", get(silences::list).post(silences::create))
+        .route("/silences/{id}", delete(silences::delete))
+        .route("/stream", get(stream::sse))
+        .route("/ws", get(stream::websocket))
+        .route("/host/cpu/last", get(cpu_last))
+        .route("/host/cpu/last/{interval}", get(cpu_interval))
+        .route("/host/cpu/history", get(history::cpu_history))
+        .route("/host/cpu/range", get(cpu_range))
+        .route("/host/memory/last", get(memory_last))
+        .route("/host/memory/last/{interval}", get(memory_interval))
+        .route("/host/memory/history", get(history::memory_history))
+        .route("/host/memory/range", get(memory_range))
+        .route("/host/memory/forecast", get(memory_forecast))
+        .route("/custom/{name}/last", get(custom::last))
+        .route("/custom/{name}/last/{interval}", get(custom::interval))
+        .route("/custom/{name}/history", get(history::custom_history))
+        .route("/custom/{name}/range", get(custom::range))
+        .route("/{container}/cpu/last", get(cpu_last))
+        .route("/{container}/cpu/last/{interval}", get(cpu_interval))
+        .route("/{container}/cpu/history", get(history::cpu_history))
+        .route("/{container}/cpu/range", get(cpu_range))
+        .route("/{container}/memory/last", get(memory_last))
+        .route("/{container}/memory/last/{interval}", get(memory_interval))
+        .route("/{container}/memory/history", get(history::memory_history))
+        .route("/{container}/memory/range", get(memory_range))
+        .route("/{container}/memory/forecast", get(memory_forecast))
+        .route("/{container}/custom/{name}/last", get(custom::last))
+        .route(
+            "/{container}/custom/{name}/last/{interval}",
+            get(custom::interval),
+        )
+        .route(
+            "/{container}/custom/{name}/history",
+            get(history::custom_history),
+        )
+        .route("/{container}/custom/{name}/range", get(custom::range))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Diagnostics {
+    db_queue: QueueStats,
+}
+
+async fn diagnostics(State(tx): State<DbChannelTx>) -> impl IntoResponse {
+    Json(Diagnostics {
+        db_queue: tx.stats(),
+    })
+}
+
+async fn memory_last(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+) -> Result<impl IntoResponse, ApiError> {
+    let result = query(&tx, |respond_to| DbCommand::GetLastMemoryUsage {
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(Json(result))
+}
+
+async fn cpu_last(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+) -> Result<impl IntoResponse, ApiError> {
+    let result = query(&tx, |respond_to| DbCommand::GetLastCpuUsage {
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize)]
+struct IntervalRouteParams {
+    interval: Interval,
+}
+
+#[derive(Debug, Deserialize)]
+struct AggregationQueryParams {
+    agg: Option<Aggregations>,
+}
+
+impl AggregationQueryParams {
+    fn aggregations(&self) -> Aggregations {
+        self.agg.clone().unwrap_or(Aggregation::Avg.into())
+    }
+}
+
+/// Without `?agg=` the plain averaged data points are returned, as before aggregations existed
+async fn cpu_interval(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    ApiPath(IntervalRouteParams { interval }): ApiPath<IntervalRouteParams>,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<AggregationQueryParams>,
+) -> Result<Response, ApiError> {
+    let aggregations = params.aggregations();
+    let points = query(&tx, |respond_to| DbCommand::GetIntervalCpuUsage {
+        container,
+        interval,
+        aggregations,
+        respond_to,
+    })
+    .await?;
+
+    Ok(match params.agg {
+        Some(_) => format.respond(points),
+        None => format.respond(
+            points
+                .into_iter()
+                .map(CpuUsageDataPoint::from)
+                .collect::<Vec<_>>(),
+        ),
+    })
+}
+
+async fn memory_interval(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    ApiPath(IntervalRouteParams { interval }): ApiPath<IntervalRouteParams>,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<AggregationQueryParams>,
+) -> Result<Response, ApiError> {
+    let aggregations = params.aggregations();
+    let points = query(&tx, |respond_to| DbCommand::GetIntervalMemoryUsage {
+        container,
+        interval,
+        aggregations,
+        respond_to,
+    })
+    .await?;
+
+    Ok(match params.agg {
+        Some(_) => format.respond(points),
+        None => format.respond(
+            points
+                .into_iter()
+                .map(MemoryUsageDataPoint::from)
+                .collect::<Vec<_>>(),
+        ),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeQueryParams {
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    step: Step,
+    #[serde(default)]
+    agg: Aggregation,
+}
+
+impl RangeQueryParams {
+    /// Defaults to the last hour when `from` or `to` is missing
+    fn into_range(self) -> Result<RangeQuery, ApiError> {
+        let now = Utc::now();
+        let to = self
+            .to
+            .map_or(Ok(now), |to| to.resolve(now))
+            .map_err(ApiError::BadRequest)?;
+        let from = TimeSpec::resolve_or_before(self.from, now, to, Duration::hours(1))
+            .map_err(ApiError::BadRequest)?;
+
+        RangeQuery::new(from, to, self.step, self.agg).map_err(ApiError::BadRequest)
+    }
+}
+
+async fn cpu_range(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<RangeQueryParams>,
+) -> Result<Response, ApiError> {
+    let range = params.into_range()?;
+    let points = query(&tx, |respond_to| DbCommand::GetRangeCpuUsage {
+        range,
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(format.respond(points))
+}
+
+async fn memory_range(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    format: ExportFormat,
+    ApiQuery(params): ApiQuery<RangeQueryParams>,
+) -> Result<Response, ApiError> {
+    let range = params.into_range()?;
+    let points = query(&tx, |respond_to| DbCommand::GetRangeMemoryUsage {
+        range,
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(format.respond(points))
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastQueryParams {
+    /// Samples of this window before now are fitted
+    #[serde(default = "default_forecast_window")]
+    window: Step,
+    #[serde(default)]
+    method: ForecastMethod,
+}
+
+fn default_forecast_window() -> Step {
+    "6h".parse().unwrap()
+}
+
+/// Every sample of the window is loaded, so it is kept to the history a forecast makes sense for
+const MAX_FORECAST_WINDOW: Duration = Duration::days(7);
+
+/// `null` without at least two samples in the window
+async fn memory_forecast(
+    State(tx): State<DbChannelTx>,
+    ContainerId(container): ContainerId,
+    ApiQuery(params): ApiQuery<ForecastQueryParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let window = params.window.to_duration();
+    if window > MAX_FORECAST_WINDOW {
+        return Err(ApiError::BadRequest(format!(
+            "'window' must not be longer than {} days",
+            MAX_FORECAST_WINDOW.num_days()
+        )));
+    }
+
+    let now = Utc::now();
+    let from = now
+        .checked_sub_signed(window)
+        .ok_or_else(|| ApiError::BadRequest("'window' is out of range".to_string()))?;
+    let points = query(&tx, |respond_to| DbCommand::GetMemoryUsageHistory {
+        from: Some(from),
+        to: Some(now),
+        limit: None,
+        container,
+        respond_to,
+    })
+    .await?;
+
+    Ok(Json(forecast::memory(&points, params.method)))
+}
+
+/// [`db::query`] with its errors turned into API errors
+async fn query<T, F>(db_tx: &DbChannelTx, fun: F) -> Result<T, ApiError>
+where
+    F: FnOnce(oneshot::Sender<Result<T>>) -> DbCommand,
+{
+    db::query(db_tx, fun).await.map_err(|e| {
+        if e.is::<ChannelClosed>() {
+            ApiError::DbUnavailable
+        } else {
+            ApiError::Internal(e)
+        }
+    })
+}
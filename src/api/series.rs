@@ -0,0 +1,232 @@
+use axum::{
+    Json,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{
+    ApiError,
+    export::ExportFormat,
+    extract::{ApiJson, resolve_container},
+    query,
+};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    types::{Aggregation, Metric, RangeQuery, Series, SeriesPoints, SeriesQuery, Step, TimeSpec},
+};
+
+/// Upper bound of the series in a single request, each of them can have up to
+/// [`RangeQuery::MAX_POINTS`] points
+const MAX_SERIES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    from: Option<TimeSpec>,
+    to: Option<TimeSpec>,
+    step: Step,
+    series: Vec<SeriesSelector>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SeriesSelector {
+    /// Container id, id prefix or name, the host when missing or `host`
+    container: Option<String>,
+    metric: Metric,
+    #[serde(default)]
+    agg: Aggregation,
+}
+
+/// A point of one of the series, CSV and NDJSON responses have one per line since their rows
+/// are flat. CPU points leave `total` and `used` empty.
+#[derive(Debug, Serialize)]
+struct SeriesRow<'a> {
+    container: Option<&'a str>,
+    metric: Metric,
+    agg: Aggregation,
+    timestamp: DateTime<Utc>,
+    percentage: Option<f64>,
+    total: Option<u64>,
+    used: Option<u64>,
+}
+
+fn rows(series: &[Series]) -> Vec<SeriesRow<'_>> {
+    series
+        .iter()
+        .flat_map(|series| {
+            let row = |timestamp, percentage, total, used| SeriesRow {
+                container: series.container.as_deref(),
+                metric: series.metric,
+                agg: series.aggregation,
+                timestamp,
+                percentage,
+                total,
+                used,
+            };
+
+            match &series.points {
+                SeriesPoints::Cpu(points) => points
+                    .iter()
+                    .map(|point| row(point.timestamp, point.percentage, None, None))
+                    .collect::<Vec<_>>(),
+                SeriesPoints::Memory(points) => points
+                    .iter()
+                    .map(|point| row(point.timestamp, point.percentage, point.total, point.used))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Range queries of several series, answered in one round-trip to the database task
+pub async fn query_series(
+    State(tx): State<DbChannelTx>,
+    format: ExportFormat,
+    ApiJson(request): ApiJson<QueryRequest>,
+) -> Result<Response, ApiError> {
+    if request.series.len() > MAX_SERIES {
+        return Err(ApiError::BadRequest(format!(
+            "At most {MAX_SERIES} series can be queried at once"
+        )));
+    }
+
+    // defaults to the last hour, same as the range routes
+    let now = Utc::now();
+    let to = request
+        .to
+        .map_or(Ok(now), |to| to.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+    let from = TimeSpec::resolve_or_before(request.from, now, to, Duration::hours(1))
+        .map_err(ApiError::BadRequest)?;
+
+    let mut resolved: HashMap<String, String> = HashMap::new();
+    let mut queries = vec![];
+    for selector in request.series {
+        let container = match selector.container.as_deref() {
+            None | Some("host") => None,
+            Some(container) => match resolved.get(container) {
+                Some(id) => Some(id.clone()),
+                None => {
+                    let id = resolve_container(&tx, container).await?;
+                    resolved.insert(container.to_string(), id.clone());
+                    Some(id)
+                }
+            },
+        };
+
+        queries.push(SeriesQuery {
+            container,
+            metric: selector.metric,
+            range: RangeQuery::new(from, to, request.step, selector.agg)
+                .map_err(ApiError::BadRequest)?,
+        });
+    }
+
+    let series = query(&tx, |respond_to| DbCommand::GetRangeSeries {
+        queries,
+        respond_to,
+    })
+    .await?;
+
+    Ok(match format {
+        ExportFormat::Json => Json(series).into_response(),
+        format => format.respond(rows(&series)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::Response;
+    use chrono::DateTime;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        config::DatabaseConfig,
+        db::{self, OverflowPolicy, StorageBackend, create_command_channel},
+        types::{CpuUsage, MemoryUsage},
+    };
+
+    async fn body(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn series_are_answered_together() {
+        let (tx, rx) = create_command_channel(16, OverflowPolicy::Block);
+        let config = DatabaseConfig {
+            backend: StorageBackend::Memory,
+            ..Default::default()
+        };
+        db::start(&config, rx);
+
+        let from = DateTime::from_timestamp(Utc::now().timestamp() / 60 * 60 - 600, 0).unwrap();
+        for (seconds, cpu) in [(0, 10.0), (30, 30.0), (120, 50.0)] {
+            tx.send(DbCommand::InsertResourceUsage {
+                timestamp: from + Duration::seconds(seconds),
+                cpu_usage: CpuUsage { percentage: cpu },
+                memory_usage: MemoryUsage {
+                    total: 1000,
+                    used: cpu as u64 * 10,
+                    percentage: cpu,
+                },
+                container: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let request = serde_json::from_value(json!({
+            "from": from,
+            "to": from + Duration::minutes(2),
+            "step": "1m",
+            "series": [
+                {"metric": "cpu"},
+                {"container": "host", "metric": "memory", "agg": "max"},
+            ],
+        }))
+        .unwrap();
+        let response = query_series(State(tx), ExportFormat::Json, ApiJson(request))
+            .await
+            .unwrap()
+            .into_response();
+
+        let series = body(response).await;
+        assert_eq!(series[0]["container"], Value::Null);
+        assert_eq!(
+            (&series[0]["metric"], &series[0]["agg"]),
+            (&json!("cpu"), &json!("avg"))
+        );
+        let cpu: Vec<_> = series[0]["points"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|point| point["percentage"].clone())
+            .collect();
+        assert_eq!(cpu, [json!(20.0), Value::Null, json!(50.0)]);
+        assert_eq!(series[1]["agg"], "max");
+        assert_eq!(series[1]["points"][0]["used"], 300);
+    }
+
+    #[tokio::test]
+    async fn too_many_series_are_rejected() {
+        let (tx, _rx) = create_command_channel(1, OverflowPolicy::Block);
+        let request = serde_json::from_value(json!({
+            "step": "1m",
+            "series": vec![json!({"metric": "cpu"}); MAX_SERIES + 1],
+        }))
+        .unwrap();
+
+        let Err(ApiError::BadRequest(message)) =
+            query_series(State(tx), ExportFormat::Json, ApiJson(request)).await
+        else {
+            panic!("{} series were accepted", MAX_SERIES + 1);
+        };
+        assert_eq!(message, "At most 100 series can be queried at once");
+    }
+}
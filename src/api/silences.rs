@@ -0,0 +1,116 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+
+use super::{
+    ApiError,
+    extract::{ApiJson, ApiPath, ApiQuery},
+    query,
+};
+use crate::{
+    alerting::Silences,
+    db::{DbChannelTx, DbCommand},
+    types::{Silence, SilenceMatcher, TimeSpec},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SilencesQueryParams {
+    /// Includes silences that have ended
+    #[serde(default)]
+    expired: bool,
+}
+
+/// Active and upcoming silences by start time, maintenance windows of the config are not listed
+pub async fn list(
+    State(tx): State<DbChannelTx>,
+    ApiQuery(params): ApiQuery<SilencesQueryParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let ended_after = match params.expired {
+        true => Utc.timestamp_opt(0, 0).unwrap(),
+        false => Utc::now(),
+    };
+
+    let silences = query(&tx, |respond_to| DbCommand::GetSilences {
+        ended_after,
+        respond_to,
+    })
+    .await?;
+
+    Ok(Json(silences))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSilenceRequest {
+    #[serde(flatten)]
+    matcher: SilenceMatcher,
+    comment: Option<String>,
+    /// Defaults to now
+    starts_at: Option<TimeSpec>,
+    /// Absolute, or relative to now like `+2h`
+    ends_at: TimeSpec,
+}
+
+pub async fn create(
+    State(tx): State<DbChannelTx>,
+    State(silences): State<Silences>,
+    ApiJson(request): ApiJson<CreateSilenceRequest>,
+) -> Result<Response, ApiError> {
+    let now = Utc::now();
+    let starts_at = request
+        .starts_at
+        .map_or(Ok(now), |starts_at| starts_at.resolve(now))
+        .map_err(ApiError::BadRequest)?;
+    let ends_at = request.ends_at.resolve(now).map_err(ApiError::BadRequest)?;
+
+    if ends_at <= starts_at {
+        return Err(ApiError::BadRequest(
+            "'ends_at' has to be after 'starts_at'".to_string(),
+        ));
+    }
+    if ends_at <= now {
+        return Err(ApiError::BadRequest(
+            "'ends_at' has to be in the future".to_string(),
+        ));
+    }
+
+    let silence = Silence {
+        id: 0,
+        matcher: request.matcher,
+        comment: request.comment,
+        starts_at,
+        ends_at,
+        created_at: now,
+    };
+    let silence = query(&tx, |respond_to| DbCommand::InsertSilence {
+        silence,
+        respond_to,
+    })
+    .await?;
+    silences.reload(&tx).await?;
+
+    Ok((StatusCode::CREATED, Json(silence)).into_response())
+}
+
+/// Ends a silence right away
+pub async fn delete(
+    State(tx): State<DbChannelTx>,
+    State(silences): State<Silences>,
+    ApiPath(id): ApiPath<i64>,
+) -> Result<StatusCode, ApiError> {
+    let deleted = query(&tx, |respond_to| DbCommand::DeleteSilence {
+        id,
+        respond_to,
+    })
+    .await?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("No silence with id {id}")));
+    }
+    silences.reload(&tx).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
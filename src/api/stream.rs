@@ -0,0 +1,301 @@
+use axum::{
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt, future, stream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    pin::pin,
+    sync::Arc,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::{
+    ApiError, AppState,
+    extract::{ApiQuery, resolve_container},
+    metrics::STALENESS,
+    query,
+};
+use crate::{
+    db::DbCommand,
+    types::{CpuUsage, MemoryUsage, UsageSample},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct StreamParams {
+    /// Comma separated container ids, id prefixes or names, `host` selects the host.
+    /// Every series is streamed when missing.
+    containers: Option<String>,
+    /// Comma separated list of `cpu` and `memory`, both when missing
+    metrics: Option<String>,
+    /// Number of stored samples sent per series before the live ones
+    #[serde(default)]
+    backfill: usize,
+}
+
+struct Subscription {
+    /// `None` subscribes to every series
+    series: Option<HashSet<Option<String>>>,
+    cpu: bool,
+    memory: bool,
+}
+
+#[derive(Serialize)]
+struct StreamEvent<'a> {
+    timestamp: DateTime<Utc>,
+    container: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu: Option<&'a CpuUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<&'a MemoryUsage>,
+}
+
+impl Subscription {
+    async fn new(state: &AppState, params: &StreamParams) -> Result<Self, ApiError> {
+        let series = match &params.containers {
+            Some(containers) => {
+                let mut series = HashSet::new();
+                for container in containers.split(',').map(str::trim) {
+                    series.insert(match container {
+                        "host" => None,
+                        container => Some(resolve_container(&state.db_tx, container).await?),
+                    });
+                }
+                Some(series)
+            }
+            None => None,
+        };
+
+        let (cpu, memory) = match &params.metrics {
+            Some(metrics) => {
+                let (mut cpu, mut memory) = (false, false);
+                for metric in metrics.split(',').map(str::trim) {
+                    match metric {
+                        "cpu" => cpu = true,
+                        "memory" => memory = true,
+                        _ => {
+                            return Err(ApiError::BadRequest(format!(
+                                "Unknown metric '{metric}', expected 'cpu' or 'memory'"
+                            )));
+                        }
+                    }
+                }
+                (cpu, memory)
+            }
+            None => (true, true),
+        };
+
+        Ok(Self {
+            series,
+            cpu,
+            memory,
+        })
+    }
+
+    fn matches(&self, sample: &UsageSample) -> bool {
+        self.series
+            .as_ref()
+            .is_none_or(|series| series.contains(&sample.container))
+    }
+
+    fn to_json(&self, sample: &UsageSample) -> String {
+        let event = StreamEvent {
+            timestamp: sample.timestamp,
+            container: sample.container.as_deref(),
+            cpu: self.cpu.then_some(&sample.cpu_usage),
+            memory: self.memory.then_some(&sample.memory_usage),
+        };
+
+        serde_json::to_string(&event).unwrap_or_default()
+    }
+
+    /// Without explicitly selected containers the backfill covers the currently active ones
+    async fn backfill(&self, state: &AppState, count: usize) -> Result<Vec<UsageSample>, ApiError> {
+        let count = count.min(state.max_backfill);
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let series: Vec<Option<String>> = match &self.series {
+            Some(series) => series.iter().cloned().collect(),
+            None => query(&state.db_tx, |respond_to| DbCommand::GetLatestSamples {
+                since: Utc::now() - STALENESS,
+                respond_to,
+            })
+            .await?
+            .into_iter()
+            .map(|sample| sample.container.map(|container| container.id))
+            .collect(),
+        };
+
+        let mut samples = vec![];
+        for container in series {
+            let recent = query(&state.db_tx, |respond_to| DbCommand::GetRecentSamples {
+                container,
+                limit: count,
+                respond_to,
+            })
+            .await?;
+            samples.extend(recent);
+        }
+        samples.sort_by_key(|sample| sample.timestamp);
+
+        Ok(samples)
+    }
+}
+
+fn live_samples(rx: broadcast::Receiver<UsageSample>) -> impl Stream<Item = UsageSample> {
+    stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(sample) => return Some((sample, rx)),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("Live stream subscriber fell behind, skipped {skipped} samples");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+/// The backfilled samples followed by the live ones, serialized as JSON
+async fn events(
+    state: &AppState,
+    params: &StreamParams,
+) -> Result<impl Stream<Item = String> + Send + use<>, ApiError> {
+    let subscription = Arc::new(Subscription::new(state, params).await?);
+
+    // subscribing before backfilling, so no sample falls between the two
+    let rx = state.hub.subscribe();
+    let backfill = subscription.backfill(state, params.backfill).await?;
+
+    let backfilled: HashMap<_, _> = backfill
+        .iter()
+        .map(|sample| (sample.container.clone(), sample.timestamp))
+        .collect();
+
+    let live = live_samples(rx).filter({
+        let subscription = subscription.clone();
+        move |sample| {
+            let is_new = backfilled
+                .get(&sample.container)
+                .is_none_or(|timestamp| sample.timestamp > *timestamp);
+            future::ready(is_new && subscription.matches(sample))
+        }
+    });
+
+    Ok(stream::iter(backfill)
+        .chain(live)
+        .map(move |sample| subscription.to_json(&sample)))
+}
+
+pub async fn sse(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<StreamParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let events = events(&state, &params)
+        .await?
+        .map(|event| Ok::<_, Infallible>(Event::default().event("sample").data(event)));
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+pub async fn websocket(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<StreamParams>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let events = events(&state, &params).await?;
+
+    Ok(upgrade.on_upgrade(|socket| forward(socket, events)))
+}
+
+async fn forward(mut socket: WebSocket, events: impl Stream<Item = String>) {
+    let mut events = pin!(events);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+                if socket.send(Message::Text(event.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                // pings are answered by axum, anything else from the client is ignored
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(container: Option<&str>, seconds: i64) -> UsageSample {
+        UsageSample {
+            timestamp: DateTime::from_timestamp(1_800_000_000 + seconds, 0).unwrap(),
+            container: container.map(str::to_string),
+            cpu_usage: CpuUsage { percentage: 12.5 },
+            memory_usage: MemoryUsage {
+                total: 1000,
+                used: 250,
+                percentage: 25.0,
+            },
+        }
+    }
+
+    #[test]
+    fn subscriptions_select_series_and_metrics() {
+        let subscription = Subscription {
+            series: Some(HashSet::from([None, Some("c1".to_string())])),
+            cpu: true,
+            memory: false,
+        };
+
+        assert!(subscription.matches(&sample(None, 0)));
+        assert!(subscription.matches(&sample(Some("c1"), 0)));
+        assert!(!subscription.matches(&sample(Some("c2"), 0)));
+        assert_eq!(
+            subscription.to_json(&sample(Some("c1"), 0)),
+            r#"{"timestamp":"2027-01-15T08:00:00Z","container":"c1","cpu":{"percentage":12.5}}"#
+        );
+
+        let everything = Subscription {
+            series: None,
+            cpu: true,
+            memory: true,
+        };
+        assert!(everything.matches(&sample(Some("c2"), 0)));
+        assert_eq!(
+            everything.to_json(&sample(None, 0)),
+            r#"{"timestamp":"2027-01-15T08:00:00Z","container":null,"cpu":{"percentage":12.5},"memory":{"total":1000,"used":250,"percentage":25.0}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn lagging_subscribers_skip_to_the_newest_samples() {
+        let (tx, rx) = broadcast::channel(2);
+        for seconds in 0..5 {
+            tx.send(sample(None, seconds)).unwrap();
+        }
+        drop(tx);
+
+        let timestamps: Vec<_> = live_samples(rx)
+            .map(|sample| sample.timestamp.timestamp() - 1_800_000_000)
+            .collect()
+            .await;
+        assert_eq!(timestamps, [3, 4]);
+    }
+}
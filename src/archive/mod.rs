@@ -0,0 +1,415 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Days, NaiveDate, TimeDelta, Utc};
+use rusqlite::{Connection, OpenFlags, named_params};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    config::{ArchiveConfig, Config},
+    db::{self, StorageBackend},
+    types::CustomSample,
+};
+
+mod parquet;
+
+/// Directories of the archived tables, named like the tables and the Parquet schemas
+const USAGE_TABLE: &str = "usage";
+const CUSTOM_METRICS_TABLE: &str = "custom_metrics";
+/// Marks a day directory as completely archived by the scheduled job
+const SUCCESS_MARKER: &str = "_SUCCESS";
+/// How often the scheduled job looks for days that are not archived yet
+const JOB_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A row of the `usage` table together with the metadata of its container
+#[derive(Debug, Default, PartialEq)]
+pub struct ArchivedSample {
+    pub container: Option<String>,
+    pub container_name: Option<String>,
+    pub container_image: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub cpu_percentage: f64,
+    pub memory_total: i64,
+    pub memory_used: i64,
+    pub memory_percentage: f64,
+}
+
+/// Archives every complete day of the last `catch_up_days` days that is not archived yet,
+/// never returns when archiving is disabled.
+pub async fn start(config: &Config) -> Result<()> {
+    if !config.archive.enabled {
+        return std::future::pending().await;
+    }
+
+    if config.database.backend != StorageBackend::Sqlite {
+        return Err(anyhow!("Archiving is only supported by the sqlite backend"));
+    }
+
+    let mut ticker = tokio::time::interval(JOB_INTERVAL);
+    loop {
+        ticker.tick().await;
+
+        let database = config.database.path.clone();
+        let archive = config.archive.clone();
+        let result = tokio::task::spawn_blocking(move || archive_days(&database, &archive)).await?;
+
+        if let Err(e) = result {
+            log::error!("Failed to archive samples: {e:#}");
+        }
+    }
+}
+
+fn archive_days(database: &Path, config: &ArchiveConfig) -> Result<()> {
+    let today = Utc::now().date_naive();
+
+    for days_ago in 1..=config.catch_up_days {
+        let Some(day) = today.checked_sub_days(Days::new(days_ago.into())) else {
+            break;
+        };
+
+        // every table of a day is archived at once, the marker of `usage` stands for all of them
+        if is_finished(&config.directory, USAGE_TABLE, day) {
+            continue;
+        }
+
+        let from = day.and_time(Default::default()).and_utc();
+        let files = export(&config.directory, database, from, from + Days::new(1))?;
+
+        for table in [USAGE_TABLE, CUSTOM_METRICS_TABLE] {
+            let day_directory = day_directory(&config.directory, table, day);
+            fs::create_dir_all(&day_directory)?;
+            fs::write(day_directory.join(SUCCESS_MARKER), "")?;
+        }
+        log::info!("Archived {day} into {files} files");
+    }
+
+    Ok(())
+}
+
+fn day_directory(directory: &Path, table: &str, day: NaiveDate) -> PathBuf {
+    directory.join(table).join(format!("date={day}"))
+}
+
+fn is_finished(directory: &Path, table: &str, day: NaiveDate) -> bool {
+    day_directory(directory, table, day)
+        .join(SUCCESS_MARKER)
+        .exists()
+}
+
+/// Writes the samples between `from` (inclusive) and `to` (exclusive) to
+/// `<directory>/<table>/date=<day>/container=<id or host>/data.parquet`, replacing the files of
+/// earlier exports. Days covered only in part go to `data-<from>-<to>.parquet` with the unix
+/// times of the covered part instead, and days the scheduled job archived completely are
+/// skipped. Returns the number of written files.
+pub fn export(
+    directory: &Path,
+    database: &Path,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<usize> {
+    let connection = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open database {database:?}"))?;
+    connection.busy_timeout(Duration::from_secs(5))?;
+
+    // ordered by container first, so every partition is read in one go
+    let mut stmt = connection.prepare(
+        "SELECT usage.container, containers.name, containers.image, usage.timestamp,
+            usage.cpu_percentage, usage.memory_total, usage.memory_used, usage.memory_percentage
+        FROM usage
+        LEFT JOIN containers ON containers.id = usage.container
+        WHERE usage.timestamp >= :from AND usage.timestamp < :to
+        ORDER BY usage.container ASC, usage.timestamp ASC",
+    )?;
+    let samples = stmt.query_map(named_params! {":from": from, ":to": to}, |row| {
+        Ok(ArchivedSample {
+            container: row.get(0)?,
+            container_name: row.get(1)?,
+            container_image: row.get(2)?,
+            timestamp: row.get(3)?,
+            cpu_percentage: row.get(4)?,
+            memory_total: row.get(5)?,
+            memory_used: row.get(6)?,
+            memory_percentage: row.get(7)?,
+        })
+    })?;
+    let usage_files = write_partitions(
+        directory,
+        USAGE_TABLE,
+        samples,
+        (from, to),
+        parquet::write_usage,
+    )?;
+
+    let mut stmt = connection.prepare(
+        "SELECT name, labels, kind, container, value, timestamp
+        FROM custom_metrics
+        WHERE timestamp >= :from AND timestamp < :to
+        ORDER BY container ASC, timestamp ASC",
+    )?;
+    let samples = stmt.query_map(named_params! {":from": from, ":to": to}, |row| {
+        let labels: String = row.get(1)?;
+        let kind: String = row.get(2)?;
+
+        Ok(CustomSample {
+            name: row.get(0)?,
+            labels: serde_json::from_str(&labels).unwrap_or_default(),
+            kind: kind.parse().unwrap_or_default(),
+            container: row.get(3)?,
+            value: row.get(4)?,
+            timestamp: row.get(5)?,
+        })
+    })?;
+    let custom_files = write_partitions(
+        directory,
+        CUSTOM_METRICS_TABLE,
+        samples,
+        (from, to),
+        parquet::write_custom_metrics,
+    )?;
+
+    Ok(usage_files + custom_files)
+}
+
+/// A row of an archived table, which are partitioned by day and container
+trait Partitioned {
+    fn container(&self) -> Option<&str>;
+    fn timestamp(&self) -> DateTime<Utc>;
+}
+
+impl Partitioned for ArchivedSample {
+    fn container(&self) -> Option<&str> {
+        self.container.as_deref()
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+impl Partitioned for CustomSample {
+    fn container(&self) -> Option<&str> {
+        self.container.as_deref()
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+/// `rows` have to be ordered by container and timestamp, returns the number of written files
+fn write_partitions<T: Partitioned>(
+    directory: &Path,
+    table: &str,
+    rows: impl Iterator<Item = rusqlite::Result<T>>,
+    range: (DateTime<Utc>, DateTime<Utc>),
+    write: impl Fn(&Path, &[T]) -> Result<()>,
+) -> Result<usize> {
+    let mut files = 0;
+    let mut finished: HashMap<NaiveDate, bool> = HashMap::new();
+    let mut partition: Vec<T> = vec![];
+    for row in rows {
+        let row = row?;
+
+        let day = row.timestamp().date_naive();
+        let is_finished = *finished
+            .entry(day)
+            .or_insert_with(|| is_finished(directory, table, day));
+        if is_finished {
+            continue;
+        }
+
+        let same_partition = partition.last().is_none_or(|last| {
+            last.container() == row.container()
+                && last.timestamp().date_naive() == row.timestamp().date_naive()
+        });
+        if !same_partition {
+            write_partition(directory, table, &partition, range, &write)?;
+            files += 1;
+            partition.clear();
+        }
+
+        partition.push(row);
+    }
+
+    if !partition.is_empty() {
+        write_partition(directory, table, &partition, range, &write)?;
+        files += 1;
+    }
+
+    for (day, _) in finished.into_iter().filter(|(_, finished)| *finished) {
+        log::warn!("Skipped {table} of {day}, it is archived completely already");
+    }
+
+    Ok(files)
+}
+
+/// `rows` have to share their container and day, `(from, to)` is the exported range
+fn write_partition<T: Partitioned>(
+    directory: &Path,
+    table: &str,
+    rows: &[T],
+    (from, to): (DateTime<Utc>, DateTime<Utc>),
+    write: impl Fn(&Path, &[T]) -> Result<()>,
+) -> Result<()> {
+    let first = &rows[0];
+    let day = first.timestamp().date_naive();
+    let partition = day_directory(directory, table, day)
+        .join(format!("container={}", first.container().unwrap_or("host")));
+    fs::create_dir_all(&partition)?;
+
+    let start = day.and_time(Default::default()).and_utc();
+    let end = start + Days::new(1);
+    let name = if from <= start && end <= to {
+        "data.parquet".to_string()
+    } else {
+        format!(
+            "data-{}-{}.parquet",
+            from.max(start).timestamp(),
+            to.min(end).timestamp()
+        )
+    };
+
+    // renamed once complete, so readers never see a partially written file
+    let temporary = partition.join(format!("{name}.tmp"));
+    write(&temporary, rows)?;
+    fs::rename(&temporary, partition.join(name))?;
+
+    Ok(())
+}
+
+/// Loads archived samples into the database at `database`, creating it when needed. `paths` can
+/// be files or directories, which are searched for `.parquet` files. Samples the database already
+/// has, e.g. from importing the same files twice, are skipped. Returns the number of imported
+/// samples.
+pub fn import(database: &Path, paths: &[PathBuf]) -> Result<usize> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            find_parquet_files(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut connection = db::open_sqlite(database)?;
+    let transaction = connection.transaction()?;
+
+    let mut count = 0;
+    {
+        // archives keep microseconds, so a stored sample matches anywhere within its microsecond
+        let mut insert_usage = transaction.prepare(
+            "INSERT INTO usage (container, timestamp, cpu_percentage, memory_total, memory_used,
+                memory_percentage)
+            SELECT :container, :timestamp, :cpu_percentage, :memory_total, :memory_used,
+                :memory_percentage
+            WHERE NOT EXISTS (
+                SELECT 1 FROM usage
+                WHERE container IS :container AND timestamp >= :timestamp AND timestamp < :next
+            )",
+        )?;
+        // keeps the metadata of containers the database already knows
+        let mut upsert_container = transaction.prepare(
+            "INSERT INTO containers (id, name, image, first_seen, last_seen)
+            VALUES (:id, :name, :image, :timestamp, :timestamp)
+            ON CONFLICT (id) DO UPDATE
+            SET name = COALESCE(name, excluded.name),
+                image = COALESCE(image, excluded.image),
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen)",
+        )?;
+        let mut insert_custom_metric = transaction.prepare(
+            "INSERT INTO custom_metrics (series, name, labels, kind, container, value, timestamp)
+            SELECT :series, :name, :labels, :kind, :container, :value, :timestamp
+            WHERE NOT EXISTS (
+                SELECT 1 FROM custom_metrics
+                WHERE series = :series AND container IS :container
+                    AND timestamp >= :timestamp AND timestamp < :next
+            )",
+        )?;
+
+        for file in &files {
+            let (imported, total) = match parquet::table(file)?.as_str() {
+                USAGE_TABLE => {
+                    let samples = parquet::read_usage(file)?;
+
+                    let mut imported = 0;
+                    for sample in &samples {
+                        imported += insert_usage.execute(named_params! {
+                            ":container": sample.container,
+                            ":timestamp": sample.timestamp,
+                            ":next": sample.timestamp + TimeDelta::microseconds(1),
+                            ":cpu_percentage": sample.cpu_percentage,
+                            ":memory_total": sample.memory_total,
+                            ":memory_used": sample.memory_used,
+                            ":memory_percentage": sample.memory_percentage,
+                        })?;
+
+                        if let Some(container) = &sample.container {
+                            upsert_container.execute(named_params! {
+                                ":id": container,
+                                ":name": sample.container_name,
+                                ":image": sample.container_image,
+                                ":timestamp": sample.timestamp,
+                            })?;
+                        }
+                    }
+
+                    (imported, samples.len())
+                }
+                CUSTOM_METRICS_TABLE => {
+                    let samples = parquet::read_custom_metrics(file)?;
+
+                    let mut imported = 0;
+                    for sample in &samples {
+                        imported += insert_custom_metric.execute(named_params! {
+                            ":series": sample.series(),
+                            ":name": sample.name,
+                            ":labels": serde_json::to_string(&sample.labels)?,
+                            ":kind": sample.kind.as_str(),
+                            ":container": sample.container,
+                            ":value": sample.value,
+                            ":timestamp": sample.timestamp,
+                            ":next": sample.timestamp + TimeDelta::microseconds(1),
+                        })?;
+                    }
+
+                    (imported, samples.len())
+                }
+                table => return Err(anyhow!("Unknown archived table '{table}' in {file:?}")),
+            };
+
+            log::info!(
+                "Imported {imported} samples from {file:?}, skipped {} already present",
+                total - imported
+            );
+            count += imported;
+        }
+    }
+    transaction.commit()?;
+
+    Ok(count)
+}
+
+fn find_parquet_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(directory)
+        .with_context(|| format!("Failed to read directory {directory:?}"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            find_parquet_files(&entry, files)?;
+        } else if entry
+            .extension()
+            .is_some_and(|extension| extension == "parquet")
+        {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,332 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::DateTime;
+use parquet::{
+    basic::Compression,
+    column::writer::ColumnWriterImpl,
+    data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int64Type},
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    record::Field,
+    schema::parser::parse_message_type,
+};
+use std::{fs::File, path::Path, sync::Arc};
+
+use super::ArchivedSample;
+use crate::types::CustomSample;
+
+/// Columns of the `usage` archives, the container metadata is kept so the files are readable on
+/// their own
+const USAGE_SCHEMA: &str = "
+message usage {
+    OPTIONAL BYTE_ARRAY container (UTF8);
+    OPTIONAL BYTE_ARRAY container_name (UTF8);
+    OPTIONAL BYTE_ARRAY container_image (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP(MICROS, true));
+    REQUIRED DOUBLE cpu_percentage;
+    REQUIRED INT64 memory_total;
+    REQUIRED INT64 memory_used;
+    REQUIRED DOUBLE memory_percentage;
+}
+";
+
+/// Columns of the `custom_metrics` archives, the labels are a JSON object like in the database
+const CUSTOM_METRICS_SCHEMA: &str = "
+message custom_metrics {
+    REQUIRED BYTE_ARRAY series (UTF8);
+    REQUIRED BYTE_ARRAY name (UTF8);
+    REQUIRED BYTE_ARRAY labels (UTF8);
+    REQUIRED BYTE_ARRAY kind (UTF8);
+    OPTIONAL BYTE_ARRAY container (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP(MICROS, true));
+    REQUIRED DOUBLE value;
+}
+";
+
+fn create_writer(path: &Path, schema: &str) -> Result<SerializedFileWriter<File>> {
+    let schema = Arc::new(parse_message_type(schema)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+
+    let file = File::create(path).with_context(|| format!("Failed to create {path:?}"))?;
+
+    Ok(SerializedFileWriter::new(file, schema, properties)?)
+}
+
+/// Writes `samples` as a single row group to `path`
+pub fn write_usage(path: &Path, samples: &[ArchivedSample]) -> Result<()> {
+    let mut writer = create_writer(path, USAGE_SCHEMA)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => write_strings(column.typed(), samples, |s| s.container.as_deref())?,
+            1 => write_strings(column.typed(), samples, |s| s.container_name.as_deref())?,
+            2 => write_strings(column.typed(), samples, |s| s.container_image.as_deref())?,
+            3 => write_values::<Int64Type, _>(column.typed(), samples, |s| {
+                s.timestamp.timestamp_micros()
+            })?,
+            4 => write_values::<DoubleType, _>(column.typed(), samples, |s| s.cpu_percentage)?,
+            5 => write_values::<Int64Type, _>(column.typed(), samples, |s| s.memory_total)?,
+            6 => write_values::<Int64Type, _>(column.typed(), samples, |s| s.memory_used)?,
+            7 => write_values::<DoubleType, _>(column.typed(), samples, |s| s.memory_percentage)?,
+            _ => return Err(anyhow!("Unexpected column {index} in the usage schema")),
+        }
+        column.close()?;
+        index += 1;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/// Writes `samples` as a single row group to `path`
+pub fn write_custom_metrics(path: &Path, samples: &[CustomSample]) -> Result<()> {
+    let labels = samples
+        .iter()
+        .map(|sample| serde_json::to_string(&sample.labels))
+        .collect::<Result<Vec<_>, _>>()?;
+    let series: Vec<String> = samples.iter().map(CustomSample::series).collect();
+
+    let mut writer = create_writer(path, CUSTOM_METRICS_SCHEMA)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => write_values::<ByteArrayType, _>(column.typed(), &series, |s| s.as_str().into())?,
+            1 => write_values::<ByteArrayType, _>(column.typed(), samples, |s| {
+                s.name.as_str().into()
+            })?,
+            2 => write_values::<ByteArrayType, _>(column.typed(), &labels, |s| s.as_str().into())?,
+            3 => write_values::<ByteArrayType, _>(column.typed(), samples, |s| {
+                s.kind.as_str().into()
+            })?,
+            4 => write_strings(column.typed(), samples, |s| s.container.as_deref())?,
+            5 => write_values::<Int64Type, _>(column.typed(), samples, |s| {
+                s.timestamp.timestamp_micros()
+            })?,
+            6 => write_values::<DoubleType, _>(column.typed(), samples, |s| s.value)?,
+            _ => {
+                return Err(anyhow!(
+                    "Unexpected column {index} in the custom metrics schema"
+                ));
+            }
+        }
+        column.close()?;
+        index += 1;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+fn write_values<T: DataType, S>(
+    writer: &mut ColumnWriterImpl<T>,
+    samples: &[S],
+    value: impl Fn(&S) -> T::T,
+) -> Result<()> {
+    let values: Vec<T::T> = samples.iter().map(value).collect();
+    writer.write_batch(&values, None, None)?;
+
+    Ok(())
+}
+
+/// Nulls are only present in the definition levels, not among the values
+fn write_strings<S>(
+    writer: &mut ColumnWriterImpl<ByteArrayType>,
+    samples: &[S],
+    value: impl Fn(&S) -> Option<&str>,
+) -> Result<()> {
+    let definition_levels: Vec<i16> = samples
+        .iter()
+        .map(|sample| value(sample).is_some() as i16)
+        .collect();
+    let values: Vec<ByteArray> = samples
+        .iter()
+        .filter_map(|sample| value(sample).map(ByteArray::from))
+        .collect();
+    writer.write_batch(&values, Some(&definition_levels), None)?;
+
+    Ok(())
+}
+
+/// Name of the table the file was exported from, see the schemas
+pub fn table(path: &Path) -> Result<String> {
+    let reader =
+        SerializedFileReader::try_from(path).with_context(|| format!("Failed to open {path:?}"))?;
+
+    Ok(reader
+        .metadata()
+        .file_metadata()
+        .schema()
+        .name()
+        .to_string())
+}
+
+pub fn read_usage(path: &Path) -> Result<Vec<ArchivedSample>> {
+    let reader =
+        SerializedFileReader::try_from(path).with_context(|| format!("Failed to open {path:?}"))?;
+
+    reader
+        .get_row_iter(None)?
+        .map(|row| {
+            let row = row?;
+            let mut sample = ArchivedSample::default();
+
+            for (name, field) in row.get_column_iter() {
+                match (name.as_str(), field) {
+                    ("container", Field::Str(value)) => sample.container = Some(value.clone()),
+                    ("container_name", Field::Str(value)) => {
+                        sample.container_name = Some(value.clone())
+                    }
+                    ("container_image", Field::Str(value)) => {
+                        sample.container_image = Some(value.clone())
+                    }
+                    ("timestamp", Field::TimestampMicros(micros)) => {
+                        sample.timestamp = DateTime::from_timestamp_micros(*micros)
+                            .ok_or_else(|| anyhow!("Invalid timestamp {micros}"))?;
+                    }
+                    ("cpu_percentage", Field::Double(value)) => sample.cpu_percentage = *value,
+                    ("memory_total", Field::Long(value)) => sample.memory_total = *value,
+                    ("memory_used", Field::Long(value)) => sample.memory_used = *value,
+                    ("memory_percentage", Field::Double(value)) => {
+                        sample.memory_percentage = *value
+                    }
+                    _ => {}
+                }
+            }
+
+            Ok(sample)
+        })
+        .collect()
+}
+
+pub fn read_custom_metrics(path: &Path) -> Result<Vec<CustomSample>> {
+    let reader =
+        SerializedFileReader::try_from(path).with_context(|| format!("Failed to open {path:?}"))?;
+
+    reader
+        .get_row_iter(None)?
+        .map(|row| {
+            let row = row?;
+            let mut sample = CustomSample {
+                name: String::new(),
+                labels: Default::default(),
+                kind: Default::default(),
+                container: None,
+                value: 0.0,
+                timestamp: Default::default(),
+            };
+
+            // the series is derived from the name and labels again
+            for (name, field) in row.get_column_iter() {
+                match (name.as_str(), field) {
+                    ("name", Field::Str(value)) => sample.name = value.clone(),
+                    ("labels", Field::Str(value)) => sample.labels = serde_json::from_str(value)?,
+                    ("kind", Field::Str(value)) => {
+                        sample.kind = value.parse().map_err(|e: String| anyhow!(e))?
+                    }
+                    ("container", Field::Str(value)) => sample.container = Some(value.clone()),
+                    ("timestamp", Field::TimestampMicros(micros)) => {
+                        sample.timestamp = DateTime::from_timestamp_micros(*micros)
+                            .ok_or_else(|| anyhow!("Invalid timestamp {micros}"))?;
+                    }
+                    ("value", Field::Double(value)) => sample.value = *value,
+                    _ => {}
+                }
+            }
+
+            Ok(sample)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MetricKind;
+    use std::path::PathBuf;
+
+    /// Path in a fresh temporary directory, removed again by the test
+    fn temp_file(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("sentinel-parquet-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory.join("data.parquet")
+    }
+
+    fn at(micros: i64) -> chrono::DateTime<chrono::Utc> {
+        DateTime::from_timestamp_micros(micros).unwrap()
+    }
+
+    #[test]
+    fn usage_round_trips() {
+        let path = temp_file("usage");
+        let samples = vec![
+            ArchivedSample {
+                container: Some("abc".to_string()),
+                container_name: Some("web".to_string()),
+                container_image: Some("nginx:latest".to_string()),
+                timestamp: at(1_700_000_000_123_456),
+                cpu_percentage: 12.5,
+                memory_total: 1 << 30,
+                memory_used: 1 << 20,
+                memory_percentage: 0.1,
+            },
+            ArchivedSample {
+                timestamp: at(1_700_000_001_000_000),
+                cpu_percentage: 3.0,
+                memory_total: 8 << 30,
+                memory_used: 4 << 30,
+                memory_percentage: 50.0,
+                ..Default::default()
+            },
+        ];
+
+        write_usage(&path, &samples).unwrap();
+
+        assert_eq!(table(&path).unwrap(), "usage");
+        assert_eq!(read_usage(&path).unwrap(), samples);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn custom_metrics_round_trip() {
+        let path = temp_file("custom");
+        let samples = vec![
+            CustomSample {
+                name: "queue_length".to_string(),
+                labels: [("queue".to_string(), "mail, \"urgent\"".to_string())].into(),
+                kind: MetricKind::Gauge,
+                container: Some("abc".to_string()),
+                value: 7.0,
+                timestamp: at(1_700_000_000_000_001),
+            },
+            CustomSample {
+                name: "requests".to_string(),
+                labels: Default::default(),
+                kind: MetricKind::Counter,
+                container: None,
+                value: 1e12,
+                timestamp: at(1_700_000_060_000_000),
+            },
+        ];
+
+        write_custom_metrics(&path, &samples).unwrap();
+
+        assert_eq!(table(&path).unwrap(), "custom_metrics");
+        assert_eq!(read_custom_metrics(&path).unwrap(), samples);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
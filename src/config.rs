@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::{
+    alerting::{AlertRule, AnomalyConfig, MaintenanceWindow},
+    db::{OverflowPolicy, StorageBackend},
+    notifier::WebhookConfig,
+    types::Step,
+};
+
+const CONFIG_PATH_ENV: &str = "SENTINEL_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "./sentinel.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub stream: StreamConfig,
+    pub archive: ArchiveConfig,
+    pub alerting: AlertingConfig,
+    pub notifier: NotifierConfig,
+    pub statsd: StatsdConfig,
+    pub scrape: ScrapeConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub backend: StorageBackend,
+    /// Database file, only used by the `sqlite` backend
+    pub path: PathBuf,
+    /// Number of samples kept per host/container series by the `memory` backend
+    pub memory_samples_per_series: usize,
+    /// Maximum number of samples waiting to be written before the overflow policy kicks in
+    pub queue_capacity: usize,
+    pub queue_overflow: OverflowPolicy,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Sqlite,
+            path: PathBuf::from("./test.db"),
+            memory_samples_per_series: 17280,
+            queue_capacity: 4096,
+            queue_overflow: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// Number of samples a live stream subscriber may fall behind before it misses some
+    pub buffer: usize,
+    /// Upper limit of the `backfill` parameter of the stream endpoints, per series
+    pub max_backfill: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            buffer: 1024,
+            max_backfill: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Archives every complete day to Parquet files, see `sentinel-rs export` for a single range
+    pub enabled: bool,
+    pub directory: PathBuf,
+    /// Number of past days checked for missing archives, older days are left alone
+    pub catch_up_days: u32,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: PathBuf::from("./archive"),
+            catch_up_days: 7,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AlertingConfig {
+    /// How often every rule is evaluated
+    pub interval: Step,
+    pub rules: Vec<AlertRule>,
+    /// Recurring silences, in addition to the ones created through the API
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// Rolling baselines the `anomaly` rules and `/anomalies` are based on
+    pub anomaly: AnomalyConfig,
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        Self {
+            interval: "15s".parse().unwrap(),
+            rules: vec![],
+            maintenance_windows: vec![],
+            anomaly: AnomalyConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NotifierConfig {
+    /// Notifications of the same alert rule, or container events, arriving within this time of
+    /// the first one are sent together
+    pub group_wait: Step,
+    /// Identical notifications within this time are sent once, e.g. a crash looping container
+    pub dedup_window: Step,
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl Default for NotifierConfig {
+    fn default() -> Self {
+        Self {
+            group_wait: "10s".parse().unwrap(),
+            dedup_window: "5m".parse().unwrap(),
+            webhooks: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct StatsdConfig {
+    /// Listens for StatsD metrics over UDP and stores them as custom series
+    pub enabled: bool,
+    pub address: String,
+    /// Metrics received within this time are aggregated into one sample per series
+    pub flush_interval: Step,
+    /// Percentiles of the timers stored on every flush, between 0 and 100
+    pub percentiles: Vec<f64>,
+    /// Upper limit of the series aggregated at once, metrics of further series are dropped
+    pub max_series: usize,
+    /// Series not updated for this many flushes are forgotten, a counter starts from zero
+    /// again when it comes back
+    pub expire_after_flushes: u32,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "0.0.0.0:8125".to_string(),
+            flush_interval: "10s".parse().unwrap(),
+            percentiles: vec![50.0, 95.0, 99.0],
+            max_series: 1000,
+            expire_after_flushes: 60,
+        }
+    }
+}
+
+/// Containers opt in with the label `sentinel.scrape=true`. `sentinel.port` is required,
+/// `sentinel.path` defaults to `/metrics` and `sentinel.metrics` overrides `metrics` for the
+/// container with a comma separated list.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ScrapeConfig {
+    /// Scrapes the Prometheus metrics of the labelled containers and stores them as custom series
+    pub enabled: bool,
+    pub interval: Step,
+    pub timeout: Step,
+    /// Names of the stored metrics, a trailing `*` matches any suffix. Every metric when empty.
+    pub metrics: Vec<String>,
+    /// Upper limit of the series stored per container and scrape, the others are dropped
+    pub max_series: usize,
+}
+
+impl Default for ScrapeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: "15s".parse().unwrap(),
+            timeout: "5s".parse().unwrap(),
+            metrics: vec![],
+            max_series: 500,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from the file pointed to by `SENTINEL_CONFIG` (or `./sentinel.toml`).
+    /// A missing default config file is not an error, every option has a default value.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var_os(CONFIG_PATH_ENV).map(PathBuf::from);
+        let explicit = path.is_some();
+        let path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                log::info!("No config file found at {path:?}, using defaults");
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config {path:?}")),
+        };
+
+        toml::from_str(&content).with_context(|| format!("Failed to parse config {path:?}"))
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use super::DbCommand;
+
+/// What to do with a new sample when the command queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait until the database task makes room
+    Block,
+    /// Discard the oldest queued sample to make room for the new one
+    DropOldest,
+    /// Discard the new sample
+    DropNewest,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueStats {
+    pub capacity: usize,
+    pub depth: usize,
+    pub policy: OverflowPolicy,
+    pub dropped_oldest: u64,
+    pub dropped_newest: u64,
+    /// Writes the database task failed to carry out, e.g. because the disk is full
+    pub failed_writes: u64,
+}
+
+#[derive(Debug)]
+pub struct ChannelClosed;
+
+impl std::fmt::Display for ChannelClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "database task is not running")
+    }
+}
+
+impl std::error::Error for ChannelClosed {}
+
+struct State {
+    queue: VecDeque<DbCommand>,
+    /// Number of queued inserts, only these count against the capacity
+    inserts: usize,
+    senders: usize,
+    receiver_alive: bool,
+    dropped_oldest: u64,
+    dropped_newest: u64,
+    failed_writes: u64,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// Wakes the (blocking) database task when a command arrives
+    available: Condvar,
+    /// Wakes blocked senders when room is made in the queue
+    space: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Sending half of the database command queue.
+///
+/// Only inserts are subject to the capacity, queries are always enqueued since their number is
+/// bounded by the in-flight API requests. Of the inserts only samples are dropped under the
+/// overflow policy, the others wait for room like under [`OverflowPolicy::Block`].
+pub struct DbChannelTx {
+    shared: Arc<Shared>,
+}
+
+pub struct DbChannelRx {
+    shared: Arc<Shared>,
+}
+
+pub fn create_command_channel(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (DbChannelTx, DbChannelRx) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            inserts: 0,
+            senders: 1,
+            receiver_alive: true,
+            dropped_oldest: 0,
+            dropped_newest: 0,
+            failed_writes: 0,
+        }),
+        capacity: capacity.max(1),
+        policy,
+        available: Condvar::new(),
+        space: Notify::new(),
+    });
+
+    (
+        DbChannelTx {
+            shared: shared.clone(),
+        },
+        DbChannelRx { shared },
+    )
+}
+
+impl DbChannelTx {
+    pub async fn send(&self, command: DbCommand) -> Result<(), ChannelClosed> {
+        let is_insert = command.is_insert();
+
+        loop {
+            let space = self.shared.space.notified();
+
+            {
+                let mut state = self.shared.lock();
+                if !state.receiver_alive {
+                    return Err(ChannelClosed);
+                }
+
+                if !is_insert || state.inserts < self.shared.capacity {
+                    state.push(command);
+                    self.shared.available.notify_one();
+                    return Ok(());
+                }
+
+                match self.shared.policy {
+                    _ if !command.is_droppable() => {}
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropNewest => {
+                        state.dropped_newest += 1;
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropOldest => {
+                        // waits when only container metadata is queued
+                        if let Some(index) = state.queue.iter().position(DbCommand::is_droppable) {
+                            state.queue.remove(index);
+                            state.inserts -= 1;
+                            state.dropped_oldest += 1;
+                            state.push(command);
+                            self.shared.available.notify_one();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            space.await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.shared.lock();
+
+        QueueStats {
+            capacity: self.shared.capacity,
+            depth: state.queue.len(),
+            policy: self.shared.policy,
+            dropped_oldest: state.dropped_oldest,
+            dropped_newest: state.dropped_newest,
+            failed_writes: state.failed_writes,
+        }
+    }
+}
+
+impl Clone for DbChannelTx {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for DbChannelTx {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+
+        if state.senders == 0 {
+            self.shared.available.notify_all();
+        }
+    }
+}
+
+impl DbChannelRx {
+    /// Blocks the current thread until a command arrives, returns `None` once every sender is gone.
+    pub fn blocking_recv(&mut self) -> Option<DbCommand> {
+        let mut state = self.shared.lock();
+
+        loop {
+            if let Some(command) = state.queue.pop_front() {
+                if command.is_insert() {
+                    state.inserts -= 1;
+                    self.shared.space.notify_waiters();
+                }
+                return Some(command);
+            }
+
+            if state.senders == 0 {
+                return None;
+            }
+
+            state = self
+                .shared
+                .available
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl DbChannelRx {
+    /// Counts a write that failed, see [`QueueStats::failed_writes`]
+    pub fn record_failed_write(&self) {
+        self.shared.lock().failed_writes += 1;
+    }
+}
+
+impl Drop for DbChannelRx {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.queue.clear();
+        state.inserts = 0;
+
+        self.shared.space.notify_waiters();
+    }
+}
+
+impl State {
+    fn push(&mut self, command: DbCommand) {
+        if command.is_insert() {
+            self.inserts += 1;
+        }
+        self.queue.push_back(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::types::{ContainerInfo, CpuUsage, MemoryUsage};
+
+    fn sample(cpu: f64) -> DbCommand {
+        DbCommand::InsertResourceUsage {
+            timestamp: Utc::now(),
+            cpu_usage: CpuUsage { percentage: cpu },
+            memory_usage: MemoryUsage {
+                total: 100,
+                used: 50,
+                percentage: 50.0,
+            },
+            container: None,
+        }
+    }
+
+    fn upsert() -> DbCommand {
+        DbCommand::UpsertContainer {
+            timestamp: Utc::now(),
+            container: ContainerInfo::default(),
+        }
+    }
+
+    fn query() -> DbCommand {
+        DbCommand::GetLastCpuUsage {
+            container: None,
+            respond_to: oneshot::channel().0,
+        }
+    }
+
+    /// CPU percentages of the queued samples, `-1` for any other command
+    fn drain(rx: &mut DbChannelRx, count: usize) -> Vec<f64> {
+        (0..count)
+            .map(|_| match rx.blocking_recv() {
+                Some(DbCommand::InsertResourceUsage { cpu_usage, .. }) => cpu_usage.percentage,
+                Some(_) => -1.0,
+                None => panic!("channel closed"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn drop_newest_discards_new_samples() {
+        let (tx, mut rx) = create_command_channel(2, OverflowPolicy::DropNewest);
+        for cpu in [1.0, 2.0, 3.0] {
+            tx.send(sample(cpu)).await.unwrap();
+        }
+
+        let stats = tx.stats();
+        assert_eq!(stats.depth, 2);
+        assert_eq!((stats.dropped_oldest, stats.dropped_newest), (0, 1));
+        assert_eq!(drain(&mut rx, 2), [1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_discards_queued_samples() {
+        let (tx, mut rx) = create_command_channel(2, OverflowPolicy::DropOldest);
+        for cpu in [1.0, 2.0, 3.0, 4.0] {
+            tx.send(sample(cpu)).await.unwrap();
+        }
+
+        let stats = tx.stats();
+        assert_eq!(stats.depth, 2);
+        assert_eq!((stats.dropped_oldest, stats.dropped_newest), (2, 0));
+        assert_eq!(drain(&mut rx, 2), [3.0, 4.0]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_container_metadata() {
+        let (tx, mut rx) = create_command_channel(2, OverflowPolicy::DropOldest);
+        tx.send(upsert()).await.unwrap();
+        tx.send(sample(1.0)).await.unwrap();
+        tx.send(sample(2.0)).await.unwrap();
+
+        assert_eq!(tx.stats().dropped_oldest, 1);
+        assert_eq!(drain(&mut rx, 2), [-1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (tx, mut rx) = create_command_channel(1, OverflowPolicy::Block);
+        tx.send(sample(1.0)).await.unwrap();
+
+        let tx = Arc::new(tx);
+        let sender = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send(sample(2.0)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!sender.is_finished());
+
+        assert_eq!(drain(&mut rx, 1), [1.0]);
+        tokio::time::timeout(Duration::from_secs(1), sender)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let stats = tx.stats();
+        assert_eq!((stats.dropped_oldest, stats.dropped_newest), (0, 0));
+        assert_eq!(drain(&mut rx, 1), [2.0]);
+    }
+
+    #[tokio::test]
+    async fn container_metadata_waits_instead_of_being_dropped() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let (tx, mut rx) = create_command_channel(1, policy);
+            tx.send(sample(1.0)).await.unwrap();
+
+            let tx = Arc::new(tx);
+            let sender = tokio::spawn({
+                let tx = tx.clone();
+                async move { tx.send(upsert()).await }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(!sender.is_finished());
+
+            assert_eq!(drain(&mut rx, 1), [1.0]);
+            tokio::time::timeout(Duration::from_secs(1), sender)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(drain(&mut rx, 1), [-1.0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_are_not_limited_by_the_capacity() {
+        let (tx, _rx) = create_command_channel(1, OverflowPolicy::DropNewest);
+        tx.send(sample(1.0)).await.unwrap();
+        tx.send(query()).await.unwrap();
+        tx.send(query()).await.unwrap();
+
+        let stats = tx.stats();
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.dropped_newest, 0);
+    }
+
+    #[tokio::test]
+    async fn sending_fails_without_receiver() {
+        let (tx, rx) = create_command_channel(1, OverflowPolicy::Block);
+        drop(rx);
+
+        assert!(tx.send(sample(1.0)).await.is_err());
+    }
+}
@@ -3,19 +3,112 @@ use anyhow::anyhow;
 use chrono::DateTime;
 use chrono::TimeZone;
 use chrono::Utc;
-use rusqlite::Row;
 use rusqlite::named_params;
-use rusqlite::{Connection, OptionalExtension, Params, Statement};
+use rusqlite::types::FromSql;
+use rusqlite::{Connection, OptionalExtension, Params, Row, Statement, ToSql};
+use std::collections::HashMap;
 
+use super::{ContainerMatch, MetricsStore};
 use crate::types::CpuUsageDataPoint;
 use crate::types::Interval;
 use crate::types::MemoryUsageDataPoint;
+use crate::types::{Aggregation, Aggregations, aggregate};
+use crate::types::{
+    AlertEvent, Anomaly, AnomalyQuery, Metric, RangeQuery, RankedContainer, Silence, SilenceMatcher,
+};
+use crate::types::{Annotation, AnnotationQuery};
+use crate::types::{ContainerInfo, ContainerOverview, LatestSample, LatestUsage, UsageSample};
 use crate::types::{CpuUsage, MemoryUsage};
+use crate::types::{CpuUsageAggregatePoint, MemoryUsageAggregatePoint};
+use crate::types::{CpuUsageRangePoint, MemoryUsageRangePoint};
+use crate::types::{
+    CustomAggregatePoint, CustomDataPoint, CustomRangePoint, CustomSample, CustomSeries,
+};
+
+/// Index of the range bucket a row falls into, relative to `:from_seconds`
+const BUCKET_EXPR: &str = "(CAST(strftime('%s', timestamp) AS INTEGER) - :from_seconds) / :step";
+
+/// Rows aggregated by [`DbManager::query_aggregated`]
+enum Source {
+    /// Samples of the host (`None`) or of a container in the `usage` table
+    Usage(Option<String>),
+    /// Samples of a series of the host or of a container in the `custom_metrics` table
+    Custom(String, Option<String>),
+}
+
+/// Labels are stored as a JSON object, rows written before they were collected have none
+fn parse_labels(labels: Option<String>) -> HashMap<String, String> {
+    labels
+        .and_then(|labels| serde_json::from_str(&labels).ok())
+        .unwrap_or_default()
+}
+
+/// Rows of the `custom_last` and `custom_history` queries
+fn to_custom_data_point(row: &Row) -> rusqlite::Result<CustomDataPoint> {
+    Ok(CustomDataPoint {
+        value: row.get(0)?,
+        timestamp: row.get(1)?,
+    })
+}
+
+/// Rows of the `*_cpu_last` and `*_cpu_history` queries
+pub(super) fn to_cpu_data_point(row: &Row) -> rusqlite::Result<CpuUsageDataPoint> {
+    Ok(CpuUsageDataPoint {
+        percentage: row.get(0)?,
+        timestamp: row.get(1)?,
+    })
+}
+
+/// Rows of the `*_memory_last` and `*_memory_history` queries
+pub(super) fn to_memory_data_point(row: &Row) -> rusqlite::Result<MemoryUsageDataPoint> {
+    Ok(MemoryUsageDataPoint {
+        total: row.get(0)?,
+        used: row.get(1)?,
+        percentage: row.get(2)?,
+        timestamp: row.get(3)?,
+    })
+}
+
+fn to_container_overview(row: &Row) -> rusqlite::Result<ContainerOverview> {
+    let latest = match row.get::<_, Option<DateTime<Utc>>>(7)? {
+        Some(timestamp) => Some(LatestUsage {
+            timestamp,
+            cpu_usage: CpuUsage {
+                percentage: row.get(8)?,
+            },
+            memory_usage: MemoryUsage {
+                total: row.get(9)?,
+                used: row.get(10)?,
+                percentage: row.get(11)?,
+            },
+        }),
+        None => None,
+    };
+
+    Ok(ContainerOverview {
+        info: ContainerInfo {
+            id: row.get(0)?,
+            name: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+            image: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            labels: parse_labels(row.get(3)?),
+            status: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+        },
+        first_seen: row.get(5)?,
+        last_seen: row.get(6)?,
+        latest,
+    })
+}
 
 #[derive(Debug)]
 pub struct DbManager<'conn> {
     connection: &'conn Connection,
     insert_usage_stmt: Statement<'conn>,
+    upsert_container_stmt: Statement<'conn>,
+    resolve_container_stmt: Statement<'conn>,
+    get_latest_samples_stmt: Statement<'conn>,
+    get_recent_samples_stmt: Statement<'conn>,
+    get_containers_stmt: Statement<'conn>,
+    get_container_stmt: Statement<'conn>,
     get_last_cpu_container_stmt: Statement<'conn>,
     get_last_cpu_host_stmt: Statement<'conn>,
     get_last_memory_container_stmt: Statement<'conn>,
@@ -24,15 +117,39 @@ pub struct DbManager<'conn> {
     get_history_cpu_container_stmt: Statement<'conn>,
     get_history_memory_host_stmt: Statement<'conn>,
     get_history_memory_container_stmt: Statement<'conn>,
+    insert_alert_event_stmt: Statement<'conn>,
+    get_alert_history_stmt: Statement<'conn>,
+    insert_silence_stmt: Statement<'conn>,
+    get_silences_stmt: Statement<'conn>,
+    insert_anomaly_stmt: Statement<'conn>,
+    get_anomalies_stmt: Statement<'conn>,
+    insert_annotation_stmt: Statement<'conn>,
+    get_annotations_stmt: Statement<'conn>,
+    insert_custom_sample_stmt: Statement<'conn>,
+    get_custom_series_stmt: Statement<'conn>,
+    get_last_custom_stmt: Statement<'conn>,
+    get_history_custom_stmt: Statement<'conn>,
 }
 
 impl<'conn> DbManager<'conn> {
     pub fn new(connection: &'conn Connection) -> Result<Self> {
+        // readers of exports keep their own connection, WAL lets them read while samples are written
+        connection.pragma_update(None, "journal_mode", "WAL")?;
         connection.execute_batch(include_str!("./queries/init.sql"))?;
 
         Ok(Self {
             connection,
             insert_usage_stmt: connection.prepare(include_str!("./queries/insert_usage.sql"))?,
+            upsert_container_stmt: connection
+                .prepare(include_str!("./queries/upsert_container.sql"))?,
+            resolve_container_stmt: connection
+                .prepare(include_str!("./queries/container_resolve.sql"))?,
+            get_latest_samples_stmt: connection
+                .prepare(include_str!("./queries/latest_samples.sql"))?,
+            get_recent_samples_stmt: connection
+                .prepare(include_str!("./queries/recent_samples.sql"))?,
+            get_containers_stmt: connection.prepare(include_str!("./queries/containers.sql"))?,
+            get_container_stmt: connection.prepare(include_str!("./queries/container_get.sql"))?,
             get_last_cpu_container_stmt: connection
                 .prepare(include_str!("./queries/container_cpu_last.sql"))?,
             get_last_memory_container_stmt: connection
@@ -49,10 +166,32 @@ impl<'conn> DbManager<'conn> {
                 .prepare(include_str!("./queries/host_memory_history.sql"))?,
             get_history_memory_container_stmt: connection
                 .prepare(include_str!("./queries/container_memory_history.sql"))?,
+            insert_alert_event_stmt: connection
+                .prepare(include_str!("./queries/insert_alert_event.sql"))?,
+            get_alert_history_stmt: connection
+                .prepare(include_str!("./queries/alert_history.sql"))?,
+            insert_silence_stmt: connection
+                .prepare(include_str!("./queries/insert_silence.sql"))?,
+            get_silences_stmt: connection.prepare(include_str!("./queries/silences.sql"))?,
+            insert_anomaly_stmt: connection
+                .prepare(include_str!("./queries/insert_anomaly.sql"))?,
+            get_anomalies_stmt: connection.prepare(include_str!("./queries/anomalies.sql"))?,
+            insert_annotation_stmt: connection
+                .prepare(include_str!("./queries/insert_annotation.sql"))?,
+            get_annotations_stmt: connection.prepare(include_str!("./queries/annotations.sql"))?,
+            insert_custom_sample_stmt: connection
+                .prepare(include_str!("./queries/insert_custom_sample.sql"))?,
+            get_custom_series_stmt: connection
+                .prepare(include_str!("./queries/custom_series.sql"))?,
+            get_last_custom_stmt: connection.prepare(include_str!("./queries/custom_last.sql"))?,
+            get_history_custom_stmt: connection
+                .prepare(include_str!("./queries/custom_history.sql"))?,
         })
     }
+}
 
-    pub fn insert_resource_usage(
+impl MetricsStore for DbManager<'_> {
+    fn insert_resource_usage(
         &mut self,
         timestamp: DateTime<Utc>,
         memory_usage: MemoryUsage,
@@ -71,7 +210,166 @@ impl<'conn> DbManager<'conn> {
         Ok(())
     }
 
-    pub fn get_last_cpu_usage(
+    fn upsert_container(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        container: ContainerInfo,
+    ) -> Result<()> {
+        self.upsert_container_stmt.execute(named_params!(
+            ":timestamp": timestamp,
+            ":id": container.id,
+            ":name": container.name,
+            ":image": container.image,
+            ":labels": serde_json::to_string(&container.labels)?,
+            ":status": container.status,
+        ))?;
+
+        Ok(())
+    }
+
+    fn resolve_container(&mut self, container: &str) -> Result<ContainerMatch> {
+        let matches = self
+            .resolve_container_stmt
+            .query_map(named_params! {":container": container}, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+            })
+            .and_then(|result| result.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(|e| anyhow!("Failed to resolve container: {e}"))?;
+
+        Ok(match matches.as_slice() {
+            [] => ContainerMatch::NotFound,
+            [(id, _)] | [(id, true), ..] => ContainerMatch::Found(id.clone()),
+            _ => ContainerMatch::Ambiguous(matches.into_iter().map(|(id, _)| id).collect()),
+        })
+    }
+
+    fn get_latest_samples(&mut self, since: DateTime<Utc>) -> Result<Vec<LatestSample>> {
+        self.get_latest_samples_stmt
+            .query_map(named_params! {":since": since}, |row| {
+                let container = match row.get::<_, Option<String>>(0)? {
+                    Some(id) => Some(ContainerInfo {
+                        id,
+                        name: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                        image: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                        labels: parse_labels(row.get(3)?),
+                        status: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                    }),
+                    None => None,
+                };
+
+                Ok(LatestSample {
+                    container,
+                    cpu_usage: CpuUsage {
+                        percentage: row.get(5)?,
+                    },
+                    memory_usage: MemoryUsage {
+                        total: row.get(6)?,
+                        used: row.get(7)?,
+                        percentage: row.get(8)?,
+                    },
+                    timestamp: row.get(9)?,
+                })
+            })
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get latest samples: {e}"))
+    }
+
+    fn get_containers(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ContainerOverview>> {
+        self.get_containers_stmt
+            .query_map(
+                named_params! {":from": from, ":to": to},
+                to_container_overview,
+            )
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get containers: {e}"))
+    }
+
+    fn get_top_containers(
+        &mut self,
+        metric: Metric,
+        aggregation: Aggregation,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<RankedContainer>> {
+        let fun = aggregation
+            .to_sql_function()
+            .ok_or_else(|| anyhow!("Containers can not be ranked by {aggregation:?}"))?;
+
+        let sql = format!(
+            "SELECT usage.container, containers.name, containers.image, containers.labels,
+                containers.status, {fun}(usage.{column}) AS value
+            FROM usage
+            LEFT JOIN containers ON containers.id = usage.container
+            WHERE usage.container IS NOT NULL AND usage.timestamp >= :since
+            GROUP BY usage.container
+            ORDER BY value DESC
+            LIMIT :limit",
+            column = metric.to_column_name()
+        );
+        let mut stmt = self
+            .connection
+            .prepare_cached(&sql)
+            .map_err(|e| anyhow!("Failed to prepare statement: {e}"))?;
+
+        stmt.query_map(named_params! {":since": since, ":limit": limit}, |row| {
+            Ok(RankedContainer {
+                info: ContainerInfo {
+                    id: row.get(0)?,
+                    name: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    image: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    labels: parse_labels(row.get(3)?),
+                    status: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                },
+                value: row.get(5)?,
+            })
+        })
+        .and_then(|result| result.collect())
+        .map_err(|e| anyhow!("Failed to get top containers: {e}"))
+    }
+
+    fn get_container(&mut self, id: &str) -> Result<Option<ContainerOverview>> {
+        self.get_container_stmt
+            .query_one(named_params! {":id": id}, to_container_overview)
+            .optional()
+            .map_err(|e| anyhow!("Failed to get container: {e}"))
+    }
+
+    fn get_recent_samples(
+        &mut self,
+        container: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<UsageSample>> {
+        let mut samples = self
+            .get_recent_samples_stmt
+            .query_map(
+                named_params! {":container": container, ":limit": limit},
+                |row| {
+                    Ok(UsageSample {
+                        timestamp: row.get(0)?,
+                        container: container.clone(),
+                        cpu_usage: CpuUsage {
+                            percentage: row.get(1)?,
+                        },
+                        memory_usage: MemoryUsage {
+                            total: row.get(2)?,
+                            used: row.get(3)?,
+                            percentage: row.get(4)?,
+                        },
+                    })
+                },
+            )
+            .and_then(|result| result.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(|e| anyhow!("Failed to get recent samples: {e}"))?;
+        samples.reverse();
+
+        Ok(samples)
+    }
+
+    fn get_last_cpu_usage(
         &mut self,
         container: Option<String>,
     ) -> Result<Option<CpuUsageDataPoint>> {
@@ -84,7 +382,7 @@ impl<'conn> DbManager<'conn> {
         }
     }
 
-    pub fn get_last_memory_usage(
+    fn get_last_memory_usage(
         &mut self,
         container: Option<String>,
     ) -> Result<Option<MemoryUsageDataPoint>> {
@@ -97,187 +395,670 @@ impl<'conn> DbManager<'conn> {
         }
     }
 
-    pub fn get_interval_cpu_usage(
+    fn get_interval_cpu_usage(
         &mut self,
         interval: Interval,
+        aggregations: &Aggregations,
         container: Option<String>,
-    ) -> Result<Vec<CpuUsageDataPoint>> {
-        self.query_interval(
-            interval,
-            container,
-            |group_column, container_cond| {
-                format!(
-                    "SELECT AVG(cpu_percentage) as percentage, {group_column} as timestamp
-                    FROM usage 
-                    WHERE container {container_cond} AND timestamp BETWEEN :from AND :to
-                    GROUP BY {group_column}
-                    ORDER BY {group_column} ASC"
-                )
-            },
-            |row| {
-                Ok(CpuUsageDataPoint {
-                    percentage: row.get(0)?,
-                    timestamp: row.get(1)?,
-                })
-            },
-        )
+    ) -> Result<Vec<CpuUsageAggregatePoint>> {
+        let to = Utc::now();
+        let from = to - interval.to_duration();
+
+        let groups = self.query_aggregated(
+            interval.to_group_column_name(),
+            &["cpu_percentage"],
+            aggregations.as_slice(),
+            Source::Usage(container),
+            named_params! {":from": from, ":to": to},
+        )?;
+
+        Ok(groups
+            .into_iter()
+            .map(|(timestamp, values)| CpuUsageAggregatePoint {
+                timestamp,
+                percentage: aggregations.to_values(&values),
+            })
+            .collect())
     }
 
-    pub fn get_interval_memory_usage(
+    fn get_interval_memory_usage(
         &mut self,
         interval: Interval,
+        aggregations: &Aggregations,
         container: Option<String>,
-    ) -> Result<Vec<MemoryUsageDataPoint>> {
-        self.query_interval(
-            interval,
-            container,
-            |group_column, container_cond| {
-                format!(
-                    "SELECT AVG(memory_total) as total, AVG(memory_used) as used, AVG(memory_percentage) as percentage, {group_column} as timestamp
-                    FROM usage 
-                    WHERE container {container_cond} AND timestamp BETWEEN :from AND :to
-                    GROUP BY {group_column}
-                    ORDER BY {group_column} ASC"
-                )
-            },
-            |row| {
-                Ok(MemoryUsageDataPoint {
-                    total: row.get::<_, f64>(0)? as u64,
-                    used: row.get::<_, f64>(1)? as u64,
-                    percentage: row.get(2)?,
-                    timestamp: row.get(3)?,
-                })
-            }
-        )
+    ) -> Result<Vec<MemoryUsageAggregatePoint>> {
+        let to = Utc::now();
+        let from = to - interval.to_duration();
+
+        let groups = self.query_aggregated(
+            interval.to_group_column_name(),
+            &["memory_total", "memory_used", "memory_percentage"],
+            aggregations.as_slice(),
+            Source::Usage(container),
+            named_params! {":from": from, ":to": to},
+        )?;
+
+        let count = aggregations.as_slice().len();
+        Ok(groups
+            .into_iter()
+            .map(|(timestamp, values)| MemoryUsageAggregatePoint {
+                timestamp,
+                total: aggregations.to_values(&values[..count]),
+                used: aggregations.to_values(&values[count..count * 2]),
+                percentage: aggregations.to_values(&values[count * 2..]),
+            })
+            .collect())
     }
 
-    pub fn get_cpu_usage_history(
+    fn get_cpu_usage_history(
         &mut self,
         from: Option<DateTime<Utc>>,
         to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
         container: Option<String>,
     ) -> Result<Vec<CpuUsageDataPoint>> {
         let from = from.unwrap_or(Utc.timestamp_opt(0, 0).unwrap());
         let to = to.unwrap_or(Utc::now());
+        // a negative limit means no limit in SQLite
+        let limit = limit.map_or(-1, |limit| limit as i64);
 
         match container {
             Some(container) => Self::query_cpu_usages(
                 &mut self.get_history_cpu_container_stmt,
-                named_params! {":container": container, ":from": from, ":to": to},
+                named_params! {":container": container, ":from": from, ":to": to, ":limit": limit},
             ),
             None => Self::query_cpu_usages(
                 &mut self.get_history_cpu_host_stmt,
-                named_params! {":from": from, ":to": to},
+                named_params! {":from": from, ":to": to, ":limit": limit},
             ),
         }
     }
 
-    pub fn get_memory_usage_history(
+    fn get_memory_usage_history(
         &mut self,
         from: Option<DateTime<Utc>>,
         to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
         container: Option<String>,
     ) -> Result<Vec<MemoryUsageDataPoint>> {
         let from = from.unwrap_or(Utc.timestamp_opt(0, 0).unwrap());
         let to = to.unwrap_or(Utc::now());
+        // a negative limit means no limit in SQLite
+        let limit = limit.map_or(-1, |limit| limit as i64);
 
         match container {
             Some(container) => Self::query_memory_usages(
                 &mut self.get_history_memory_container_stmt,
-                named_params! {":container": container, ":from": from, ":to": to},
+                named_params! {":container": container, ":from": from, ":to": to, ":limit": limit},
             ),
             None => Self::query_memory_usages(
                 &mut self.get_history_memory_host_stmt,
-                named_params! {":from": from, ":to": to},
+                named_params! {":from": from, ":to": to, ":limit": limit},
             ),
         }
     }
 
+    fn get_range_cpu_usage(
+        &mut self,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageRangePoint>> {
+        let groups = self.query_aggregated(
+            BUCKET_EXPR,
+            &["cpu_percentage"],
+            &[range.aggregation],
+            Source::Usage(container),
+            named_params! {
+                ":from": range.from,
+                ":to": range.to,
+                ":from_seconds": range.from.timestamp(),
+                ":step": range.step.num_seconds(),
+            },
+        )?;
+
+        Ok(range.fill(groups, |timestamp, values| CpuUsageRangePoint {
+            timestamp,
+            percentage: values.map(|values| values[0]),
+        }))
+    }
+
+    fn get_range_memory_usage(
+        &mut self,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageRangePoint>> {
+        let groups = self.query_aggregated(
+            BUCKET_EXPR,
+            &["memory_total", "memory_used", "memory_percentage"],
+            &[range.aggregation],
+            Source::Usage(container),
+            named_params! {
+                ":from": range.from,
+                ":to": range.to,
+                ":from_seconds": range.from.timestamp(),
+                ":step": range.step.num_seconds(),
+            },
+        )?;
+
+        Ok(
+            range.fill(groups, |timestamp, values| MemoryUsageRangePoint {
+                timestamp,
+                total: values.as_ref().map(|values| values[0] as u64),
+                used: values.as_ref().map(|values| values[1] as u64),
+                percentage: values.as_ref().map(|values| values[2]),
+            }),
+        )
+    }
+
+    fn insert_alert_event(&mut self, event: AlertEvent) -> Result<()> {
+        self.insert_alert_event_stmt.execute(named_params!(
+            ":rule": event.rule,
+            ":container": event.container,
+            ":state": event.state.as_str(),
+            ":value": event.value,
+            ":threshold": event.threshold,
+            ":timestamp": event.timestamp,
+        ))?;
+
+        Ok(())
+    }
+
+    fn get_alert_history(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<AlertEvent>> {
+        self.get_alert_history_stmt
+            .query_map(
+                named_params! {":from": from, ":to": to, ":limit": limit},
+                |row| {
+                    let state: String = row.get(2)?;
+
+                    Ok(AlertEvent {
+                        rule: row.get(0)?,
+                        container: row.get(1)?,
+                        state: state.parse().map_err(|e: String| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                2,
+                                rusqlite::types::Type::Text,
+                                e.into(),
+                            )
+                        })?,
+                        value: row.get(3)?,
+                        threshold: row.get(4)?,
+                        timestamp: row.get(5)?,
+                    })
+                },
+            )
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get alert history: {e}"))
+    }
+
+    fn insert_silence(&mut self, mut silence: Silence) -> Result<Silence> {
+        self.insert_silence_stmt.execute(named_params!(
+            ":matchers": serde_json::to_string(&silence.matcher)?,
+            ":comment": silence.comment,
+            ":starts_at": silence.starts_at,
+            ":ends_at": silence.ends_at,
+            ":created_at": silence.created_at,
+        ))?;
+        silence.id = self.connection.last_insert_rowid();
+
+        Ok(silence)
+    }
+
+    fn get_silences(&mut self, ended_after: DateTime<Utc>) -> Result<Vec<Silence>> {
+        self.get_silences_stmt
+            .query_map(named_params! {":ended_after": ended_after}, |row| {
+                let matchers: String = row.get(1)?;
+
+                Ok(Silence {
+                    id: row.get(0)?,
+                    // written by `insert_silence`, only unreadable if edited by hand
+                    matcher: serde_json::from_str::<SilenceMatcher>(&matchers).unwrap_or_default(),
+                    comment: row.get(2)?,
+                    starts_at: row.get(3)?,
+                    ends_at: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            })
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get silences: {e}"))
+    }
+
+    fn delete_silence(&mut self, id: i64) -> Result<bool> {
+        let deleted = self.connection.execute(
+            "DELETE FROM silences WHERE id = :id",
+            named_params! {":id": id},
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    fn insert_anomaly(&mut self, anomaly: Anomaly) -> Result<()> {
+        self.insert_anomaly_stmt.execute(named_params!(
+            ":container": anomaly.container,
+            ":metric": anomaly.metric.as_str(),
+            ":value": anomaly.value,
+            ":expected": anomaly.expected,
+            ":score": anomaly.score,
+            ":timestamp": anomaly.timestamp,
+        ))?;
+
+        Ok(())
+    }
+
+    fn get_anomalies(&mut self, query: AnomalyQuery) -> Result<Vec<Anomaly>> {
+        // a negative limit means no limit in SQLite
+        let limit = query.limit.map_or(-1, |limit| limit as i64);
+
+        self.get_anomalies_stmt
+            .query_map(
+                named_params! {
+                    ":from": query.from,
+                    ":to": query.to,
+                    ":all_containers": query.container.is_none(),
+                    ":container": query.container.flatten(),
+                    ":metric": query.metric.map(Metric::as_str),
+                    ":limit": limit,
+                },
+                |row| {
+                    let metric: String = row.get(1)?;
+
+                    Ok(Anomaly {
+                        container: row.get(0)?,
+                        metric: metric.parse().map_err(|e: String| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                1,
+                                rusqlite::types::Type::Text,
+                                e.into(),
+                            )
+                        })?,
+                        value: row.get(2)?,
+                        expected: row.get(3)?,
+                        score: row.get(4)?,
+                        timestamp: row.get(5)?,
+                    })
+                },
+            )
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get anomalies: {e}"))
+    }
+
+    fn insert_annotation(&mut self, mut annotation: Annotation) -> Result<Annotation> {
+        self.insert_annotation_stmt.execute(named_params!(
+            ":container": annotation.container,
+            ":timestamp": annotation.timestamp,
+            ":ends_at": annotation.ends_at,
+            ":text": annotation.text,
+            ":tags": serde_json::to_string(&annotation.tags)?,
+        ))?;
+        annotation.id = self.connection.last_insert_rowid();
+
+        Ok(annotation)
+    }
+
+    fn get_annotations(&mut self, query: AnnotationQuery) -> Result<Vec<Annotation>> {
+        self.get_annotations_stmt
+            .query_map(
+                named_params! {
+                    ":from": query.from,
+                    ":to": query.to,
+                    ":all_containers": query.container.is_none(),
+                    ":container": query.container.flatten(),
+                    ":tag": query.tag,
+                },
+                |row| {
+                    let tags: String = row.get(5)?;
+
+                    Ok(Annotation {
+                        id: row.get(0)?,
+                        container: row.get(1)?,
+                        timestamp: row.get(2)?,
+                        ends_at: row.get(3)?,
+                        text: row.get(4)?,
+                        // written by `insert_annotation`, only unreadable if edited by hand
+                        tags: serde_json::from_str(&tags).unwrap_or_default(),
+                    })
+                },
+            )
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get annotations: {e}"))
+    }
+
+    fn delete_annotation(&mut self, id: i64) -> Result<bool> {
+        let deleted = self.connection.execute(
+            "DELETE FROM annotations WHERE id = :id",
+            named_params! {":id": id},
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    fn insert_custom_samples(&mut self, samples: Vec<CustomSample>) -> Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        for sample in samples {
+            self.insert_custom_sample_stmt.execute(named_params!(
+                ":series": sample.series(),
+                ":name": sample.name,
+                ":labels": serde_json::to_string(&sample.labels)?,
+                ":kind": sample.kind.as_str(),
+                ":container": sample.container,
+                ":value": sample.value,
+                ":timestamp": sample.timestamp,
+            ))?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn get_custom_series(&mut self) -> Result<Vec<CustomSeries>> {
+        self.get_custom_series_stmt
+            .query_map([], |row| {
+                let labels: String = row.get(2)?;
+                let kind: String = row.get(3)?;
+
+                Ok(CustomSeries {
+                    series: row.get(0)?,
+                    name: row.get(1)?,
+                    // written by `insert_custom_samples`, only unreadable if edited by hand
+                    labels: serde_json::from_str(&labels).unwrap_or_default(),
+                    kind: kind.parse().map_err(|e: String| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            3,
+                            rusqlite::types::Type::Text,
+                            e.into(),
+                        )
+                    })?,
+                    container: row.get(4)?,
+                    first_seen: row.get(5)?,
+                    last_seen: row.get(6)?,
+                    samples: row.get(7)?,
+                })
+            })
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to get custom series: {e}"))
+    }
+
+    fn get_last_custom_metric(
+        &mut self,
+        series: String,
+        container: Option<String>,
+    ) -> Result<Option<CustomDataPoint>> {
+        self.get_last_custom_stmt
+            .query_one(
+                named_params! {":series": series, ":container": container},
+                to_custom_data_point,
+            )
+            .optional()
+            .map_err(|e| anyhow!("Failed to get last custom metric: {e}"))
+    }
+
+    fn get_interval_custom_metric(
+        &mut self,
+        series: String,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<CustomAggregatePoint>> {
+        let to = Utc::now();
+        let from = to - interval.to_duration();
+
+        let groups = self.query_aggregated(
+            interval.to_group_column_name(),
+            &["value"],
+            aggregations.as_slice(),
+            Source::Custom(series, container),
+            named_params! {":from": from, ":to": to},
+        )?;
+
+        Ok(groups
+            .into_iter()
+            .map(|(timestamp, values)| CustomAggregatePoint {
+                timestamp,
+                value: aggregations.to_values(&values),
+            })
+            .collect())
+    }
+
+    fn get_custom_metric_history(
+        &mut self,
+        series: String,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<CustomDataPoint>> {
+        let from = from.unwrap_or(Utc.timestamp_opt(0, 0).unwrap());
+        let to = to.unwrap_or(Utc::now());
+        // a negative limit means no limit in SQLite
+        let limit = limit.map_or(-1, |limit| limit as i64);
+
+        self.get_history_custom_stmt
+            .query_map(
+                named_params! {
+                    ":series": series,
+                    ":container": container,
+                    ":from": from,
+                    ":to": to,
+                    ":limit": limit,
+                },
+                to_custom_data_point,
+            )
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to query custom metric: {e}"))
+    }
+
+    fn get_range_custom_metric(
+        &mut self,
+        series: String,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<CustomRangePoint>> {
+        let groups = self.query_aggregated(
+            BUCKET_EXPR,
+            &["value"],
+            &[range.aggregation],
+            Source::Custom(series, container),
+            named_params! {
+                ":from": range.from,
+                ":to": range.to,
+                ":from_seconds": range.from.timestamp(),
+                ":step": range.step.num_seconds(),
+            },
+        )?;
+
+        Ok(range.fill(groups, |timestamp, values| CustomRangePoint {
+            timestamp,
+            value: values.map(|values| values[0]),
+        }))
+    }
+}
+
+impl DbManager<'_> {
     fn query_last_memory_usage(
         stmt: &mut Statement,
         params: impl Params,
     ) -> Result<Option<MemoryUsageDataPoint>> {
-        stmt.query_one(params, |row| {
-            Ok(MemoryUsageDataPoint {
-                total: row.get(0)?,
-                used: row.get(1)?,
-                percentage: row.get(2)?,
-                timestamp: row.get(3)?,
-            })
-        })
-        .optional()
-        .map_err(|e| anyhow!("Failed to get last memory usage: {e}"))
+        stmt.query_one(params, to_memory_data_point)
+            .optional()
+            .map_err(|e| anyhow!("Failed to get last memory usage: {e}"))
     }
 
     fn query_last_cpu_usage(
         stmt: &mut Statement,
         params: impl Params,
     ) -> Result<Option<CpuUsageDataPoint>> {
-        stmt.query_one(params, |row| {
-            Ok(CpuUsageDataPoint {
-                percentage: row.get(0)?,
-                timestamp: row.get(1)?,
-            })
-        })
-        .optional()
-        .map_err(|e| anyhow!("Failed to get last CPU usage: {e}"))
+        stmt.query_one(params, to_cpu_data_point)
+            .optional()
+            .map_err(|e| anyhow!("Failed to get last CPU usage: {e}"))
     }
 
-    fn query_interval<T>(
+    /// Groups the rows of `source` between `:from` and `:to` by `group_expr`, then aggregates
+    /// each of `columns` with every aggregation. The values of a group are laid out column by
+    /// column, each column in the order of `aggregations`.
+    fn query_aggregated<K: FromSql + PartialEq>(
         &self,
-        interval: Interval,
-        container: Option<String>,
-        get_sql: impl FnOnce(&str, String) -> String,
-        fun: impl FnMut(&Row) -> rusqlite::Result<T>,
-    ) -> Result<Vec<T>> {
-        let group_column = interval.to_group_column_name();
-        let container_cond = if let Some(container) = &container {
-            format!("LIKE {container} || '%'")
-        } else {
-            "IS NULL".to_string()
+        group_expr: &str,
+        columns: &[&str],
+        aggregations: &[Aggregation],
+        source: Source,
+        params: &[(&str, &dyn ToSql)],
+    ) -> Result<Vec<(K, Vec<f64>)>> {
+        let (table, container_cond) = match source {
+            Source::Usage(Some(_)) => ("usage", "container = :container"),
+            Source::Usage(None) => ("usage", "container IS NULL"),
+            Source::Custom(..) => (
+                "custom_metrics",
+                "series = :series AND container IS :container",
+            ),
         };
 
-        let sql = get_sql(group_column, container_cond);
+        let mut params = params.to_vec();
+        match &source {
+            Source::Usage(Some(container)) => params.push((":container", container)),
+            Source::Usage(None) => {}
+            Source::Custom(series, container) => {
+                params.push((":series", series));
+                params.push((":container", container));
+            }
+        }
+
+        let sql_functions: Option<Vec<_>> = aggregations
+            .iter()
+            .map(|aggregation| aggregation.to_sql_function())
+            .collect();
+
+        // Percentiles and `last` have no SQLite equivalent, so in that case the raw values are
+        // fetched in timestamp order and aggregated here.
+        let Some(sql_functions) = sql_functions else {
+            let sql = format!(
+                "SELECT {group_expr} AS grp, {}
+                FROM {table}
+                WHERE {container_cond} AND timestamp BETWEEN :from AND :to
+                ORDER BY timestamp ASC",
+                columns.join(", ")
+            );
+            let mut stmt = self
+                .connection
+                .prepare_cached(&sql)
+                .map_err(|e| anyhow!("Failed to prepare statement: {e}"))?;
+
+            let mut rows = stmt.query(params.as_slice())?;
+            let mut groups: Vec<(K, Vec<Vec<f64>>)> = vec![];
+            while let Some(row) = rows.next()? {
+                let key: K = row.get(0)?;
+                if groups.last().is_none_or(|(last, _)| *last != key) {
+                    groups.push((key, vec![vec![]; columns.len()]));
+                }
+
+                let (_, values) = groups.last_mut().unwrap();
+                for (i, column_values) in values.iter_mut().enumerate() {
+                    column_values.push(row.get(i + 1)?);
+                }
+            }
+
+            return Ok(groups
+                .into_iter()
+                .map(|(key, values)| (key, aggregate(&values, aggregations)))
+                .collect());
+        };
+
+        let selects = columns
+            .iter()
+            .flat_map(|column| {
+                sql_functions
+                    .iter()
+                    .map(move |fun| format!("{fun}({column})"))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql = format!(
+            "SELECT {group_expr} AS grp, {selects}
+            FROM {table}
+            WHERE {container_cond} AND timestamp BETWEEN :from AND :to
+            GROUP BY grp
+            ORDER BY grp ASC"
+        );
         let mut stmt = self
             .connection
             .prepare_cached(&sql)
             .map_err(|e| anyhow!("Failed to prepare statement: {e}"))?;
 
-        let to = Utc::now();
-        let from = to - interval.to_duration();
-
-        stmt.query_map(named_params! {":from": from, ":to": to}, fun)
-            .and_then(|result| result.collect())
-            .map_err(|e| anyhow!("Failed to run query_map: {e}"))
+        let value_count = columns.len() * aggregations.len();
+        stmt.query_map(params.as_slice(), |row| {
+            let values = (1..=value_count)
+                .map(|i| row.get(i))
+                .collect::<rusqlite::Result<_>>()?;
+            Ok((row.get(0)?, values))
+        })
+        .and_then(|result| result.collect())
+        .map_err(|e| anyhow!("Failed to run query_map: {e}"))
     }
 
     fn query_memory_usages(
         stmt: &mut Statement,
         params: impl Params,
     ) -> Result<Vec<MemoryUsageDataPoint>> {
-        stmt.query_map(params, |row| {
-            Ok(MemoryUsageDataPoint {
-                total: row.get(0)?,
-                used: row.get(1)?,
-                percentage: row.get(2)?,
-                timestamp: row.get(3)?,
-            })
-        })
-        .and_then(|result| result.collect())
-        .map_err(|e| anyhow!("Failed to query memory usage: {e}"))
+        stmt.query_map(params, to_memory_data_point)
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to query memory usage: {e}"))
     }
 
     fn query_cpu_usages(
         stmt: &mut Statement,
         params: impl Params,
     ) -> Result<Vec<CpuUsageDataPoint>> {
-        stmt.query_map(params, |row| {
-            Ok(CpuUsageDataPoint {
-                percentage: row.get(0)?,
-                timestamp: row.get(1)?,
-            })
-        })
-        .and_then(|result| result.collect())
-        .map_err(|e| anyhow!("Failed to query CPU usage: {e}"))
+        stmt.query_map(params, to_cpu_data_point)
+            .and_then(|result| result.collect())
+            .map_err(|e| anyhow!("Failed to query CPU usage: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::types::MetricKind;
+
+    fn custom(kind: MetricKind, value: f64, seconds: i64) -> CustomSample {
+        CustomSample {
+            name: "orders".to_string(),
+            labels: BTreeMap::from([("shop".to_string(), "web".to_string())]),
+            kind,
+            container: None,
+            value,
+            timestamp: Utc.timestamp_opt(1_800_000_000 + seconds, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn custom_series_are_described_by_their_newest_sample() {
+        let connection = Connection::open_in_memory().unwrap();
+        let mut manager = DbManager::new(&connection).unwrap();
+        // inserted out of order, the newest sample is not the last row
+        manager
+            .insert_custom_samples(vec![
+                custom(MetricKind::Gauge, 1.0, 0),
+                custom(MetricKind::Gauge, 2.0, 20),
+                custom(MetricKind::Counter, 3.0, 10),
+            ])
+            .unwrap();
+
+        let series = manager.get_custom_series().unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].kind, MetricKind::Gauge);
+        assert_eq!(series[0].samples, 3);
+        assert_eq!(
+            series[0].first_seen,
+            custom(MetricKind::Gauge, 0.0, 0).timestamp
+        );
+        assert_eq!(
+            series[0].last_seen,
+            custom(MetricKind::Gauge, 0.0, 20).timestamp
+        );
     }
 }
@@ -0,0 +1,1072 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use super::{ContainerMatch, MetricsStore};
+use crate::types::{
+    Aggregation, Aggregations, AlertEvent, Annotation, AnnotationQuery, Anomaly, AnomalyQuery,
+    ContainerInfo, ContainerOverview, CpuUsage, CpuUsageAggregatePoint, CpuUsageDataPoint,
+    CpuUsageRangePoint, CustomAggregatePoint, CustomDataPoint, CustomRangePoint, CustomSample,
+    CustomSeries, Interval, LatestSample, LatestUsage, MemoryUsage, MemoryUsageAggregatePoint,
+    MemoryUsageDataPoint, MemoryUsageRangePoint, Metric, RangeQuery, RankedContainer, Silence,
+    UsageSample, aggregate,
+};
+
+#[derive(Debug)]
+struct Sample {
+    timestamp: DateTime<Utc>,
+    cpu_percentage: f64,
+    memory_total: u64,
+    memory_used: u64,
+    memory_percentage: f64,
+}
+
+/// A sample of a custom series, the series itself is the key of its [`CustomRecord`]
+#[derive(Debug)]
+struct CustomPoint {
+    timestamp: DateTime<Utc>,
+    value: f64,
+}
+
+/// Samples of anything bucketed by [`between`], [`bucket_interval`] and [`bucket_range`]
+trait Timestamped {
+    fn timestamp(&self) -> DateTime<Utc>;
+}
+
+impl Timestamped for Sample {
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+impl Timestamped for CustomPoint {
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+#[derive(Debug)]
+struct CustomRecord {
+    /// `last_seen` and `samples` are filled in when listed
+    info: CustomSeries,
+    points: VecDeque<CustomPoint>,
+}
+
+#[derive(Debug)]
+struct ContainerRecord {
+    info: ContainerInfo,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+/// Keeps the newest `capacity` samples of every series in memory.
+#[derive(Debug)]
+pub struct MemoryStore {
+    capacity: usize,
+    series: HashMap<Option<String>, VecDeque<Sample>>,
+    containers: HashMap<String, ContainerRecord>,
+    /// Oldest first, holds at most `capacity` events
+    alert_history: VecDeque<AlertEvent>,
+    silences: Vec<Silence>,
+    next_silence_id: i64,
+    /// Oldest first, holds at most `capacity` anomalies
+    anomalies: VecDeque<Anomaly>,
+    /// Oldest first, holds at most `capacity` annotations
+    annotations: VecDeque<Annotation>,
+    next_annotation_id: i64,
+    /// Keyed by series and container, each holds at most `capacity` samples
+    custom: HashMap<(String, Option<String>), CustomRecord>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            series: HashMap::new(),
+            containers: HashMap::new(),
+            alert_history: VecDeque::new(),
+            silences: vec![],
+            next_silence_id: 1,
+            anomalies: VecDeque::new(),
+            annotations: VecDeque::new(),
+            next_annotation_id: 1,
+            custom: HashMap::new(),
+        }
+    }
+
+    fn matching(&self, container: &Option<String>) -> impl Iterator<Item = &Sample> {
+        self.series.get(container).into_iter().flatten()
+    }
+
+    fn last(&self, container: &Option<String>) -> Option<&Sample> {
+        self.matching(container)
+            .max_by_key(|sample| sample.timestamp)
+    }
+
+    fn overview(&self, record: &ContainerRecord) -> ContainerOverview {
+        let latest = self
+            .last(&Some(record.info.id.clone()))
+            .map(|sample| LatestUsage {
+                timestamp: sample.timestamp,
+                cpu_usage: CpuUsage {
+                    percentage: sample.cpu_percentage,
+                },
+                memory_usage: MemoryUsage {
+                    total: sample.memory_total,
+                    used: sample.memory_used,
+                    percentage: sample.memory_percentage,
+                },
+            });
+
+        ContainerOverview {
+            info: record.info.clone(),
+            first_seen: record.first_seen,
+            last_seen: record.last_seen,
+            latest,
+        }
+    }
+
+    fn custom_points(
+        &self,
+        series: String,
+        container: Option<String>,
+    ) -> impl Iterator<Item = &CustomPoint> {
+        self.custom
+            .get(&(series, container))
+            .into_iter()
+            .flat_map(|record| &record.points)
+    }
+}
+
+/// The samples between `from` and `to`, in timestamp order
+fn between<'a, S: Timestamped + 'a>(
+    samples: impl Iterator<Item = &'a S>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Vec<&'a S> {
+    let from = from.unwrap_or(Utc.timestamp_opt(0, 0).unwrap());
+    let to = to.unwrap_or(Utc::now());
+
+    let mut samples: Vec<_> = samples
+        .filter(|sample| sample.timestamp() >= from && sample.timestamp() <= to)
+        .collect();
+    samples.sort_by_key(|sample| sample.timestamp());
+
+    samples
+}
+
+fn bucket_interval<'a, S: Timestamped + 'a, T>(
+    samples: impl Iterator<Item = &'a S>,
+    interval: Interval,
+    fun: impl Fn(DateTime<Utc>, &[&S]) -> T,
+) -> Vec<T> {
+    let to = Utc::now();
+    let from = to - interval.to_duration();
+    let width = interval.to_bucket_width().num_seconds();
+
+    let mut buckets: BTreeMap<i64, Vec<&S>> = BTreeMap::new();
+    for sample in between(samples, Some(from), Some(to)) {
+        let bucket = sample.timestamp().timestamp().div_euclid(width) * width;
+        buckets.entry(bucket).or_default().push(sample);
+    }
+
+    buckets
+        .into_iter()
+        .map(|(bucket, samples)| fun(Utc.timestamp_opt(bucket, 0).unwrap(), &samples))
+        .collect()
+}
+
+fn bucket_range<'a, S: Timestamped + 'a, T>(
+    samples: impl Iterator<Item = &'a S>,
+    range: &RangeQuery,
+    fun: impl Fn(DateTime<Utc>, Option<&[&S]>) -> T,
+) -> Vec<T> {
+    let mut buckets: BTreeMap<i64, Vec<&S>> = BTreeMap::new();
+    for sample in between(samples, Some(range.from), Some(range.to)) {
+        buckets
+            .entry(range.bucket_index(sample.timestamp()))
+            .or_default()
+            .push(sample);
+    }
+
+    range.fill(buckets, |timestamp, samples| {
+        fun(timestamp, samples.as_deref())
+    })
+}
+
+type Column = fn(&Sample) -> f64;
+
+const CPU_COLUMNS: &[Column] = &[|s| s.cpu_percentage];
+const MEMORY_COLUMNS: &[Column] = &[
+    |s| s.memory_total as f64,
+    |s| s.memory_used as f64,
+    |s| s.memory_percentage,
+];
+
+/// Column containers are ranked by, see [`Metric::to_column_name`]
+fn metric_column(metric: Metric) -> Column {
+    match metric {
+        Metric::Cpu => CPU_COLUMNS[0],
+        Metric::Memory => MEMORY_COLUMNS[1],
+    }
+}
+
+/// Same layout as the values returned by the SQLite store, see [`aggregate`]
+fn aggregate_samples(
+    samples: &[&Sample],
+    columns: &[Column],
+    aggregations: &[Aggregation],
+) -> Vec<f64> {
+    let columns: Vec<Vec<f64>> = columns
+        .iter()
+        .map(|column| samples.iter().map(|sample| column(sample)).collect())
+        .collect();
+
+    aggregate(&columns, aggregations)
+}
+
+fn to_custom_data_point(point: &CustomPoint) -> CustomDataPoint {
+    CustomDataPoint {
+        timestamp: point.timestamp,
+        value: point.value,
+    }
+}
+
+fn to_cpu_data_point(sample: &Sample) -> CpuUsageDataPoint {
+    CpuUsageDataPoint {
+        timestamp: sample.timestamp,
+        percentage: sample.cpu_percentage,
+    }
+}
+
+fn to_memory_data_point(sample: &Sample) -> MemoryUsageDataPoint {
+    MemoryUsageDataPoint {
+        timestamp: sample.timestamp,
+        total: sample.memory_total,
+        used: sample.memory_used,
+        percentage: sample.memory_percentage,
+    }
+}
+
+impl MetricsStore for MemoryStore {
+    fn insert_resource_usage(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        memory_usage: MemoryUsage,
+        cpu_usage: CpuUsage,
+        container: Option<String>,
+    ) -> Result<()> {
+        let samples = self.series.entry(container).or_default();
+        if samples.len() >= self.capacity {
+            samples.pop_front();
+        }
+
+        samples.push_back(Sample {
+            timestamp,
+            cpu_percentage: cpu_usage.percentage.round(),
+            memory_total: memory_usage.total,
+            memory_used: memory_usage.used,
+            memory_percentage: memory_usage.percentage.round(),
+        });
+
+        Ok(())
+    }
+
+    fn upsert_container(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        container: ContainerInfo,
+    ) -> Result<()> {
+        let previous = self.containers.get(&container.id);
+        let first_seen = previous.map_or(timestamp, |record| record.first_seen);
+        let last_seen = match previous {
+            Some(record)
+                if container.status != "running" && record.info.status == container.status =>
+            {
+                record.last_seen
+            }
+            _ => timestamp,
+        };
+
+        self.containers.insert(
+            container.id.clone(),
+            ContainerRecord {
+                info: container,
+                first_seen,
+                last_seen,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn resolve_container(&mut self, container: &str) -> Result<ContainerMatch> {
+        if self.containers.contains_key(container) {
+            return Ok(ContainerMatch::Found(container.to_string()));
+        }
+
+        let by_name = self
+            .containers
+            .iter()
+            .filter(|(_, record)| record.info.name == container)
+            .max_by_key(|(_, record)| record.last_seen);
+        if let Some((id, _)) = by_name {
+            return Ok(ContainerMatch::Found(id.clone()));
+        }
+
+        let mut matches: Vec<_> = self
+            .containers
+            .keys()
+            .filter(|id| id.starts_with(container))
+            .cloned()
+            .collect();
+
+        Ok(match matches.len() {
+            0 => ContainerMatch::NotFound,
+            1 => ContainerMatch::Found(matches.remove(0)),
+            _ => {
+                matches.truncate(10);
+                ContainerMatch::Ambiguous(matches)
+            }
+        })
+    }
+
+    fn get_latest_samples(&mut self, since: DateTime<Utc>) -> Result<Vec<LatestSample>> {
+        Ok(self
+            .series
+            .iter()
+            .filter_map(|(key, samples)| {
+                let sample = samples.back().filter(|sample| sample.timestamp >= since)?;
+                let container = key.as_ref().map(|id| match self.containers.get(id) {
+                    Some(record) => record.info.clone(),
+                    None => ContainerInfo {
+                        id: id.clone(),
+                        ..Default::default()
+                    },
+                });
+
+                Some(LatestSample {
+                    container,
+                    timestamp: sample.timestamp,
+                    cpu_usage: CpuUsage {
+                        percentage: sample.cpu_percentage,
+                    },
+                    memory_usage: MemoryUsage {
+                        total: sample.memory_total,
+                        used: sample.memory_used,
+                        percentage: sample.memory_percentage,
+                    },
+                })
+            })
+            .collect())
+    }
+
+    fn get_containers(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ContainerOverview>> {
+        let mut containers: Vec<_> = self
+            .containers
+            .values()
+            .filter(|record| record.last_seen >= from && record.first_seen <= to)
+            .map(|record| self.overview(record))
+            .collect();
+        containers.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then_with(|| a.info.name.cmp(&b.info.name))
+        });
+
+        Ok(containers)
+    }
+
+    fn get_top_containers(
+        &mut self,
+        metric: Metric,
+        aggregation: Aggregation,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<RankedContainer>> {
+        let column = metric_column(metric);
+
+        let mut ranked: Vec<_> = self
+            .series
+            .iter()
+            .filter_map(|(key, samples)| {
+                let id = key.as_ref()?;
+                let values: Vec<f64> = samples
+                    .iter()
+                    .filter(|sample| sample.timestamp >= since)
+                    .map(column)
+                    .collect();
+                let value = aggregation.apply(&values)?;
+
+                let info = match self.containers.get(id) {
+                    Some(record) => record.info.clone(),
+                    None => ContainerInfo {
+                        id: id.clone(),
+                        ..Default::default()
+                    },
+                };
+
+                Some(RankedContainer { info, value })
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.value.total_cmp(&a.value));
+        ranked.truncate(limit);
+
+        Ok(ranked)
+    }
+
+    fn get_container(&mut self, id: &str) -> Result<Option<ContainerOverview>> {
+        Ok(self.containers.get(id).map(|record| self.overview(record)))
+    }
+
+    fn get_recent_samples(
+        &mut self,
+        container: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<UsageSample>> {
+        let samples = self.matching(&container).collect::<Vec<_>>();
+
+        Ok(samples[samples.len().saturating_sub(limit)..]
+            .iter()
+            .map(|sample| UsageSample {
+                timestamp: sample.timestamp,
+                container: container.clone(),
+                cpu_usage: CpuUsage {
+                    percentage: sample.cpu_percentage,
+                },
+                memory_usage: MemoryUsage {
+                    total: sample.memory_total,
+                    used: sample.memory_used,
+                    percentage: sample.memory_percentage,
+                },
+            })
+            .collect())
+    }
+
+    fn get_last_cpu_usage(
+        &mut self,
+        container: Option<String>,
+    ) -> Result<Option<CpuUsageDataPoint>> {
+        Ok(self.last(&container).map(to_cpu_data_point))
+    }
+
+    fn get_last_memory_usage(
+        &mut self,
+        container: Option<String>,
+    ) -> Result<Option<MemoryUsageDataPoint>> {
+        Ok(self.last(&container).map(to_memory_data_point))
+    }
+
+    fn get_interval_cpu_usage(
+        &mut self,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageAggregatePoint>> {
+        Ok(bucket_interval(
+            self.matching(&container),
+            interval,
+            |timestamp, samples| {
+                let values = aggregate_samples(samples, CPU_COLUMNS, aggregations.as_slice());
+
+                CpuUsageAggregatePoint {
+                    timestamp,
+                    percentage: aggregations.to_values(&values),
+                }
+            },
+        ))
+    }
+
+    fn get_interval_memory_usage(
+        &mut self,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageAggregatePoint>> {
+        let count = aggregations.as_slice().len();
+
+        Ok(bucket_interval(
+            self.matching(&container),
+            interval,
+            |timestamp, samples| {
+                let values = aggregate_samples(samples, MEMORY_COLUMNS, aggregations.as_slice());
+
+                MemoryUsageAggregatePoint {
+                    timestamp,
+                    total: aggregations.to_values(&values[..count]),
+                    used: aggregations.to_values(&values[count..count * 2]),
+                    percentage: aggregations.to_values(&values[count * 2..]),
+                }
+            },
+        ))
+    }
+
+    fn get_cpu_usage_history(
+        &mut self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageDataPoint>> {
+        Ok(between(self.matching(&container), from, to)
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(to_cpu_data_point)
+            .collect())
+    }
+
+    fn get_memory_usage_history(
+        &mut self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageDataPoint>> {
+        Ok(between(self.matching(&container), from, to)
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(to_memory_data_point)
+            .collect())
+    }
+
+    fn get_range_cpu_usage(
+        &mut self,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageRangePoint>> {
+        Ok(bucket_range(
+            self.matching(&container),
+            &range,
+            |timestamp, samples| {
+                let values =
+                    samples.map(|s| aggregate_samples(s, CPU_COLUMNS, &[range.aggregation]));
+
+                CpuUsageRangePoint {
+                    timestamp,
+                    percentage: values.map(|values| values[0]),
+                }
+            },
+        ))
+    }
+
+    fn get_range_memory_usage(
+        &mut self,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageRangePoint>> {
+        Ok(bucket_range(
+            self.matching(&container),
+            &range,
+            |timestamp, samples| {
+                let values =
+                    samples.map(|s| aggregate_samples(s, MEMORY_COLUMNS, &[range.aggregation]));
+
+                MemoryUsageRangePoint {
+                    timestamp,
+                    total: values.as_ref().map(|values| values[0] as u64),
+                    used: values.as_ref().map(|values| values[1] as u64),
+                    percentage: values.as_ref().map(|values| values[2]),
+                }
+            },
+        ))
+    }
+
+    fn insert_alert_event(&mut self, event: AlertEvent) -> Result<()> {
+        if self.alert_history.len() >= self.capacity {
+            self.alert_history.pop_front();
+        }
+        self.alert_history.push_back(event);
+
+        Ok(())
+    }
+
+    fn get_alert_history(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<AlertEvent>> {
+        Ok(self
+            .alert_history
+            .iter()
+            .rev()
+            .filter(|event| event.timestamp >= from && event.timestamp <= to)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn insert_silence(&mut self, mut silence: Silence) -> Result<Silence> {
+        silence.id = self.next_silence_id;
+        self.next_silence_id += 1;
+        self.silences.push(silence.clone());
+
+        Ok(silence)
+    }
+
+    fn get_silences(&mut self, ended_after: DateTime<Utc>) -> Result<Vec<Silence>> {
+        let mut silences: Vec<_> = self
+            .silences
+            .iter()
+            .filter(|silence| silence.ends_at > ended_after)
+            .cloned()
+            .collect();
+        silences.sort_by_key(|silence| silence.starts_at);
+
+        Ok(silences)
+    }
+
+    fn delete_silence(&mut self, id: i64) -> Result<bool> {
+        let count = self.silences.len();
+        self.silences.retain(|silence| silence.id != id);
+
+        Ok(self.silences.len() != count)
+    }
+
+    fn insert_anomaly(&mut self, anomaly: Anomaly) -> Result<()> {
+        if self.anomalies.len() >= self.capacity {
+            self.anomalies.pop_front();
+        }
+        self.anomalies.push_back(anomaly);
+
+        Ok(())
+    }
+
+    fn get_anomalies(&mut self, query: AnomalyQuery) -> Result<Vec<Anomaly>> {
+        Ok(self
+            .anomalies
+            .iter()
+            .rev()
+            .filter(|anomaly| anomaly.timestamp >= query.from && anomaly.timestamp <= query.to)
+            .filter(|anomaly| {
+                query
+                    .container
+                    .as_ref()
+                    .is_none_or(|container| &anomaly.container == container)
+            })
+            .filter(|anomaly| query.metric.is_none_or(|metric| anomaly.metric == metric))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    fn insert_annotation(&mut self, mut annotation: Annotation) -> Result<Annotation> {
+        annotation.id = self.next_annotation_id;
+        self.next_annotation_id += 1;
+
+        if self.annotations.len() >= self.capacity {
+            self.annotations.pop_front();
+        }
+        self.annotations.push_back(annotation.clone());
+
+        Ok(annotation)
+    }
+
+    fn get_annotations(&mut self, query: AnnotationQuery) -> Result<Vec<Annotation>> {
+        let mut annotations: Vec<_> = self
+            .annotations
+            .iter()
+            .filter(|annotation| {
+                query
+                    .from
+                    .is_none_or(|from| annotation.ends_at.unwrap_or(annotation.timestamp) >= from)
+            })
+            .filter(|annotation| query.to.is_none_or(|to| annotation.timestamp <= to))
+            .filter(|annotation| {
+                query.container.as_ref().is_none_or(|container| {
+                    annotation.container.is_none() || &annotation.container == container
+                })
+            })
+            .filter(|annotation| {
+                query
+                    .tag
+                    .as_ref()
+                    .is_none_or(|tag| annotation.tags.contains(tag))
+            })
+            .cloned()
+            .collect();
+        // stored in the order they were created, which can differ from their timestamps
+        annotations.sort_by_key(|annotation| annotation.timestamp);
+
+        Ok(annotations)
+    }
+
+    fn delete_annotation(&mut self, id: i64) -> Result<bool> {
+        let count = self.annotations.len();
+        self.annotations.retain(|annotation| annotation.id != id);
+
+        Ok(self.annotations.len() != count)
+    }
+
+    fn insert_custom_samples(&mut self, samples: Vec<CustomSample>) -> Result<()> {
+        for sample in samples {
+            let series = sample.series();
+            let record = self
+                .custom
+                .entry((series.clone(), sample.container.clone()))
+                .or_insert_with(|| CustomRecord {
+                    info: CustomSeries {
+                        series,
+                        name: sample.name.clone(),
+                        labels: sample.labels.clone(),
+                        kind: sample.kind,
+                        container: sample.container.clone(),
+                        first_seen: sample.timestamp,
+                        last_seen: sample.timestamp,
+                        samples: 0,
+                    },
+                    points: VecDeque::new(),
+                });
+            record.info.kind = sample.kind;
+            record.info.first_seen = record.info.first_seen.min(sample.timestamp);
+
+            if record.points.len() >= self.capacity {
+                record.points.pop_front();
+            }
+            record.points.push_back(CustomPoint {
+                timestamp: sample.timestamp,
+                value: sample.value,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn get_custom_series(&mut self) -> Result<Vec<CustomSeries>> {
+        let mut series: Vec<_> = self
+            .custom
+            .values()
+            .map(|record| {
+                let timestamps = record.points.iter().map(|point| point.timestamp);
+
+                // the oldest samples may have been dropped, `first_seen` is kept regardless
+                CustomSeries {
+                    last_seen: timestamps.max().unwrap_or(record.info.first_seen),
+                    samples: record.points.len() as u64,
+                    ..record.info.clone()
+                }
+            })
+            .collect();
+        series.sort_by(|a, b| (&a.series, &a.container).cmp(&(&b.series, &b.container)));
+
+        Ok(series)
+    }
+
+    fn get_last_custom_metric(
+        &mut self,
+        series: String,
+        container: Option<String>,
+    ) -> Result<Option<CustomDataPoint>> {
+        Ok(self
+            .custom_points(series, container)
+            .max_by_key(|point| point.timestamp)
+            .map(to_custom_data_point))
+    }
+
+    fn get_interval_custom_metric(
+        &mut self,
+        series: String,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<CustomAggregatePoint>> {
+        Ok(bucket_interval(
+            self.custom_points(series, container),
+            interval,
+            |timestamp, points| {
+                let values: Vec<f64> = points.iter().map(|point| point.value).collect();
+
+                CustomAggregatePoint {
+                    timestamp,
+                    value: aggregations.to_values(&aggregate(&[values], aggregations.as_slice())),
+                }
+            },
+        ))
+    }
+
+    fn get_custom_metric_history(
+        &mut self,
+        series: String,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<CustomDataPoint>> {
+        Ok(between(self.custom_points(series, container), from, to)
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(to_custom_data_point)
+            .collect())
+    }
+
+    fn get_range_custom_metric(
+        &mut self,
+        series: String,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<CustomRangePoint>> {
+        let aggregation = range.aggregation;
+
+        Ok(bucket_range(
+            self.custom_points(series, container),
+            &range,
+            |timestamp, points| CustomRangePoint {
+                timestamp,
+                value: points.and_then(|points| {
+                    let values: Vec<f64> = points.iter().map(|point| point.value).collect();
+                    aggregation.apply(&values)
+                }),
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::types::Step;
+
+    /// Start of the current 10 second bucket of the `5m` interval
+    fn bucket_start() -> DateTime<Utc> {
+        let now = Utc::now().timestamp();
+        Utc.timestamp_opt(now - now.rem_euclid(10), 0).unwrap()
+    }
+
+    fn insert(
+        store: &mut MemoryStore,
+        container: Option<&str>,
+        timestamp: DateTime<Utc>,
+        cpu: f64,
+    ) {
+        store
+            .insert_resource_usage(
+                timestamp,
+                MemoryUsage {
+                    total: 1000,
+                    used: (cpu * 10.0) as u64,
+                    percentage: cpu,
+                },
+                CpuUsage { percentage: cpu },
+                container.map(str::to_string),
+            )
+            .unwrap();
+    }
+
+    fn custom(name: &str, value: f64, timestamp: DateTime<Utc>) -> CustomSample {
+        CustomSample {
+            name: name.to_string(),
+            labels: BTreeMap::from([("shop".to_string(), "web".to_string())]),
+            kind: Default::default(),
+            container: None,
+            value,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn last_is_the_newest_sample_of_the_series() {
+        let mut store = MemoryStore::new(10);
+        let start = bucket_start();
+        insert(&mut store, None, start, 10.0);
+        insert(&mut store, None, start + Duration::seconds(2), 20.0);
+        insert(&mut store, Some("c1"), start + Duration::seconds(4), 30.0);
+
+        let host = store.get_last_cpu_usage(None).unwrap().unwrap();
+        assert_eq!(host.percentage, 20.0);
+        assert_eq!(host.timestamp, start + Duration::seconds(2));
+
+        let memory = store
+            .get_last_memory_usage(Some("c1".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!((memory.used, memory.percentage), (300, 30.0));
+
+        assert!(
+            store
+                .get_last_cpu_usage(Some("c2".to_string()))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn oldest_samples_are_evicted_at_capacity() {
+        let mut store = MemoryStore::new(2);
+        // history ends now without `to`
+        let start = bucket_start() - Duration::minutes(1);
+        for (seconds, cpu) in [(0, 1.0), (1, 2.0), (2, 3.0)] {
+            insert(&mut store, None, start + Duration::seconds(seconds), cpu);
+        }
+
+        let history = store.get_cpu_usage_history(None, None, None, None).unwrap();
+        let values: Vec<_> = history.iter().map(|point| point.percentage).collect();
+        assert_eq!(values, [2.0, 3.0]);
+    }
+
+    #[test]
+    fn interval_aggregates_buckets() {
+        let mut store = MemoryStore::new(10);
+        let start = bucket_start();
+        insert(&mut store, None, start - Duration::seconds(10), 10.0);
+        insert(&mut store, None, start - Duration::seconds(9), 30.0);
+        insert(&mut store, None, start, 50.0);
+        // outside of the interval
+        insert(&mut store, None, start - Duration::minutes(10), 90.0);
+
+        let aggregations: Aggregations = "avg,max,count".to_string().try_into().unwrap();
+        let points = store
+            .get_interval_cpu_usage(Interval::Minute5, &aggregations, None)
+            .unwrap();
+
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp, start - Duration::seconds(10));
+        assert_eq!(points[0].percentage[&Aggregation::Avg], 20.0);
+        assert_eq!(points[0].percentage[&Aggregation::Max], 30.0);
+        assert_eq!(points[0].percentage[&Aggregation::Count], 2.0);
+        assert_eq!(points[1].timestamp, start);
+        assert_eq!(points[1].percentage[&Aggregation::Avg], 50.0);
+    }
+
+    #[test]
+    fn history_is_filtered_ordered_and_limited() {
+        let mut store = MemoryStore::new(10);
+        let start = bucket_start() - Duration::minutes(1);
+        // inserted out of order
+        for (seconds, cpu) in [(3, 4.0), (0, 1.0), (2, 3.0), (1, 2.0)] {
+            insert(&mut store, None, start + Duration::seconds(seconds), cpu);
+        }
+        insert(&mut store, Some("c1"), start, 99.0);
+
+        let history = store
+            .get_cpu_usage_history(
+                Some(start + Duration::seconds(1)),
+                Some(start + Duration::seconds(3)),
+                None,
+                None,
+            )
+            .unwrap();
+        let values: Vec<_> = history.iter().map(|point| point.percentage).collect();
+        assert_eq!(values, [2.0, 3.0, 4.0]);
+
+        let limited = store
+            .get_memory_usage_history(None, None, Some(2), None)
+            .unwrap();
+        let values: Vec<_> = limited.iter().map(|point| point.percentage).collect();
+        assert_eq!(values, [1.0, 2.0]);
+    }
+
+    #[test]
+    fn range_fills_gaps() {
+        let mut store = MemoryStore::new(10);
+        let from = bucket_start() - Duration::minutes(5);
+        insert(&mut store, None, from, 10.0);
+        insert(&mut store, None, from + Duration::seconds(30), 20.0);
+        insert(&mut store, None, from + Duration::seconds(120), 40.0);
+
+        let step: Step = "1m".parse().unwrap();
+        let range =
+            RangeQuery::new(from, from + Duration::minutes(2), step, Aggregation::Max).unwrap();
+        let points = store.get_range_cpu_usage(range, None).unwrap();
+
+        let values: Vec<_> = points.iter().map(|point| point.percentage).collect();
+        assert_eq!(values, [Some(20.0), None, Some(40.0)]);
+        assert_eq!(points[1].timestamp, from + Duration::minutes(1));
+
+        let memory = store.get_range_memory_usage(range, None).unwrap();
+        assert_eq!(memory[0].used, Some(200));
+        assert_eq!(memory[1].used, None);
+    }
+
+    #[test]
+    fn custom_series_are_kept_apart() {
+        let mut store = MemoryStore::new(10);
+        let start = bucket_start() - Duration::minutes(1);
+        store
+            .insert_custom_samples(vec![
+                custom("orders", 1.0, start),
+                custom("orders", 3.0, start + Duration::seconds(1)),
+                custom("visits", 7.0, start),
+            ])
+            .unwrap();
+
+        let series = custom("orders", 0.0, start).series();
+        let last = store
+            .get_last_custom_metric(series.clone(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(last.value, 3.0);
+
+        let history = store
+            .get_custom_metric_history(series, None, None, None, None)
+            .unwrap();
+        let values: Vec<_> = history.iter().map(|point| point.value).collect();
+        assert_eq!(values, [1.0, 3.0]);
+
+        let mut listed = store.get_custom_series().unwrap();
+        listed.sort_by(|a, b| a.series.cmp(&b.series));
+        assert_eq!(listed.len(), 2);
+        assert_eq!((listed[0].samples, listed[0].first_seen), (2, start));
+    }
+
+    #[test]
+    fn recent_samples_are_the_newest_of_the_series() {
+        let mut store = MemoryStore::new(10);
+        let start = bucket_start() - Duration::minutes(1);
+        for seconds in 0..4 {
+            insert(
+                &mut store,
+                None,
+                start + Duration::seconds(seconds),
+                seconds as f64,
+            );
+        }
+        insert(&mut store, Some("c1"), start, 50.0);
+
+        let recent = store.get_recent_samples(None, 2).unwrap();
+        let values: Vec<_> = recent
+            .iter()
+            .map(|sample| (sample.container.clone(), sample.cpu_usage.percentage))
+            .collect();
+        assert_eq!(values, [(None, 2.0), (None, 3.0)]);
+
+        let recent = store.get_recent_samples(Some("c1".to_string()), 5).unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].container.as_deref(), Some("c1"));
+        assert_eq!(recent[0].memory_usage.used, 500);
+    }
+
+    #[test]
+    fn stopped_containers_keep_their_last_seen() {
+        let mut store = MemoryStore::new(10);
+        let start = bucket_start();
+        let mut upsert = |seconds, status: &str| {
+            let container = ContainerInfo {
+                id: "c1".to_string(),
+                name: "web".to_string(),
+                image: "nginx".to_string(),
+                labels: Default::default(),
+                status: status.to_string(),
+            };
+            store
+                .upsert_container(start + Duration::seconds(seconds), container)
+                .unwrap();
+            let record = store.get_container("c1").unwrap().unwrap();
+            (record.first_seen, record.last_seen)
+        };
+
+        assert_eq!(upsert(0, "running"), (start, start));
+        assert_eq!(upsert(5, "running").1, start + Duration::seconds(5));
+        assert_eq!(upsert(10, "exited").1, start + Duration::seconds(10));
+        assert_eq!(upsert(15, "exited").1, start + Duration::seconds(10));
+        assert_eq!(
+            upsert(20, "running"),
+            (start, start + Duration::seconds(20))
+        );
+    }
+}
@@ -1,18 +1,33 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use rusqlite::Connection;
-use tokio::{
-    sync::{
-        mpsc::{UnboundedReceiver, UnboundedSender},
-        oneshot,
+use std::path::Path;
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    config::DatabaseConfig,
+    types::{
+        Aggregation, Aggregations, AlertEvent, Annotation, AnnotationQuery, Anomaly, AnomalyQuery,
+        ContainerInfo, ContainerOverview, CpuUsage, CpuUsageAggregatePoint, CpuUsageDataPoint,
+        CpuUsageRangePoint, CustomAggregatePoint, CustomDataPoint, CustomRangePoint, CustomSample,
+        CustomSeries, Interval, LatestSample, MemoryUsage, MemoryUsageAggregatePoint,
+        MemoryUsageDataPoint, MemoryUsageRangePoint, Metric, RangeQuery, RankedContainer, Series,
+        SeriesQuery, Silence, UsageSample,
     },
-    task::JoinHandle,
 };
-
-use crate::types::{CpuUsage, CpuUsageDataPoint, Interval, MemoryUsage, MemoryUsageDataPoint};
+pub use channel::{
+    ChannelClosed, DbChannelRx, DbChannelTx, OverflowPolicy, QueueStats, create_command_channel,
+};
 use manager::DbManager;
+use memory::MemoryStore;
+pub use reader::HistoryReader;
+pub use store::{ContainerMatch, MetricsStore, StorageBackend};
 
+mod channel;
 mod manager;
+mod memory;
+mod reader;
+mod store;
 
 pub enum DbCommand {
     InsertResourceUsage {
@@ -21,49 +36,198 @@ pub enum DbCommand {
         memory_usage: MemoryUsage,
         container: Option<String>,
     },
+    UpsertContainer {
+        timestamp: DateTime<Utc>,
+        container: ContainerInfo,
+    },
+    ResolveContainer {
+        container: String,
+        respond_to: oneshot::Sender<Result<ContainerMatch>>,
+    },
+    GetLatestSamples {
+        since: DateTime<Utc>,
+        respond_to: oneshot::Sender<Result<Vec<LatestSample>>>,
+    },
+    GetContainers {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        respond_to: oneshot::Sender<Result<Vec<ContainerOverview>>>,
+    },
+    GetTopContainers {
+        metric: Metric,
+        aggregation: Aggregation,
+        since: DateTime<Utc>,
+        limit: usize,
+        respond_to: oneshot::Sender<Result<Vec<RankedContainer>>>,
+    },
+    GetContainer {
+        id: String,
+        respond_to: oneshot::Sender<Result<Option<ContainerOverview>>>,
+    },
+    GetRecentSamples {
+        container: Option<String>,
+        limit: usize,
+        respond_to: oneshot::Sender<Result<Vec<UsageSample>>>,
+    },
     GetLastCpuUsage {
         container: Option<String>,
-        respond_to: oneshot::Sender<Option<CpuUsageDataPoint>>,
+        respond_to: oneshot::Sender<Result<Option<CpuUsageDataPoint>>>,
     },
     GetLastMemoryUsage {
         container: Option<String>,
-        respond_to: oneshot::Sender<Option<MemoryUsageDataPoint>>,
+        respond_to: oneshot::Sender<Result<Option<MemoryUsageDataPoint>>>,
     },
     GetIntervalCpuUsage {
         interval: Interval,
+        aggregations: Aggregations,
         container: Option<String>,
-        respond_to: oneshot::Sender<Vec<CpuUsageDataPoint>>,
+        respond_to: oneshot::Sender<Result<Vec<CpuUsageAggregatePoint>>>,
     },
     GetIntervalMemoryUsage {
         interval: Interval,
+        aggregations: Aggregations,
         container: Option<String>,
-        respond_to: oneshot::Sender<Vec<MemoryUsageDataPoint>>,
+        respond_to: oneshot::Sender<Result<Vec<MemoryUsageAggregatePoint>>>,
     },
     GetCpuUsageHistory {
         from: Option<DateTime<Utc>>,
         to: Option<DateTime<Utc>>,
+        /// Only the first `limit` points are returned when set
+        limit: Option<usize>,
         container: Option<String>,
-        respond_to: oneshot::Sender<Vec<CpuUsageDataPoint>>,
+        respond_to: oneshot::Sender<Result<Vec<CpuUsageDataPoint>>>,
     },
     GetMemoryUsageHistory {
         from: Option<DateTime<Utc>>,
         to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<MemoryUsageDataPoint>>>,
+    },
+    GetRangeCpuUsage {
+        range: RangeQuery,
+        container: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<CpuUsageRangePoint>>>,
+    },
+    GetRangeMemoryUsage {
+        range: RangeQuery,
+        container: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<MemoryUsageRangePoint>>>,
+    },
+    GetRangeSeries {
+        queries: Vec<SeriesQuery>,
+        respond_to: oneshot::Sender<Result<Vec<Series>>>,
+    },
+    /// Not subject to the overflow policy of the sample inserts, alert events are never dropped
+    InsertAlertEvent { event: AlertEvent },
+    GetAlertHistory {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+        respond_to: oneshot::Sender<Result<Vec<AlertEvent>>>,
+    },
+    InsertSilence {
+        silence: Silence,
+        respond_to: oneshot::Sender<Result<Silence>>,
+    },
+    GetSilences {
+        ended_after: DateTime<Utc>,
+        respond_to: oneshot::Sender<Result<Vec<Silence>>>,
+    },
+    DeleteSilence {
+        id: i64,
+        respond_to: oneshot::Sender<Result<bool>>,
+    },
+    /// Not subject to the overflow policy either, anomalies are rare
+    InsertAnomaly { anomaly: Anomaly },
+    GetAnomalies {
+        query: AnomalyQuery,
+        respond_to: oneshot::Sender<Result<Vec<Anomaly>>>,
+    },
+    InsertAnnotation {
+        annotation: Annotation,
+        respond_to: oneshot::Sender<Result<Annotation>>,
+    },
+    GetAnnotations {
+        query: AnnotationQuery,
+        respond_to: oneshot::Sender<Result<Vec<Annotation>>>,
+    },
+    DeleteAnnotation {
+        id: i64,
+        respond_to: oneshot::Sender<Result<bool>>,
+    },
+    /// Dropped under the overflow policy like the usage samples, a dropped batch loses all its
+    /// samples
+    InsertCustomSamples { samples: Vec<CustomSample> },
+    GetCustomSeries {
+        respond_to: oneshot::Sender<Result<Vec<CustomSeries>>>,
+    },
+    GetLastCustomMetric {
+        series: String,
+        container: Option<String>,
+        respond_to: oneshot::Sender<Result<Option<CustomDataPoint>>>,
+    },
+    GetIntervalCustomMetric {
+        series: String,
+        interval: Interval,
+        aggregations: Aggregations,
+        container: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<CustomAggregatePoint>>>,
+    },
+    GetCustomMetricHistory {
+        series: String,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
         container: Option<String>,
-        respond_to: oneshot::Sender<Vec<MemoryUsageDataPoint>>,
+        respond_to: oneshot::Sender<Result<Vec<CustomDataPoint>>>,
+    },
+    GetRangeCustomMetric {
+        series: String,
+        range: RangeQuery,
+        container: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<CustomRangePoint>>>,
     },
 }
 
-pub type DbChannelTx = UnboundedSender<DbCommand>;
-pub type DbChannelRx = UnboundedReceiver<DbCommand>;
+impl DbCommand {
+    /// Counts against the capacity of the command queue
+    fn is_insert(&self) -> bool {
+        self.is_droppable() || matches!(self, DbCommand::UpsertContainer { .. })
+    }
 
-pub fn create_command_channel() -> (DbChannelTx, DbChannelRx) {
-    tokio::sync::mpsc::unbounded_channel::<DbCommand>()
+    /// May be dropped under the overflow policy. Container metadata always waits for room,
+    /// without it the containers of the samples could not be resolved.
+    fn is_droppable(&self) -> bool {
+        matches!(
+            self,
+            DbCommand::InsertResourceUsage { .. } | DbCommand::InsertCustomSamples { .. }
+        )
+    }
 }
 
-pub fn start(mut db_rx: DbChannelRx) -> JoinHandle<Result<()>> {
+/// Opens the SQLite database at `path`, creating its tables when needed
+pub fn open_sqlite(path: &Path) -> Result<Connection> {
+    let connection = Connection::open(path)?;
+    DbManager::new(&connection)?;
+
+    Ok(connection)
+}
+
+pub fn start(config: &DatabaseConfig, mut db_rx: DbChannelRx) -> JoinHandle<Result<()>> {
+    let backend = config.backend;
+    let path = config.path.clone();
+    let memory_capacity = config.memory_samples_per_series;
+
     tokio::task::spawn_blocking(move || {
-        let connection = Connection::open("./test.db")?;
-        let mut db = DbManager::new(&connection)?;
+        let connection;
+        let mut db: Box<dyn MetricsStore> = match backend {
+            StorageBackend::Sqlite => {
+                connection = Connection::open(path)?;
+                Box::new(DbManager::new(&connection)?)
+            }
+            StorageBackend::Memory => Box::new(MemoryStore::new(memory_capacity)),
+        };
 
         while let Some(command) = db_rx.blocking_recv() {
             match command {
@@ -73,72 +237,232 @@ pub fn start(mut db_rx: DbChannelRx) -> JoinHandle<Result<()>> {
                     cpu_usage,
                     container,
                 } => {
-                    db.insert_resource_usage(timestamp, memory_usage, cpu_usage, container)?;
+                    let result =
+                        db.insert_resource_usage(timestamp, memory_usage, cpu_usage, container);
+                    report_write(&db_rx, "usage sample", result);
+                }
+                DbCommand::UpsertContainer {
+                    timestamp,
+                    container,
+                } => {
+                    let result = db.upsert_container(timestamp, container);
+                    report_write(&db_rx, "container", result);
+                }
+                DbCommand::GetLatestSamples { since, respond_to } => {
+                    let result = db.get_latest_samples(since);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::ResolveContainer {
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.resolve_container(&container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetContainers {
+                    from,
+                    to,
+                    respond_to,
+                } => {
+                    let result = db.get_containers(from, to);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetTopContainers {
+                    metric,
+                    aggregation,
+                    since,
+                    limit,
+                    respond_to,
+                } => {
+                    let result = db.get_top_containers(metric, aggregation, since, limit);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetContainer { id, respond_to } => {
+                    let result = db.get_container(&id);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetRecentSamples {
+                    container,
+                    limit,
+                    respond_to,
+                } => {
+                    let result = db.get_recent_samples(container, limit);
+                    let _ = respond_to.send(result);
                 }
                 DbCommand::GetLastCpuUsage {
                     container,
                     respond_to,
                 } => {
-                    let result = db
-                        .get_last_cpu_usage(container)
-                        .inspect_err(|e| log::error!("Error getting last cpu usage: {e}"))
-                        .unwrap_or(None);
+                    let result = db.get_last_cpu_usage(container);
                     let _ = respond_to.send(result);
                 }
                 DbCommand::GetLastMemoryUsage {
                     container,
                     respond_to,
                 } => {
-                    let result = db
-                        .get_last_memory_usage(container)
-                        .inspect_err(|e| log::error!("Error getting last memory usage: {e}"))
-                        .unwrap_or(None);
+                    let result = db.get_last_memory_usage(container);
                     let _ = respond_to.send(result);
                 }
                 DbCommand::GetIntervalCpuUsage {
                     interval,
+                    aggregations,
                     container,
                     respond_to,
                 } => {
-                    let result = db
-                        .get_interval_cpu_usage(interval, container)
-                        .inspect_err(|e| log::error!("Error getting interval memory usage: {e}"))
-                        .unwrap_or_default();
+                    let result = db.get_interval_cpu_usage(interval, &aggregations, container);
                     let _ = respond_to.send(result);
                 }
                 DbCommand::GetIntervalMemoryUsage {
                     interval,
+                    aggregations,
                     container,
                     respond_to,
                 } => {
-                    let result = db
-                        .get_interval_memory_usage(interval, container)
-                        .inspect_err(|e| log::error!("Error getting interval memory usage: {e}"))
-                        .unwrap_or_default();
+                    let result = db.get_interval_memory_usage(interval, &aggregations, container);
                     let _ = respond_to.send(result);
                 }
                 DbCommand::GetCpuUsageHistory {
                     from,
                     to,
+                    limit,
                     container,
                     respond_to,
                 } => {
-                    let result = db
-                        .get_cpu_usage_history(from, to, container)
-                        .inspect_err(|e| log::error!("Error getting cpu usage history: {e}"))
-                        .unwrap_or_default();
+                    let result = db.get_cpu_usage_history(from, to, limit, container);
                     let _ = respond_to.send(result);
                 }
                 DbCommand::GetMemoryUsageHistory {
                     from,
                     to,
+                    limit,
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.get_memory_usage_history(from, to, limit, container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetRangeCpuUsage {
+                    range,
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.get_range_cpu_usage(range, container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetRangeMemoryUsage {
+                    range,
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.get_range_memory_usage(range, container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetRangeSeries {
+                    queries,
+                    respond_to,
+                } => {
+                    let result = db.get_range_series(queries);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::InsertAlertEvent { event } => {
+                    let result = db.insert_alert_event(event);
+                    report_write(&db_rx, "alert event", result);
+                }
+                DbCommand::GetAlertHistory {
+                    from,
+                    to,
+                    limit,
+                    respond_to,
+                } => {
+                    let result = db.get_alert_history(from, to, limit);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::InsertSilence {
+                    silence,
+                    respond_to,
+                } => {
+                    let result = db.insert_silence(silence);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetSilences {
+                    ended_after,
+                    respond_to,
+                } => {
+                    let result = db.get_silences(ended_after);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::DeleteSilence { id, respond_to } => {
+                    let result = db.delete_silence(id);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::InsertAnomaly { anomaly } => {
+                    let result = db.insert_anomaly(anomaly);
+                    report_write(&db_rx, "anomaly", result);
+                }
+                DbCommand::GetAnomalies { query, respond_to } => {
+                    let result = db.get_anomalies(query);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::InsertAnnotation {
+                    annotation,
+                    respond_to,
+                } => {
+                    let result = db.insert_annotation(annotation);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetAnnotations { query, respond_to } => {
+                    let result = db.get_annotations(query);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::DeleteAnnotation { id, respond_to } => {
+                    let result = db.delete_annotation(id);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::InsertCustomSamples { samples } => {
+                    let result = db.insert_custom_samples(samples);
+                    report_write(&db_rx, "custom samples", result);
+                }
+                DbCommand::GetCustomSeries { respond_to } => {
+                    let result = db.get_custom_series();
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetLastCustomMetric {
+                    series,
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.get_last_custom_metric(series, container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetIntervalCustomMetric {
+                    series,
+                    interval,
+                    aggregations,
                     container,
                     respond_to,
                 } => {
-                    let result = db
-                        .get_memory_usage_history(from, to, container)
-                        .inspect_err(|e| log::error!("Error getting memory usage history: {e}"))
-                        .unwrap_or_default();
+                    let result =
+                        db.get_interval_custom_metric(series, interval, &aggregations, container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetCustomMetricHistory {
+                    series,
+                    from,
+                    to,
+                    limit,
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.get_custom_metric_history(series, from, to, limit, container);
+                    let _ = respond_to.send(result);
+                }
+                DbCommand::GetRangeCustomMetric {
+                    series,
+                    range,
+                    container,
+                    respond_to,
+                } => {
+                    let result = db.get_range_custom_metric(series, range, container);
                     let _ = respond_to.send(result);
                 }
             };
@@ -147,3 +471,23 @@ pub fn start(mut db_rx: DbChannelRx) -> JoinHandle<Result<()>> {
         Ok(())
     })
 }
+
+/// Sends a command to the database task and waits for its response. Fails with
+/// [`ChannelClosed`] when the database task is not running.
+pub async fn query<T, F>(db_tx: &DbChannelTx, fun: F) -> Result<T>
+where
+    F: FnOnce(oneshot::Sender<Result<T>>) -> DbCommand,
+{
+    let (tx, rx) = oneshot::channel();
+    db_tx.send(fun(tx)).await?;
+
+    rx.await.map_err(|_| ChannelClosed)?
+}
+
+/// A failed write only loses its own data, the database task keeps serving the others
+fn report_write(db_rx: &DbChannelRx, what: &str, result: Result<()>) {
+    if let Err(e) = result {
+        log::error!("Failed to write {what} to the database: {e}");
+        db_rx.record_failed_write();
+    }
+}
@@ -0,0 +1,16 @@
+SELECT
+  rule,
+  container,
+  state,
+  value,
+  threshold,
+  timestamp
+FROM
+  alert_history
+WHERE
+  timestamp BETWEEN :from
+  AND :to
+ORDER BY
+  timestamp DESC
+LIMIT
+  :limit;
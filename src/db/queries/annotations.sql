@@ -0,0 +1,36 @@
+SELECT
+  id,
+  container,
+  timestamp,
+  ends_at,
+  text,
+  tags
+FROM
+  annotations
+WHERE
+  (
+    :from IS NULL
+    OR COALESCE(ends_at, timestamp) >= :from
+  )
+  AND (
+    :to IS NULL
+    OR timestamp <= :to
+  )
+  AND (
+    :all_containers
+    OR container IS NULL
+    OR container = :container
+  )
+  AND (
+    :tag IS NULL
+    OR EXISTS (
+      SELECT
+        1
+      FROM
+        json_each(tags)
+      WHERE
+        value = :tag
+    )
+  )
+ORDER BY
+  timestamp ASC;
@@ -0,0 +1,24 @@
+SELECT
+  container,
+  metric,
+  value,
+  expected,
+  score,
+  timestamp
+FROM
+  anomalies
+WHERE
+  timestamp BETWEEN :from
+  AND :to
+  AND (
+    :all_containers
+    OR container IS :container
+  )
+  AND (
+    :metric IS NULL
+    OR metric = :metric
+  )
+ORDER BY
+  timestamp DESC
+LIMIT
+  :limit;
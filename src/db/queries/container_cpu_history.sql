@@ -4,8 +4,11 @@ SELECT
 FROM
   usage
 WHERE
-  container LIKE (:container || '%')
+  container = :container
   AND timestamp BETWEEN :from
   AND :to
 ORDER BY
-  timestamp ASC;
\ No newline at end of file
+  timestamp ASC,
+  rowid ASC
+LIMIT
+  :limit;
\ No newline at end of file
@@ -4,7 +4,7 @@ SELECT
 FROM
   usage
 WHERE
-  container LIKE (:container || '%')
+  container = :container
 ORDER BY
   timestamp DESC
 LIMIT
@@ -0,0 +1,29 @@
+SELECT
+  containers.id,
+  containers.name,
+  containers.image,
+  containers.labels,
+  containers.status,
+  containers.first_seen,
+  containers.last_seen,
+  usage.timestamp,
+  usage.cpu_percentage,
+  usage.memory_total,
+  usage.memory_used,
+  usage.memory_percentage
+FROM
+  containers
+  LEFT JOIN usage ON usage.rowid = (
+    SELECT
+      rowid
+    FROM
+      usage
+    WHERE
+      container = containers.id
+    ORDER BY
+      timestamp DESC
+    LIMIT
+      1
+  )
+WHERE
+  containers.id = :id;
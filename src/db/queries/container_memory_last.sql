@@ -6,7 +6,7 @@ SELECT
 FROM
   usage
 WHERE
-  container LIKE (:container || '%')
+  container = :container
 ORDER BY
   timestamp DESC
 LIMIT
@@ -0,0 +1,16 @@
+SELECT
+  id,
+  id = :container
+  OR name = :container AS exact
+FROM
+  containers
+WHERE
+  id = :container
+  OR name = :container
+  OR substr(id, 1, length(:container)) = :container
+ORDER BY
+  id = :container DESC,
+  name = :container DESC,
+  last_seen DESC
+LIMIT
+  10;
@@ -0,0 +1,15 @@
+SELECT
+  value,
+  timestamp
+FROM
+  custom_metrics
+WHERE
+  series = :series
+  AND container IS :container
+  AND timestamp BETWEEN :from
+  AND :to
+ORDER BY
+  timestamp ASC,
+  rowid ASC
+LIMIT
+  :limit;
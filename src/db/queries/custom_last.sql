@@ -0,0 +1,12 @@
+SELECT
+  value,
+  timestamp
+FROM
+  custom_metrics
+WHERE
+  series = :series
+  AND container IS :container
+ORDER BY
+  timestamp DESC
+LIMIT
+  1;
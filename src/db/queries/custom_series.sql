@@ -0,0 +1,43 @@
+-- name, labels and kind are taken from the newest sample of each series
+SELECT
+  series,
+  name,
+  labels,
+  kind,
+  container,
+  first_seen,
+  last_seen,
+  samples
+FROM
+  (
+    SELECT
+      series,
+      name,
+      labels,
+      kind,
+      container,
+      MIN(timestamp) OVER per_series AS first_seen,
+      MAX(timestamp) OVER per_series AS last_seen,
+      COUNT(*) OVER per_series AS samples,
+      ROW_NUMBER() OVER (
+        PARTITION BY
+          series,
+          container
+        ORDER BY
+          timestamp DESC,
+          rowid DESC
+      ) AS position
+    FROM
+      custom_metrics
+    WINDOW
+      per_series AS (
+        PARTITION BY
+          series,
+          container
+      )
+  )
+WHERE
+  position = 1
+ORDER BY
+  series ASC,
+  container ASC;
@@ -8,4 +8,7 @@ WHERE
   AND timestamp BETWEEN :from
   AND :to
 ORDER BY
-  timestamp ASC;
\ No newline at end of file
+  timestamp ASC,
+  rowid ASC
+LIMIT
+  :limit;
\ No newline at end of file
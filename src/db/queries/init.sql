@@ -18,4 +18,95 @@ CREATE INDEX IF NOT EXISTS idx_timestamp_container_desc ON usage(timestamp DESC,
 
 CREATE INDEX IF NOT EXISTS idx_timestamp_container_asc ON usage(timestamp ASC, container);
 
+CREATE INDEX IF NOT EXISTS idx_container_timestamp ON usage(container, timestamp);
+
+CREATE TABLE IF NOT EXISTS containers (
+    id CHAR(64) PRIMARY KEY,
+    name TEXT,
+    image TEXT,
+    -- JSON object of the docker labels
+    labels TEXT,
+    status TEXT,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_containers_name ON containers(name);
+
+-- state transitions of the alerts, see the alerting rules of the config
+CREATE TABLE IF NOT EXISTS alert_history (
+    rule TEXT NOT NULL,
+    container CHAR(64),
+    state TEXT NOT NULL,
+    value REAL,
+    threshold REAL NOT NULL,
+    timestamp DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_history_timestamp ON alert_history(timestamp);
+
+CREATE TABLE IF NOT EXISTS silences (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- JSON object of the matcher fields
+    matchers TEXT NOT NULL,
+    comment TEXT,
+    starts_at DATETIME NOT NULL,
+    ends_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+-- samples that deviated from the rolling baseline of their series, see the anomaly config
+CREATE TABLE IF NOT EXISTS anomalies (
+    container CHAR(64),
+    metric TEXT NOT NULL,
+    value REAL NOT NULL,
+    expected REAL NOT NULL,
+    score REAL NOT NULL,
+    timestamp DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_anomalies_timestamp ON anomalies(timestamp);
+
+-- notes on the timeline, like OOM kills or deploys
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    container CHAR(64),
+    timestamp DATETIME NOT NULL,
+    -- NULL for a point in time
+    ends_at DATETIME,
+    text TEXT NOT NULL,
+    -- JSON array of strings
+    tags TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_annotations_timestamp ON annotations(timestamp);
+
+-- samples reported by applications, see `POST /ingest`
+CREATE TABLE IF NOT EXISTS custom_metrics (
+    -- name and sorted labels, e.g. orders{region="eu"}
+    series TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- JSON object of the labels
+    labels TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    container CHAR(64),
+    value REAL NOT NULL,
+    timestamp DATETIME NOT NULL,
+    timestamp_5m TEXT GENERATED ALWAYS AS (datetime(strftime('%s', timestamp) / 10 * 10, 'unixepoch')) STORED,
+    timestamp_1h TEXT GENERATED ALWAYS AS (datetime(strftime('%s', timestamp) / 120 * 120, 'unixepoch')) STORED,
+    timestamp_1d TEXT GENERATED ALWAYS AS (datetime(strftime('%s', timestamp) / 2880 * 2880, 'unixepoch')) STORED,
+    timestamp_1w TEXT GENERATED ALWAYS AS (datetime(strftime('%s', timestamp) / 20160 * 20160, 'unixepoch')) STORED,
+    timestamp_30d TEXT GENERATED ALWAYS AS (datetime(strftime('%s', timestamp) / 86400 * 86400, 'unixepoch')) STORED
+);
+
+CREATE INDEX IF NOT EXISTS idx_custom_metrics_series_timestamp ON custom_metrics(series, container, timestamp);
+
+-- databases created before the containers table existed only know the ids from usage
+INSERT OR IGNORE INTO containers (id, first_seen, last_seen)
+SELECT container, MIN(timestamp), MAX(timestamp)
+FROM usage
+WHERE container IS NOT NULL
+  AND NOT EXISTS (SELECT 1 FROM containers)
+GROUP BY container;
+
 COMMIT;
\ No newline at end of file
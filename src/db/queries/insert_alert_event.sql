@@ -0,0 +1,4 @@
+INSERT INTO
+  alert_history (rule, container, state, value, threshold, timestamp)
+VALUES
+  (:rule, :container, :state, :value, :threshold, :timestamp);
@@ -0,0 +1,4 @@
+INSERT INTO
+  annotations (container, timestamp, ends_at, text, tags)
+VALUES
+  (:container, :timestamp, :ends_at, :text, :tags);
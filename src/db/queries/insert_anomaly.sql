@@ -0,0 +1,4 @@
+INSERT INTO
+  anomalies (container, metric, value, expected, score, timestamp)
+VALUES
+  (:container, :metric, :value, :expected, :score, :timestamp);
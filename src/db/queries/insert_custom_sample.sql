@@ -0,0 +1,4 @@
+INSERT INTO
+  custom_metrics (series, name, labels, kind, container, value, timestamp)
+VALUES
+  (:series, :name, :labels, :kind, :container, :value, :timestamp);
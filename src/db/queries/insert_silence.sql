@@ -0,0 +1,4 @@
+INSERT INTO
+  silences (matchers, comment, starts_at, ends_at, created_at)
+VALUES
+  (:matchers, :comment, :starts_at, :ends_at, :created_at);
@@ -0,0 +1,18 @@
+SELECT
+  usage.container,
+  containers.name,
+  containers.image,
+  containers.labels,
+  containers.status,
+  usage.cpu_percentage,
+  usage.memory_total,
+  usage.memory_used,
+  usage.memory_percentage,
+  MAX(usage.timestamp) AS timestamp
+FROM
+  usage
+  LEFT JOIN containers ON containers.id = usage.container
+WHERE
+  usage.timestamp >= :since
+GROUP BY
+  usage.container;
\ No newline at end of file
@@ -0,0 +1,14 @@
+SELECT
+  timestamp,
+  cpu_percentage,
+  memory_total,
+  memory_used,
+  memory_percentage
+FROM
+  usage
+WHERE
+  container IS :container
+ORDER BY
+  timestamp DESC
+LIMIT
+  :limit;
@@ -0,0 +1,13 @@
+SELECT
+  id,
+  matchers,
+  comment,
+  starts_at,
+  ends_at,
+  created_at
+FROM
+  silences
+WHERE
+  ends_at > :ended_after
+ORDER BY
+  starts_at ASC;
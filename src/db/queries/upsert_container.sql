@@ -0,0 +1,16 @@
+INSERT INTO
+  containers (id, name, image, labels, status, first_seen, last_seen)
+VALUES
+  (:id, :name, :image, :labels, :status, :timestamp, :timestamp)
+ON CONFLICT (id) DO UPDATE
+SET
+  name = excluded.name,
+  image = excluded.image,
+  labels = excluded.labels,
+  status = excluded.status,
+  -- stopped containers are listed on every collection too, they were last seen when they stopped
+  last_seen = CASE
+    WHEN excluded.status = 'running'
+    OR containers.status IS NOT excluded.status THEN excluded.last_seen
+    ELSE containers.last_seen
+  END;
\ No newline at end of file
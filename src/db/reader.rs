@@ -0,0 +1,108 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{Connection, OpenFlags, Row, ToSql, named_params};
+use std::{path::PathBuf, time::Duration};
+use tokio::sync::mpsc;
+
+use super::{
+    StorageBackend,
+    manager::{to_cpu_data_point, to_memory_data_point},
+};
+use crate::{
+    config::DatabaseConfig,
+    types::{CpuUsageDataPoint, MemoryUsageDataPoint},
+};
+
+/// Rows read ahead of the consumer of a stream
+const BUFFER: usize = 256;
+
+/// Streams history rows straight from an SQLite cursor on a dedicated read-only connection, so
+/// long exports neither hold up the database task nor collect the whole result in memory.
+#[derive(Debug, Clone)]
+pub struct HistoryReader {
+    /// `None` with the memory backend, which has no database file to read from
+    path: Option<PathBuf>,
+}
+
+impl HistoryReader {
+    pub fn new(config: &DatabaseConfig) -> Self {
+        Self {
+            path: (config.backend == StorageBackend::Sqlite).then(|| config.path.clone()),
+        }
+    }
+
+    /// `None` if the backend can not be streamed from, the database task has to be asked instead
+    pub fn cpu_history(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        container: Option<String>,
+    ) -> Option<mpsc::Receiver<Result<CpuUsageDataPoint>>> {
+        let sql = match container {
+            Some(_) => include_str!("./queries/container_cpu_history.sql"),
+            None => include_str!("./queries/host_cpu_history.sql"),
+        };
+
+        self.stream(sql, from, to, container, to_cpu_data_point)
+    }
+
+    pub fn memory_history(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        container: Option<String>,
+    ) -> Option<mpsc::Receiver<Result<MemoryUsageDataPoint>>> {
+        let sql = match container {
+            Some(_) => include_str!("./queries/container_memory_history.sql"),
+            None => include_str!("./queries/host_memory_history.sql"),
+        };
+
+        self.stream(sql, from, to, container, to_memory_data_point)
+    }
+
+    fn stream<T: Send + 'static>(
+        &self,
+        sql: &'static str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        container: Option<String>,
+        to_row: fn(&Row) -> rusqlite::Result<T>,
+    ) -> Option<mpsc::Receiver<Result<T>>> {
+        let path = self.path.clone()?;
+        let (tx, rx) = mpsc::channel(BUFFER);
+
+        tokio::task::spawn_blocking(move || {
+            let from = from.unwrap_or(Utc.timestamp_opt(0, 0).unwrap());
+            let to = to.unwrap_or(Utc::now());
+
+            let result = (|| -> Result<()> {
+                let connection =
+                    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+                connection.busy_timeout(Duration::from_secs(5))?;
+
+                let mut stmt = connection.prepare(sql)?;
+                let mut params: Vec<(&str, &dyn ToSql)> =
+                    named_params! {":from": from, ":to": to, ":limit": -1}.to_vec();
+                if let Some(container) = &container {
+                    params.push((":container", container));
+                }
+
+                let mut rows = stmt.query(params.as_slice())?;
+                while let Some(row) = rows.next()? {
+                    // the receiver is gone when the client disconnected
+                    if tx.blocking_send(Ok(to_row(row)?)).is_err() {
+                        break;
+                    }
+                }
+
+                Ok(())
+            })();
+
+            if let Err(e) = result {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+
+        Some(rx)
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::types::{
+    Aggregation, Aggregations, AlertEvent, Annotation, AnnotationQuery, Anomaly, AnomalyQuery,
+    ContainerInfo, ContainerOverview, CpuUsage, CpuUsageAggregatePoint, CpuUsageDataPoint,
+    CpuUsageRangePoint, CustomAggregatePoint, CustomDataPoint, CustomRangePoint, CustomSample,
+    CustomSeries, Interval, LatestSample, MemoryUsage, MemoryUsageAggregatePoint,
+    MemoryUsageDataPoint, MemoryUsageRangePoint, Metric, RangeQuery, RankedContainer, Series,
+    SeriesPoints, SeriesQuery, Silence, UsageSample,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// Persistent storage in an SQLite database file
+    Sqlite,
+    /// Ring buffers kept in memory, lost on restart
+    Memory,
+}
+
+/// Outcome of resolving a user supplied container id prefix or name.
+#[derive(Debug)]
+pub enum ContainerMatch {
+    Found(String),
+    NotFound,
+    /// The prefix matches several containers, holds (some of) their ids
+    Ambiguous(Vec<String>),
+}
+
+/// Storage of the collected samples, the database task owns exactly one of these.
+///
+/// A `container` of `None` always refers to the host, `Some` is a full container id as returned
+/// by [`MetricsStore::resolve_container`]. Custom series without a container belong to the host.
+pub trait MetricsStore {
+    fn insert_resource_usage(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        memory_usage: MemoryUsage,
+        cpu_usage: CpuUsage,
+        container: Option<String>,
+    ) -> Result<()>;
+
+    /// `last_seen` only moves while the container is running, or when its status changes, so
+    /// stopped containers keep the time they stopped
+    fn upsert_container(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        container: ContainerInfo,
+    ) -> Result<()>;
+
+    /// Resolves an exact id or name, or an unambiguous id prefix to a full container id.
+    /// Exact matches win over prefixes, a reused name resolves to the most recently seen container.
+    fn resolve_container(&mut self, container: &str) -> Result<ContainerMatch>;
+
+    /// Newest sample of the host and of every container, ignoring samples older than `since`
+    fn get_latest_samples(&mut self, since: DateTime<Utc>) -> Result<Vec<LatestSample>>;
+
+    /// Containers seen at any point between `from` and `to`, most recently seen first
+    fn get_containers(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ContainerOverview>>;
+
+    /// The `limit` containers with the highest `aggregation` of `metric` since `since`
+    fn get_top_containers(
+        &mut self,
+        metric: Metric,
+        aggregation: Aggregation,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<RankedContainer>>;
+
+    /// `id` is a full container id
+    fn get_container(&mut self, id: &str) -> Result<Option<ContainerOverview>>;
+
+    /// The newest `limit` samples of the host or a container, in timestamp order
+    fn get_recent_samples(
+        &mut self,
+        container: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<UsageSample>>;
+
+    fn get_last_cpu_usage(
+        &mut self,
+        container: Option<String>,
+    ) -> Result<Option<CpuUsageDataPoint>>;
+
+    fn get_last_memory_usage(
+        &mut self,
+        container: Option<String>,
+    ) -> Result<Option<MemoryUsageDataPoint>>;
+
+    fn get_interval_cpu_usage(
+        &mut self,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageAggregatePoint>>;
+
+    fn get_interval_memory_usage(
+        &mut self,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageAggregatePoint>>;
+
+    fn get_cpu_usage_history(
+        &mut self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageDataPoint>>;
+
+    fn get_memory_usage_history(
+        &mut self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageDataPoint>>;
+
+    fn get_range_cpu_usage(
+        &mut self,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<CpuUsageRangePoint>>;
+
+    fn get_range_memory_usage(
+        &mut self,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<MemoryUsageRangePoint>>;
+
+    fn insert_alert_event(&mut self, event: AlertEvent) -> Result<()>;
+
+    /// Alert state transitions between `from` and `to`, newest first
+    fn get_alert_history(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<AlertEvent>>;
+
+    /// Stores `silence` under a new id, its `id` is ignored. Returns the stored silence.
+    fn insert_silence(&mut self, silence: Silence) -> Result<Silence>;
+
+    /// Silences ending after `ended_after`, by start time
+    fn get_silences(&mut self, ended_after: DateTime<Utc>) -> Result<Vec<Silence>>;
+
+    /// Whether a silence with this id existed
+    fn delete_silence(&mut self, id: i64) -> Result<bool>;
+
+    fn insert_anomaly(&mut self, anomaly: Anomaly) -> Result<()>;
+
+    /// Anomalies matching `query`, newest first
+    fn get_anomalies(&mut self, query: AnomalyQuery) -> Result<Vec<Anomaly>>;
+
+    /// Stores `annotation` under a new id, its `id` is ignored. Returns the stored annotation.
+    fn insert_annotation(&mut self, annotation: Annotation) -> Result<Annotation>;
+
+    /// Annotations matching `query`, by timestamp
+    fn get_annotations(&mut self, query: AnnotationQuery) -> Result<Vec<Annotation>>;
+
+    /// Whether an annotation with this id existed
+    fn delete_annotation(&mut self, id: i64) -> Result<bool>;
+
+    /// Stores the samples in one go, a batch is written entirely or not at all
+    fn insert_custom_samples(&mut self, samples: Vec<CustomSample>) -> Result<()>;
+
+    /// Every custom series, by series key. Series of different containers are listed separately.
+    fn get_custom_series(&mut self) -> Result<Vec<CustomSeries>>;
+
+    /// `series` is a key as returned by [`CustomSample::series`]
+    fn get_last_custom_metric(
+        &mut self,
+        series: String,
+        container: Option<String>,
+    ) -> Result<Option<CustomDataPoint>>;
+
+    fn get_interval_custom_metric(
+        &mut self,
+        series: String,
+        interval: Interval,
+        aggregations: &Aggregations,
+        container: Option<String>,
+    ) -> Result<Vec<CustomAggregatePoint>>;
+
+    fn get_custom_metric_history(
+        &mut self,
+        series: String,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+        container: Option<String>,
+    ) -> Result<Vec<CustomDataPoint>>;
+
+    fn get_range_custom_metric(
+        &mut self,
+        series: String,
+        range: RangeQuery,
+        container: Option<String>,
+    ) -> Result<Vec<CustomRangePoint>>;
+
+    /// Runs several range queries at once, the series are returned in the order of `queries`
+    fn get_range_series(&mut self, queries: Vec<SeriesQuery>) -> Result<Vec<Series>> {
+        queries
+            .into_iter()
+            .map(|query| {
+                let points = match query.metric {
+                    Metric::Cpu => SeriesPoints::Cpu(
+                        self.get_range_cpu_usage(query.range, query.container.clone())?,
+                    ),
+                    Metric::Memory => SeriesPoints::Memory(
+                        self.get_range_memory_usage(query.range, query.container.clone())?,
+                    ),
+                };
+
+                Ok(Series {
+                    container: query.container,
+                    metric: query.metric,
+                    aggregation: query.range.aggregation,
+                    points,
+                })
+            })
+            .collect()
+    }
+}
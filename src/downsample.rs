@@ -0,0 +1,121 @@
+/// Largest-triangle-three-buckets downsampling of `points` to at most `threshold` points.
+///
+/// The first and last points are always kept, every bucket in between is represented by the
+/// point forming the largest triangle with the previously selected point and the average of the
+/// next bucket, which keeps peaks and the overall shape of the series. `points` have to be
+/// ordered by `x`.
+pub fn lttb<T>(
+    mut points: Vec<T>,
+    threshold: usize,
+    x: impl Fn(&T) -> f64,
+    y: impl Fn(&T) -> f64,
+) -> Vec<T> {
+    if threshold < 3 || points.len() <= threshold {
+        return points;
+    }
+
+    let len = points.len();
+    let bucket_size = (len - 2) as f64 / (threshold - 2) as f64;
+    let bucket_bounds = |bucket: usize| {
+        let start = (bucket as f64 * bucket_size) as usize + 1;
+        let end = (((bucket + 1) as f64 * bucket_size) as usize + 1).min(len - 1);
+        start..end
+    };
+
+    let mut selected = Vec::with_capacity(threshold);
+    selected.push(0);
+
+    for bucket in 0..threshold - 2 {
+        // average of the next bucket, the last point for the last bucket
+        let next = bucket_bounds(bucket + 1);
+        let (next_x, next_y) = if next.is_empty() {
+            (x(&points[len - 1]), y(&points[len - 1]))
+        } else {
+            let count = next.len() as f64;
+            let (sum_x, sum_y) = points[next]
+                .iter()
+                .fold((0.0, 0.0), |(sx, sy), point| (sx + x(point), sy + y(point)));
+            (sum_x / count, sum_y / count)
+        };
+
+        let previous = &points[*selected.last().unwrap()];
+        let (previous_x, previous_y) = (x(previous), y(previous));
+
+        let best = bucket_bounds(bucket)
+            .max_by(|&a, &b| {
+                let area = |i: usize| {
+                    ((previous_x - next_x) * (y(&points[i]) - previous_y)
+                        - (previous_x - x(&points[i])) * (next_y - previous_y))
+                        .abs()
+                };
+                area(a).total_cmp(&area(b))
+            })
+            .unwrap();
+        selected.push(best);
+    }
+
+    selected.push(len - 1);
+
+    // indices are increasing, so the selected points can be moved out in a single pass
+    let mut selected = selected.into_iter().peekable();
+    let mut index = 0;
+    points.retain(|_| {
+        let keep = selected.next_if_eq(&index).is_some();
+        index += 1;
+        keep
+    });
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn downsample(points: Vec<(f64, f64)>, threshold: usize) -> Vec<(f64, f64)> {
+        lttb(points, threshold, |p| p.0, |p| p.1)
+    }
+
+    fn series(len: usize) -> Vec<(f64, f64)> {
+        (0..len)
+            .map(|i| (i as f64, (i as f64 / 5.0).sin()))
+            .collect()
+    }
+
+    #[test]
+    fn short_series_are_kept() {
+        assert_eq!(downsample(series(10), 10), series(10));
+        assert_eq!(downsample(series(10), 2), series(10));
+    }
+
+    #[test]
+    fn endpoints_are_kept() {
+        let points = downsample(series(1000), 50);
+
+        assert_eq!(points.len(), 50);
+        assert_eq!(points.first(), Some(&(0.0, 0.0)));
+        assert_eq!(points.last(), series(1000).last());
+    }
+
+    #[test]
+    fn every_threshold_is_reached() {
+        for threshold in 3..=40 {
+            let points = downsample(series(97), threshold);
+
+            assert_eq!(points.len(), threshold);
+            assert!(points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+    }
+
+    #[test]
+    fn peaks_are_kept() {
+        let mut points: Vec<_> = (0..100).map(|i| (i as f64, 0.0)).collect();
+        points[42].1 = 100.0;
+        points[77].1 = -50.0;
+
+        let points = downsample(points, 10);
+
+        assert!(points.contains(&(42.0, 100.0)));
+        assert!(points.contains(&(77.0, -50.0)));
+    }
+}
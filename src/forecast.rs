@@ -0,0 +1,297 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::types::MemoryUsageDataPoint;
+
+/// Theil-Sen compares every pair of points, longer windows are thinned out to this many points
+const MAX_ROBUST_POINTS: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForecastMethod {
+    /// Ordinary least squares
+    Linear,
+    /// Theil-Sen estimator, the median slope between all pairs of points. Short spikes like a
+    /// garbage collection barely move it.
+    #[default]
+    Robust,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// Projection of the memory usage of a series from the trend of its recent samples
+#[derive(Debug, Serialize)]
+pub struct MemoryForecast {
+    pub method: ForecastMethod,
+    /// First and last sample the trend is fitted to
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub samples: usize,
+    pub memory_used: u64,
+    /// Limit of the latest sample, the memory of the host for the host itself
+    pub memory_total: u64,
+    /// Growth of the used memory, negative when it shrinks
+    pub bytes_per_hour: f64,
+    /// Share of the variance of the samples explained by the trend, between 0 and 1
+    pub r_squared: f64,
+    pub confidence: Confidence,
+    /// When the trend reaches `memory_total`, `None` unless the usage grows
+    pub exhaustion_at: Option<DateTime<Utc>>,
+    pub hours_until_exhaustion: Option<f64>,
+}
+
+/// `points` have to be ordered by timestamp. `None` with fewer than two points or when all
+/// of them share a timestamp.
+pub fn memory(points: &[MemoryUsageDataPoint], method: ForecastMethod) -> Option<MemoryForecast> {
+    let (first, last) = (points.first()?, points.last()?);
+
+    // hours relative to the newest sample keep the numbers small
+    let hours = |timestamp: DateTime<Utc>| (timestamp - last.timestamp).as_seconds_f64() / 3600.0;
+    let xy: Vec<(f64, f64)> = points
+        .iter()
+        .map(|point| (hours(point.timestamp), point.used as f64))
+        .collect();
+
+    let (slope, intercept) = match method {
+        ForecastMethod::Linear => least_squares(&xy)?,
+        ForecastMethod::Robust => theil_sen(&xy)?,
+    };
+    let r_squared = r_squared(&xy, slope, intercept);
+
+    // `intercept` is the trend at the newest sample
+    let remaining = last.total as f64 - intercept;
+    let hours_until_exhaustion = match remaining {
+        remaining if remaining <= 0.0 => Some(0.0),
+        remaining if slope > 0.0 => Some(remaining / slope),
+        _ => None,
+    };
+    let exhaustion_at = hours_until_exhaustion.and_then(|hours| {
+        Duration::try_milliseconds((hours * 3_600_000.0) as i64)
+            .and_then(|until| last.timestamp.checked_add_signed(until))
+    });
+
+    let span = last.timestamp - first.timestamp;
+    let confidence = if r_squared >= 0.8 && points.len() >= 30 && span >= Duration::hours(1) {
+        Confidence::High
+    } else if r_squared >= 0.5 && points.len() >= 10 {
+        Confidence::Medium
+    } else {
+        Confidence::Low
+    };
+
+    Some(MemoryForecast {
+        method,
+        from: first.timestamp,
+        to: last.timestamp,
+        samples: points.len(),
+        memory_used: last.used,
+        memory_total: last.total,
+        bytes_per_hour: slope,
+        r_squared,
+        confidence,
+        exhaustion_at,
+        hours_until_exhaustion,
+    })
+}
+
+/// Slope and intercept of the least squares line
+fn least_squares(xy: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let count = xy.len() as f64;
+    let mean_x = xy.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = xy.iter().map(|(_, y)| y).sum::<f64>() / count;
+
+    let (covariance, variance) = xy.iter().fold((0.0, 0.0), |(cov, var), (x, y)| {
+        (
+            cov + (x - mean_x) * (y - mean_y),
+            var + (x - mean_x).powi(2),
+        )
+    });
+    if variance == 0.0 {
+        return None;
+    }
+
+    let slope = covariance / variance;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+/// Median of the pairwise slopes, the intercept is the median of the residuals
+fn theil_sen(xy: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let stride = xy.len().div_ceil(MAX_ROBUST_POINTS).max(1);
+    let xy: Vec<_> = xy.iter().step_by(stride).copied().collect();
+
+    let mut slopes = vec![];
+    for (i, (x1, y1)) in xy.iter().enumerate() {
+        for (x2, y2) in &xy[i + 1..] {
+            if x2 != x1 {
+                slopes.push((y2 - y1) / (x2 - x1));
+            }
+        }
+    }
+    let slope = median(&mut slopes)?;
+
+    let mut intercepts: Vec<_> = xy.iter().map(|(x, y)| y - slope * x).collect();
+    Some((slope, median(&mut intercepts)?))
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    Some(match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    })
+}
+
+/// Clamped to 0 for fits worse than the mean, which the robust line can be
+fn r_squared(xy: &[(f64, f64)], slope: f64, intercept: f64) -> f64 {
+    let mean_y = xy.iter().map(|(_, y)| y).sum::<f64>() / xy.len() as f64;
+    let total: f64 = xy.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+    let residual: f64 = xy
+        .iter()
+        .map(|(x, y)| (y - (slope * x + intercept)).powi(2))
+        .sum();
+
+    // a flat series is explained perfectly by a flat line
+    if total == 0.0 {
+        return 1.0;
+    }
+
+    (1.0 - residual / total).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: f64 = 1e9;
+
+    /// Samples every 10 minutes over `hours`, `used` gets the hours since the first sample
+    fn series(hours: i64, used: impl Fn(f64) -> f64) -> Vec<MemoryUsageDataPoint> {
+        (0..=hours * 6)
+            .map(|i| {
+                let used = used(i as f64 / 6.0);
+                MemoryUsageDataPoint {
+                    timestamp: DateTime::from_timestamp(1_700_000_000 + i * 600, 0).unwrap(),
+                    total: (4.0 * GB) as u64,
+                    used: used as u64,
+                    percentage: used / (4.0 * GB) * 100.0,
+                }
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= expected.abs() * 1e-9 + 1e-6,
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn perfect_lines_are_fitted_exactly() {
+        // 2.5 of 4 GB after 10 hours, growing by 0.15 GB per hour
+        let points = series(10, |hours| GB + 0.15 * GB * hours);
+
+        for method in [ForecastMethod::Linear, ForecastMethod::Robust] {
+            let forecast = memory(&points, method).unwrap();
+
+            assert_close(forecast.bytes_per_hour, 0.15 * GB);
+            assert_close(forecast.r_squared, 1.0);
+            assert_eq!(forecast.confidence, Confidence::High);
+            assert_eq!(forecast.memory_used, (2.5 * GB) as u64);
+            assert_close(forecast.hours_until_exhaustion.unwrap(), 10.0);
+            assert_eq!(
+                forecast.exhaustion_at,
+                Some(forecast.to + Duration::hours(10))
+            );
+        }
+    }
+
+    #[test]
+    fn robust_trends_ignore_spikes() {
+        let points: Vec<_> = series(10, |hours| GB + 0.1 * GB * hours)
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut point)| {
+                // e.g. a burst of allocations shortly before the newest sample
+                if (50..54).contains(&i) {
+                    point.used += (1.5 * GB) as u64;
+                }
+                point
+            })
+            .collect();
+
+        let robust = memory(&points, ForecastMethod::Robust).unwrap();
+        assert_close(robust.bytes_per_hour, 0.1 * GB);
+        assert_close(robust.hours_until_exhaustion.unwrap(), 20.0);
+
+        let linear = memory(&points, ForecastMethod::Linear).unwrap();
+        assert!((linear.bytes_per_hour - 0.1 * GB).abs() > 1e6);
+    }
+
+    #[test]
+    fn flat_and_shrinking_series_never_run_out() {
+        for used in [|_| 2.0 * GB, |hours| 3.0 * GB - 0.1 * GB * hours] {
+            for method in [ForecastMethod::Linear, ForecastMethod::Robust] {
+                let forecast = memory(&series(5, used), method).unwrap();
+
+                assert!(forecast.bytes_per_hour <= 0.0);
+                assert_eq!(forecast.hours_until_exhaustion, None);
+                assert_eq!(forecast.exhaustion_at, None);
+            }
+        }
+    }
+
+    #[test]
+    fn exhausted_memory_runs_out_now() {
+        let forecast = memory(&series(1, |_| 4.0 * GB), ForecastMethod::Robust).unwrap();
+
+        assert_eq!(forecast.hours_until_exhaustion, Some(0.0));
+        assert_eq!(forecast.exhaustion_at, Some(forecast.to));
+    }
+
+    #[test]
+    fn a_single_timestamp_has_no_trend() {
+        let mut points = series(0, |_| GB);
+        assert_eq!(points.len(), 1);
+
+        for method in [ForecastMethod::Linear, ForecastMethod::Robust] {
+            assert!(memory(&points, method).is_none());
+            assert!(memory(&[], method).is_none());
+        }
+
+        points.push(MemoryUsageDataPoint {
+            timestamp: points[0].timestamp,
+            total: points[0].total,
+            used: (2.0 * GB) as u64,
+            percentage: 50.0,
+        });
+        for method in [ForecastMethod::Linear, ForecastMethod::Robust] {
+            assert!(memory(&points, method).is_none());
+        }
+    }
+
+    #[test]
+    fn long_windows_are_thinned_out() {
+        // every other point is far off the line, thinning to every second point skips them
+        let xy: Vec<_> = (0..MAX_ROBUST_POINTS * 2)
+            .map(|i| {
+                let x = i as f64;
+                (x, if i % 2 == 0 { 3.0 * x + 7.0 } else { 1e12 })
+            })
+            .collect();
+
+        assert_eq!(theil_sen(&xy), Some((3.0, 7.0)));
+        assert_ne!(theil_sen(&xy[1..]), Some((3.0, 7.0)));
+    }
+}
@@ -0,0 +1,27 @@
+use tokio::sync::broadcast;
+
+use crate::types::UsageSample;
+
+/// Fans out every collected sample to the live stream subscribers.
+#[derive(Clone)]
+pub struct SampleHub {
+    tx: broadcast::Sender<UsageSample>,
+}
+
+impl SampleHub {
+    /// `capacity` is the number of samples a slow subscriber may lag behind before missing some
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+
+        Self { tx }
+    }
+
+    pub fn publish(&self, sample: UsageSample) {
+        // an error only means that nobody is listening right now
+        let _ = self.tx.send(sample);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<UsageSample> {
+        self.tx.subscribe()
+    }
+}
@@ -1,16 +1,127 @@
+use anyhow::anyhow;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use db::StorageBackend;
+use types::TimeSpec;
+
+mod alerting;
 mod api;
+mod archive;
+mod config;
 mod db;
+mod downsample;
+mod forecast;
+mod hub;
+mod notifier;
+mod scrape;
+mod statsd;
 mod types;
 mod usage_collector;
 
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Collects samples and serves the API, the default
+    Serve,
+    /// Writes a time range of the database to Parquet files partitioned by day and container
+    Export {
+        /// RFC 3339 timestamp or a time relative to now, e.g. `-7d`
+        #[arg(long, allow_hyphen_values = true)]
+        from: TimeSpec,
+        #[arg(long, allow_hyphen_values = true, default_value = "now")]
+        to: TimeSpec,
+        /// Defaults to the archive directory of the config
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Sends a test notification to every webhook, e.g. to try them against a local HTTP server
+    NotifyTest,
+    /// Loads Parquet files written by `export` into the database
+    Import {
+        /// Files or directories containing them
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    let (db_tx, db_rx) = db::create_command_channel();
-    let db_handle = db::start(db_rx);
-    let api_future = api::start(db_tx.clone());
-    let usage_collector_future = usage_collector::start(db_tx);
+    let cli = Cli::parse();
+    let config = config::Config::load()?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Export { from, to, out } => {
+            if config.database.backend != StorageBackend::Sqlite {
+                return Err(anyhow!("Exporting is only supported by the sqlite backend"));
+            }
+
+            let now = Utc::now();
+            let out = out.unwrap_or(config.archive.directory);
+            let files = archive::export(
+                &out,
+                &config.database.path,
+                from.resolve(now).map_err(|e| anyhow!(e))?,
+                to.resolve(now).map_err(|e| anyhow!(e))?,
+            )?;
+            println!("Exported {files} files to {out:?}");
+
+            Ok(())
+        }
+        Command::NotifyTest => notifier::send_test(&config.notifier).await,
+        Command::Import { paths } => {
+            let samples = archive::import(&config.database.path, &paths)?;
+            println!("Imported {samples} samples into {:?}", config.database.path);
+
+            Ok(())
+        }
+    }
+}
+
+async fn serve(config: config::Config) -> anyhow::Result<()> {
+    let (db_tx, db_rx) = db::create_command_channel(
+        config.database.queue_capacity,
+        config.database.queue_overflow,
+    );
+    let hub = hub::SampleHub::new(config.stream.buffer);
+    let alerts = alerting::Alerts::default();
+    let silences = alerting::Silences::new(config.alerting.maintenance_windows.clone());
+    let (notifier, notifier_rx) = notifier::create_channel(silences.clone());
+
+    let db_handle = db::start(&config.database, db_rx);
+    silences.reload(&db_tx).await?;
+    let api_future = api::start(
+        &config,
+        db_tx.clone(),
+        hub.clone(),
+        alerts.clone(),
+        silences.clone(),
+    );
+    let anomaly_future =
+        alerting::detect_anomalies(&config.alerting.anomaly, db_tx.clone(), hub.clone());
+    let alerting_future = alerting::start(
+        &config.alerting,
+        db_tx.clone(),
+        alerts,
+        silences,
+        notifier.clone(),
+    );
+    let notifier_future = notifier::start(&config.notifier, notifier_rx);
+    let events_future = usage_collector::watch_events(db_tx.clone(), notifier);
+    let statsd_future = statsd::start(&config.statsd, db_tx.clone());
+    let scrape_future = scrape::start(&config.scrape, db_tx.clone());
+    let usage_collector_future = usage_collector::start(db_tx, hub);
+    let archive_future = archive::start(&config);
 
     tokio::select! {
         Ok(Err(e)) = db_handle => {
@@ -22,6 +133,27 @@ async fn main() -> anyhow::Result<()> {
         Err(e) = usage_collector_future => {
             log::error!("Error in usage collector process: {e}");
         }
+        Err(e) = archive_future => {
+            log::error!("Error in archive process: {e}");
+        }
+        Err(e) = alerting_future => {
+            log::error!("Error in alerting process: {e}");
+        }
+        Err(e) = anomaly_future => {
+            log::error!("Error in anomaly detection process: {e}");
+        }
+        Err(e) = events_future => {
+            log::error!("Error in docker events process: {e}");
+        }
+        Err(e) = notifier_future => {
+            log::error!("Error in notifier process: {e}");
+        }
+        Err(e) = statsd_future => {
+            log::error!("Error in StatsD listener: {e}");
+        }
+        Err(e) = scrape_future => {
+            log::error!("Error in scrape process: {e}");
+        }
         _ = tokio::signal::ctrl_c() => {
             log::info!("Received Ctrl+C, shutting down gracefully...");
         }
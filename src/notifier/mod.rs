@@ -0,0 +1,352 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
+
+use crate::{
+    alerting::Silences,
+    config::NotifierConfig,
+    types::{AlertEvent, AlertState, ContainerEvent, ContainerEventKind},
+};
+use webhook::Webhook;
+pub use webhook::WebhookConfig;
+
+mod webhook;
+
+/// Notifications waiting to be grouped, more are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Notification {
+    Alert(AlertEvent),
+    Container(ContainerEvent),
+    /// Sent by `sentinel-rs notify-test` to check the webhooks
+    Test {
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl Notification {
+    /// Notifications of the same group arriving close to each other are sent together
+    fn group_key(&self) -> String {
+        match self {
+            Notification::Alert(event) => format!("alert:{}", event.rule),
+            Notification::Container(_) => "container".to_string(),
+            Notification::Test { .. } => "test".to_string(),
+        }
+    }
+
+    /// Notifications with the same key are only sent once per dedup window, unless they change
+    /// the state of an alert
+    fn dedup_key(&self) -> String {
+        match self {
+            Notification::Alert(event) => format!(
+                "alert:{}:{}",
+                event.rule,
+                event.container.as_deref().unwrap_or("host")
+            ),
+            Notification::Container(event) => {
+                format!("container:{}:{:?}", event.container, event.event)
+            }
+            Notification::Test { timestamp } => format!("test:{timestamp}"),
+        }
+    }
+
+    fn alert_state(&self) -> Option<AlertState> {
+        match self {
+            Notification::Alert(event) => Some(event.state),
+            _ => None,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match self {
+            Notification::Alert(event) => {
+                let target = event.container.as_deref().unwrap_or("host");
+                let state = match event.state {
+                    AlertState::Firing => "is firing",
+                    AlertState::Resolved => "resolved",
+                    AlertState::Pending => "is pending",
+                    AlertState::Inactive => "is inactive",
+                };
+                match event.value {
+                    Some(value) => format!(
+                        "Alert '{}' {state} for {target}, value {value:.1}, threshold {}",
+                        event.rule, event.threshold
+                    ),
+                    None => format!("Alert '{}' {state} for {target}, no data", event.rule),
+                }
+            }
+            Notification::Container(event) => match event.event {
+                ContainerEventKind::Died => format!(
+                    "Container '{}' ({}) died with exit code {}",
+                    event.name,
+                    event.image,
+                    event.exit_code.unwrap_or_default()
+                ),
+                ContainerEventKind::OomKilled => format!(
+                    "Container '{}' ({}) was killed by the OOM killer",
+                    event.name, event.image
+                ),
+            },
+            Notification::Test { .. } => "Test notification from sentinel".to_string(),
+        }
+    }
+}
+
+/// Hands notifications to the notifier task, cheap to clone.
+#[derive(Clone)]
+pub struct Notifier {
+    tx: mpsc::Sender<Notification>,
+    silences: Silences,
+}
+
+impl Notifier {
+    /// Never waits, notifications are dropped when the notifier falls behind or is disabled
+    pub fn notify(&self, notification: Notification) {
+        // alerts are checked by the rule evaluation, which knows their metric and container
+        if let Notification::Container(event) = &notification
+            && self
+                .silences
+                .is_silenced(Some(&event.info()), None, None, event.timestamp)
+        {
+            log::debug!("Dropping silenced notification: {}", notification.summary());
+            return;
+        }
+
+        if let Err(TrySendError::Full(notification)) = self.tx.try_send(notification) {
+            log::warn!(
+                "Notification queue is full, dropping: {}",
+                notification.summary()
+            );
+        }
+    }
+}
+
+pub fn create_channel(silences: Silences) -> (Notifier, mpsc::Receiver<Notification>) {
+    let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+
+    (Notifier { tx, silences }, rx)
+}
+
+struct Group {
+    deadline: Instant,
+    notifications: Vec<Notification>,
+}
+
+/// Groups and deduplicates the notifications, then sends every group to all webhooks.
+/// Never returns when no webhook is configured.
+pub async fn start(config: &NotifierConfig, rx: mpsc::Receiver<Notification>) -> Result<()> {
+    if config.webhooks.is_empty() {
+        // closes the channel, notifying becomes a no-op
+        drop(rx);
+        return std::future::pending().await;
+    }
+
+    let webhooks = webhooks(config)?;
+    let group_wait = config.group_wait.to_duration().to_std()?;
+    let dedup_window = config.dedup_window.to_duration().to_std()?;
+
+    group(rx, group_wait, dedup_window, |key, notifications| {
+        dispatch(&webhooks, key, notifications)
+    })
+    .await;
+
+    Ok(())
+}
+
+/// Hands every group to `send` once its wait is over, returns when the channel is closed
+async fn group(
+    mut rx: mpsc::Receiver<Notification>,
+    group_wait: Duration,
+    dedup_window: Duration,
+    mut send: impl FnMut(String, Vec<Notification>),
+) {
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    // the alert state and time of the last notification sent per dedup key
+    let mut sent: HashMap<String, (Option<AlertState>, Instant)> = HashMap::new();
+
+    loop {
+        let deadline = groups.values().map(|group| group.deadline).min();
+        let due = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            notification = rx.recv() => {
+                let Some(notification) = notification else {
+                    return;
+                };
+
+                let now = Instant::now();
+                let key = notification.dedup_key();
+                let state = notification.alert_state();
+                sent.retain(|_, (_, at)| now.duration_since(*at) < dedup_window);
+                if sent.get(&key).is_some_and(|(last, _)| *last == state) {
+                    log::debug!("Dropping duplicate notification: {}", notification.summary());
+                    continue;
+                }
+                sent.insert(key, (state, now));
+
+                groups
+                    .entry(notification.group_key())
+                    .or_insert_with(|| Group {
+                        deadline: now + group_wait,
+                        notifications: vec![],
+                    })
+                    .notifications
+                    .push(notification);
+            }
+            _ = due => {
+                let now = Instant::now();
+                let keys: Vec<_> = groups
+                    .iter()
+                    .filter(|(_, group)| group.deadline <= now)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+
+                for key in keys {
+                    if let Some(group) = groups.remove(&key) {
+                        send(key, group.notifications);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sends a single test notification to every webhook and waits for the deliveries
+pub async fn send_test(config: &NotifierConfig) -> Result<()> {
+    let notifications = vec![Notification::Test {
+        timestamp: Utc::now(),
+    }];
+
+    for webhook in webhooks(config)? {
+        webhook.send("test", &notifications).await;
+    }
+
+    Ok(())
+}
+
+fn webhooks(config: &NotifierConfig) -> Result<Vec<Arc<Webhook>>> {
+    config
+        .webhooks
+        .iter()
+        .map(|webhook| Ok(Arc::new(Webhook::new(webhook)?)))
+        .collect()
+}
+
+/// Every webhook is sent to on its own, a slow or failing one does not hold up the others
+fn dispatch(webhooks: &[Arc<Webhook>], group: String, notifications: Vec<Notification>) {
+    let notifications = Arc::new(notifications);
+
+    for webhook in webhooks {
+        let webhook = webhook.clone();
+        let group = group.clone();
+        let notifications = notifications.clone();
+
+        tokio::spawn(async move { webhook.send(&group, &notifications).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webhook::tests::died;
+
+    const GROUP_WAIT: Duration = Duration::from_secs(1);
+
+    fn alert(rule: &str, container: &str, state: AlertState) -> Notification {
+        Notification::Alert(AlertEvent {
+            rule: rule.to_string(),
+            container: Some(container.to_string()),
+            state,
+            value: Some(95.0),
+            threshold: 90.0,
+            timestamp: Utc::now(),
+        })
+    }
+
+    /// Runs the grouping on `notifications`, returns the sent groups as `(key, summaries)`
+    async fn groups(notifications: Vec<Notification>) -> Vec<(String, Vec<String>)> {
+        let (notifier, rx) = create_channel(Silences::new(vec![]));
+        let (sent_tx, mut sent_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(group(
+            rx,
+            GROUP_WAIT,
+            Duration::from_secs(300),
+            move |key, group| {
+                let summaries = group.iter().map(Notification::summary).collect();
+                sent_tx.send((key, summaries)).unwrap();
+            },
+        ));
+
+        for notification in notifications {
+            notifier.notify(notification);
+        }
+        // lets the grouping take the notifications before the clock moves
+        tokio::task::yield_now().await;
+        tokio::time::advance(GROUP_WAIT - Duration::from_millis(1)).await;
+        tokio::task::yield_now().await;
+        assert!(sent_rx.try_recv().is_err(), "sent before the group wait");
+        tokio::time::advance(Duration::from_millis(1)).await;
+        tokio::task::yield_now().await;
+
+        drop(notifier);
+        task.await.unwrap();
+
+        let mut groups = vec![];
+        while let Ok(group) = sent_rx.try_recv() {
+            groups.push(group);
+        }
+        groups.sort();
+        groups
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn groups_and_deduplicates_notifications() {
+        let groups = groups(vec![
+            alert("cpu", "a", AlertState::Firing),
+            alert("cpu", "b", AlertState::Firing),
+            alert("cpu", "a", AlertState::Firing),
+            alert("memory", "a", AlertState::Firing),
+            died("web"),
+            died("web"),
+        ])
+        .await;
+
+        let counts: Vec<_> = groups
+            .iter()
+            .map(|(key, summaries)| (key.as_str(), summaries.len()))
+            .collect();
+        assert_eq!(
+            counts,
+            [("alert:cpu", 2), ("alert:memory", 1), ("container", 1)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn state_changes_of_flapping_alerts_are_sent() {
+        let groups = groups(vec![
+            alert("cpu", "a", AlertState::Firing),
+            alert("cpu", "a", AlertState::Resolved),
+            alert("cpu", "a", AlertState::Firing),
+            alert("cpu", "a", AlertState::Firing),
+        ])
+        .await;
+
+        let summaries = &groups[0].1;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(summaries.len(), 3);
+        assert!(summaries[1].contains("resolved"));
+        assert!(summaries[2].contains("is firing"));
+    }
+}
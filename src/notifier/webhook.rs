@@ -0,0 +1,338 @@
+use anyhow::{Context, Result, anyhow};
+use reqwest::{
+    Client, StatusCode, Url,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::{collections::BTreeMap, time::Duration};
+
+use super::Notification;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// An HTTP endpoint every notification group is POSTed to as JSON:
+///
+/// ```toml
+/// [[notifier.webhooks]]
+/// url = "https://chatops.example.com/hooks/sentinel"
+/// headers = { Authorization = "Bearer secret" }
+/// body = { text = "{{summary}}", alerts = "{{notifications}}" }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Template of the JSON body. Strings may contain the placeholders `{{group}}`, `{{count}}`,
+    /// `{{summary}}` and `{{notifications}}`, a string consisting of a single placeholder is
+    /// replaced by its JSON value. Defaults to an object with all of them.
+    pub body: Option<Value>,
+    /// Failed deliveries are retried with exponential backoff this many times
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+#[derive(Debug)]
+pub struct Webhook {
+    client: Client,
+    url: Url,
+    headers: HeaderMap,
+    body: Option<Value>,
+    max_retries: u32,
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookConfig) -> Result<Self> {
+        let url = Url::parse(&config.url)
+            .with_context(|| format!("Invalid webhook url '{}'", config.url))?;
+
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                Ok((
+                    HeaderName::try_from(name.as_str())
+                        .map_err(|_| anyhow!("Invalid webhook header name '{name}'"))?,
+                    HeaderValue::try_from(value.as_str())
+                        .map_err(|_| anyhow!("Invalid value of webhook header '{name}'"))?,
+                ))
+            })
+            .collect::<Result<HeaderMap>>()?;
+
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            url,
+            headers,
+            body: config.body.clone(),
+            max_retries: config.max_retries,
+        })
+    }
+
+    /// Delivers the group, gives up after the last retry or on a client error response
+    pub async fn send(&self, group: &str, notifications: &[Notification]) {
+        let body = self.render(group, notifications);
+        // logged instead of the url, which often contains a token
+        let host = self.url.host_str().unwrap_or_default();
+        let mut backoff = INITIAL_BACKOFF;
+
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+
+            let response = self
+                .client
+                .post(self.url.clone())
+                .headers(self.headers.clone())
+                .json(&body)
+                .send()
+                .await;
+
+            match response {
+                Ok(response) if response.status().is_success() => {
+                    log::info!(
+                        "Sent {} notifications to webhook {host}",
+                        notifications.len()
+                    );
+                    return;
+                }
+                Ok(response) if !is_retryable(response.status()) => {
+                    log::error!(
+                        "Webhook {host} rejected the notifications with {}",
+                        response.status()
+                    );
+                    return;
+                }
+                Ok(response) => {
+                    log::warn!("Webhook {host} responded with {}", response.status());
+                }
+                Err(e) => log::warn!("Failed to reach webhook {host}: {e}"),
+            }
+        }
+
+        log::error!(
+            "Giving up on webhook {host} after {} attempts",
+            self.max_retries + 1
+        );
+    }
+
+    fn render(&self, group: &str, notifications: &[Notification]) -> Value {
+        let summary: Vec<_> = notifications.iter().map(Notification::summary).collect();
+        let variables = json!({
+            "group": group,
+            "count": notifications.len(),
+            "summary": summary.join("\n"),
+            "notifications": notifications,
+        });
+
+        match (&self.body, variables) {
+            (Some(template), Value::Object(variables)) => render(template, &variables),
+            (_, variables) => variables,
+        }
+    }
+}
+
+/// Timeouts and rate limits are worth retrying, other client errors are not
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn render(template: &Value, variables: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(text) => {
+            let placeholder = text
+                .strip_prefix("{{")
+                .and_then(|text| text.strip_suffix("}}"))
+                .and_then(|name| variables.get(name.trim()));
+            if let Some(value) = placeholder {
+                return value.clone();
+            }
+
+            let mut text = text.clone();
+            for (name, value) in variables {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                text = text.replace(&format!("{{{{{name}}}}}"), &value);
+            }
+
+            Value::String(text)
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render(value, variables))
+                .collect(),
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), render(value, variables)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::types::{ContainerEvent, ContainerEventKind};
+    use axum::{Json, Router, extract::State, routing::post};
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    /// Webhook receiver answering with the queued statuses, then with 200
+    #[derive(Default)]
+    struct Receiver {
+        statuses: Mutex<VecDeque<StatusCode>>,
+        requests: Mutex<Vec<(HeaderMap, Value)>>,
+    }
+
+    impl Receiver {
+        fn bodies(&self) -> Vec<Value> {
+            let requests = self.requests.lock().unwrap();
+            requests.iter().map(|(_, body)| body.clone()).collect()
+        }
+    }
+
+    async fn receive(
+        State(receiver): State<Arc<Receiver>>,
+        headers: HeaderMap,
+        Json(body): Json<Value>,
+    ) -> StatusCode {
+        receiver.requests.lock().unwrap().push((headers, body));
+        let status = receiver.statuses.lock().unwrap().pop_front();
+        status.unwrap_or(StatusCode::OK)
+    }
+
+    /// Starts a receiver on a free local port, returns its url
+    async fn serve(statuses: &[StatusCode]) -> (String, Arc<Receiver>) {
+        let receiver = Arc::new(Receiver::default());
+        receiver.statuses.lock().unwrap().extend(statuses);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (url, receiver)
+    }
+
+    fn config(url: String, body: Option<Value>, max_retries: u32) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            headers: [("Authorization".to_string(), "Bearer secret".to_string())].into(),
+            body,
+            max_retries,
+        }
+    }
+
+    pub fn died(name: &str) -> Notification {
+        Notification::Container(ContainerEvent {
+            container: format!("{name}-id"),
+            name: name.to_string(),
+            image: "nginx".to_string(),
+            event: ContainerEventKind::Died,
+            exit_code: Some(137),
+            labels: Default::default(),
+            timestamp: chrono::DateTime::from_timestamp(0, 0).unwrap(),
+        })
+    }
+
+    #[tokio::test]
+    async fn delivers_the_default_body() {
+        let (url, receiver) = serve(&[]).await;
+        let webhook = Webhook::new(&config(url, None, 0)).unwrap();
+
+        webhook.send("container", &[died("web")]).await;
+
+        let requests = receiver.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let (headers, body) = &requests[0];
+        assert_eq!(headers["authorization"], "Bearer secret");
+        assert_eq!(body["group"], "container");
+        assert_eq!(body["count"], 1);
+        assert_eq!(
+            body["summary"],
+            "Container 'web' (nginx) died with exit code 137"
+        );
+        assert_eq!(body["notifications"][0]["kind"], "container");
+        assert_eq!(body["notifications"][0]["name"], "web");
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, receiver) = serve(&[StatusCode::SERVICE_UNAVAILABLE]).await;
+        let webhook = Webhook::new(&config(url, None, 1)).unwrap();
+
+        webhook.send("container", &[died("web")]).await;
+
+        assert_eq!(receiver.bodies().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_retry() {
+        let (url, receiver) = serve(&[StatusCode::BAD_GATEWAY, StatusCode::BAD_GATEWAY]).await;
+        let webhook = Webhook::new(&config(url, None, 0)).unwrap();
+
+        webhook.send("container", &[died("web")]).await;
+
+        assert_eq!(receiver.bodies().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, receiver) = serve(&[StatusCode::BAD_REQUEST]).await;
+        let webhook = Webhook::new(&config(url, None, 3)).unwrap();
+
+        webhook.send("container", &[died("web")]).await;
+
+        assert_eq!(receiver.bodies().len(), 1);
+    }
+
+    #[test]
+    fn render_substitutes_placeholders() {
+        let template = json!({
+            "text": "{{count}} in {{group}}: {{summary}}",
+            "alerts": "{{notifications}}",
+            "count": "{{ count }}",
+            "nested": [{"group": "{{group}}"}, 42, null],
+            "unknown": "{{nope}}",
+        });
+        let webhook = Webhook::new(&config(
+            "http://localhost/hook".to_string(),
+            Some(template),
+            0,
+        ))
+        .unwrap();
+
+        let body = webhook.render("container", &[died("web"), died("db")]);
+
+        assert_eq!(
+            body["text"],
+            "2 in container: Container 'web' (nginx) died with exit code 137\n\
+             Container 'db' (nginx) died with exit code 137"
+        );
+        assert_eq!(body["alerts"].as_array().unwrap().len(), 2);
+        assert_eq!(body["count"], 2);
+        assert_eq!(body["nested"], json!([{"group": "container"}, 42, null]));
+        assert_eq!(body["unknown"], "{{nope}}");
+    }
+}
@@ -0,0 +1,256 @@
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::types::{CustomSample, MetricKind};
+
+/// Suffixes of the samples of counter, histogram and summary families
+const FAMILY_SUFFIXES: &[&str] = &["_total", "_count", "_sum", "_bucket"];
+
+/// Parses the Prometheus text exposition format. Every sample is taken at `timestamp`, the ones
+/// exposed with a timestamp of their own would be stored again on every scrape otherwise. Samples
+/// of `NaN` or infinity are skipped.
+pub fn parse(body: &str, timestamp: DateTime<Utc>) -> Result<Vec<CustomSample>, String> {
+    // metric family name to its type
+    let mut types: HashMap<&str, &str> = HashMap::new();
+    let mut samples = vec![];
+
+    for (number, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            if words.next() == Some("TYPE")
+                && let (Some(name), Some(kind)) = (words.next(), words.next())
+            {
+                types.insert(name, kind);
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let sample = parse_sample(line, &types, timestamp)
+            .map_err(|e| format!("Line {}: {e}", number + 1))?;
+        samples.extend(sample);
+    }
+
+    Ok(samples)
+}
+
+/// `name{label="value",...} value [timestamp]`
+fn parse_sample(
+    line: &str,
+    types: &HashMap<&str, &str>,
+    timestamp: DateTime<Utc>,
+) -> Result<Option<CustomSample>, String> {
+    let end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    if name.is_empty() {
+        return Err("missing metric name".to_string());
+    }
+
+    let (labels, rest) = match rest.trim_start().strip_prefix('{') {
+        Some(rest) => parse_labels(rest)?,
+        None => (BTreeMap::new(), rest),
+    };
+
+    let value = rest
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| "missing value".to_string())?;
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}'"))?;
+
+    if !value.is_finite() {
+        return Ok(None);
+    }
+
+    Ok(Some(CustomSample {
+        name: name.to_string(),
+        labels,
+        kind: kind(name, types),
+        container: None,
+        value,
+        timestamp,
+    }))
+}
+
+/// Parses the labels after the opening brace, returns them with the rest of the line
+fn parse_labels(input: &str) -> Result<(BTreeMap<String, String>, &str), String> {
+    let mut labels = BTreeMap::new();
+    let mut rest = input;
+
+    loop {
+        rest = rest.trim_start_matches([' ', ',']);
+        if let Some(rest) = rest.strip_prefix('}') {
+            return Ok((labels, rest));
+        }
+
+        let (name, after) = rest
+            .split_once('=')
+            .ok_or_else(|| "missing '=' in labels".to_string())?;
+        let after = after
+            .trim_start()
+            .strip_prefix('"')
+            .ok_or_else(|| format!("value of label '{}' is not quoted", name.trim()))?;
+
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated label value".to_string()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("unterminated label value".to_string()),
+            }
+        };
+
+        labels.insert(name.trim().to_string(), value);
+        rest = &after[end + 1..];
+    }
+}
+
+/// Samples of counters, and the counts and buckets of histograms and summaries only go up
+fn kind(name: &str, types: &HashMap<&str, &str>) -> MetricKind {
+    let family = types.get(name).map(|kind| (*kind, "")).or_else(|| {
+        FAMILY_SUFFIXES.iter().find_map(|suffix| {
+            let kind = types.get(name.strip_suffix(suffix)?)?;
+            Some((*kind, *suffix))
+        })
+    });
+
+    match family {
+        Some(("counter", _)) | Some(("histogram" | "summary", "_count" | "_bucket")) => {
+            MetricKind::Counter
+        }
+        _ => MetricKind::Gauge,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_800_000_000, 0).unwrap()
+    }
+
+    fn kinds(body: &str) -> Vec<(String, MetricKind)> {
+        parse(body, now())
+            .unwrap()
+            .into_iter()
+            .map(|sample| (sample.name, sample.kind))
+            .collect()
+    }
+
+    #[test]
+    fn samples_are_taken_at_the_scrape() {
+        let samples = parse(
+            "# HELP up Whether it is up\nup 1 1700000000000\n\nqueue_length 12.5\n",
+            now(),
+        )
+        .unwrap();
+
+        let values: Vec<_> = samples
+            .iter()
+            .map(|sample| (sample.name.as_str(), sample.value, sample.timestamp))
+            .collect();
+        assert_eq!(values, [("up", 1.0, now()), ("queue_length", 12.5, now())]);
+        assert!(samples.iter().all(|sample| sample.labels.is_empty()));
+    }
+
+    #[test]
+    fn label_values_are_unescaped() {
+        let samples = parse(
+            r#"requests{path="/a \"b\"",dir="C:\\tmp" , note="one\ntwo",empty="",} 3"#,
+            now(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            samples[0].labels,
+            BTreeMap::from([
+                ("dir".to_string(), r"C:\tmp".to_string()),
+                ("empty".to_string(), String::new()),
+                ("note".to_string(), "one\ntwo".to_string()),
+                ("path".to_string(), r#"/a "b""#.to_string()),
+            ])
+        );
+        assert_eq!(samples[0].value, 3.0);
+    }
+
+    #[test]
+    fn counters_and_counts_only_go_up() {
+        let body = r#"
+# TYPE requests_total counter
+requests_total 10
+# TYPE jobs counter
+jobs_total 4
+# TYPE latency histogram
+latency_bucket{le="0.5"} 3
+latency_bucket{le="+Inf"} 5
+latency_count 5
+latency_sum 1.5
+# TYPE rpc summary
+rpc{quantile="0.9"} 0.2
+rpc_count 8
+rpc_sum -2
+# TYPE temperature gauge
+temperature 21
+untyped_total 1
+"#;
+
+        assert_eq!(
+            kinds(body),
+            [
+                ("requests_total".to_string(), MetricKind::Counter),
+                ("jobs_total".to_string(), MetricKind::Counter),
+                ("latency_bucket".to_string(), MetricKind::Counter),
+                ("latency_bucket".to_string(), MetricKind::Counter),
+                ("latency_count".to_string(), MetricKind::Counter),
+                ("latency_sum".to_string(), MetricKind::Gauge),
+                ("rpc".to_string(), MetricKind::Gauge),
+                ("rpc_count".to_string(), MetricKind::Counter),
+                ("rpc_sum".to_string(), MetricKind::Gauge),
+                ("temperature".to_string(), MetricKind::Gauge),
+                ("untyped_total".to_string(), MetricKind::Gauge),
+            ]
+        );
+    }
+
+    #[test]
+    fn non_finite_samples_are_skipped() {
+        let body = "a NaN\nb +Inf\nc -Inf\nd{le=\"+Inf\"} 2\n";
+
+        let values: Vec<_> = parse(body, now())
+            .unwrap()
+            .into_iter()
+            .map(|sample| (sample.name, sample.value))
+            .collect();
+        assert_eq!(values, [("d".to_string(), 2.0)]);
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        let error = |body: &str| parse(body, now()).unwrap_err();
+
+        assert_eq!(error("up 1\n{job=\"a\"} 1"), "Line 2: missing metric name");
+        assert_eq!(error("up"), "Line 1: missing value");
+        assert_eq!(error("up{job=\"a\"}"), "Line 1: missing value");
+        assert_eq!(error("up one"), "Line 1: invalid value 'one'");
+        assert_eq!(error("up{job} 1"), "Line 1: missing '=' in labels");
+        assert_eq!(
+            error("up{job=a} 1"),
+            "Line 1: value of label 'job' is not quoted"
+        );
+        assert_eq!(error("up{job=\"a} 1"), "Line 1: unterminated label value");
+        assert_eq!(error("up{job=\"a\\"), "Line 1: unterminated label value");
+    }
+}
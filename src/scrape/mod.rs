@@ -0,0 +1,191 @@
+use anyhow::{Result, anyhow};
+use bollard::{Docker, query_parameters::ListContainersOptions, secret::ContainerSummary};
+use chrono::Utc;
+use reqwest::Client;
+use std::collections::HashMap;
+
+use crate::{
+    config::ScrapeConfig,
+    db::{DbChannelTx, DbCommand},
+    types::CustomSample,
+};
+
+mod exposition;
+
+const SCRAPE_LABEL: &str = "sentinel.scrape";
+const PORT_LABEL: &str = "sentinel.port";
+const PATH_LABEL: &str = "sentinel.path";
+const METRICS_LABEL: &str = "sentinel.metrics";
+
+/// A container exposing Prometheus metrics
+#[derive(Debug)]
+struct Target {
+    container: String,
+    name: String,
+    url: String,
+    /// Names of the stored metrics from the labels of the container, overrides the config
+    metrics: Option<Vec<String>>,
+}
+
+/// Scrapes the labelled containers every interval, never returns when disabled
+pub async fn start(config: &ScrapeConfig, db_tx: DbChannelTx) -> Result<()> {
+    if !config.enabled {
+        return std::future::pending().await;
+    }
+
+    let docker = Docker::connect_with_socket_defaults()?;
+    let client = Client::builder()
+        .timeout(config.timeout.to_duration().to_std()?)
+        .build()?;
+    let mut ticker = tokio::time::interval(config.interval.to_duration().to_std()?);
+
+    loop {
+        ticker.tick().await;
+
+        let targets = match discover(&docker).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                log::error!("Failed to discover scrape targets: {e}");
+                continue;
+            }
+        };
+
+        let scrapes = targets.iter().map(|target| scrape(&client, config, target));
+        let results = futures_util::future::join_all(scrapes).await;
+
+        for (target, result) in targets.iter().zip(results) {
+            match result {
+                Ok(samples) if samples.is_empty() => {}
+                Ok(samples) => {
+                    db_tx
+                        .send(DbCommand::InsertCustomSamples { samples })
+                        .await?
+                }
+                Err(e) => log::warn!(
+                    "Failed to scrape {} of container '{}': {e}",
+                    target.url,
+                    target.name
+                ),
+            }
+        }
+    }
+}
+
+/// Running containers labelled `sentinel.scrape=true`
+async fn discover(docker: &Docker) -> Result<Vec<Target>> {
+    let filters = HashMap::from([
+        ("label".to_string(), vec![format!("{SCRAPE_LABEL}=true")]),
+        ("status".to_string(), vec!["running".to_string()]),
+    ]);
+    let containers = docker
+        .list_containers(Some(ListContainersOptions {
+            filters: Some(filters),
+            ..Default::default()
+        }))
+        .await?;
+
+    Ok(containers.into_iter().filter_map(to_target).collect())
+}
+
+fn to_target(container: ContainerSummary) -> Option<Target> {
+    let id = container.id?;
+    let labels = container.labels.unwrap_or_default();
+    // docker reports names with a leading slash, e.g. `/my-app`
+    let name = container
+        .names
+        .and_then(|names| names.into_iter().next())
+        .map_or_else(
+            || id.clone(),
+            |name| name.trim_start_matches('/').to_string(),
+        );
+
+    if labels.get(SCRAPE_LABEL).map(String::as_str) != Some("true") {
+        return None;
+    }
+    let Some(port) = labels.get(PORT_LABEL) else {
+        log::warn!("Container '{name}' is labelled for scraping but has no {PORT_LABEL} label");
+        return None;
+    };
+
+    // containers sharing the network of the host have no address of their own
+    let host_network = container
+        .host_config
+        .and_then(|host_config| host_config.network_mode)
+        .is_some_and(|mode| mode == "host");
+    let address = container
+        .network_settings
+        .and_then(|settings| settings.networks)
+        .into_iter()
+        .flat_map(|networks| networks.into_values())
+        .find_map(|network| network.ip_address.filter(|ip| !ip.is_empty()))
+        .or_else(|| host_network.then(|| "127.0.0.1".to_string()));
+    let Some(address) = address else {
+        log::warn!("Container '{name}' is labelled for scraping but has no IP address");
+        return None;
+    };
+
+    let path = labels.get(PATH_LABEL).map_or("/metrics", String::as_str);
+    let path = path.strip_prefix('/').unwrap_or(path);
+    let metrics = labels.get(METRICS_LABEL).map(|metrics| {
+        metrics
+            .split(',')
+            .map(|metric| metric.trim().to_string())
+            .filter(|metric| !metric.is_empty())
+            .collect()
+    });
+
+    Some(Target {
+        url: format!("http://{address}:{port}/{path}"),
+        container: id,
+        name,
+        metrics,
+    })
+}
+
+/// The selected samples of the target, invalid ones like negative counters are dropped
+async fn scrape(
+    client: &Client,
+    config: &ScrapeConfig,
+    target: &Target,
+) -> Result<Vec<CustomSample>> {
+    let timestamp = Utc::now();
+    let body = client
+        .get(&target.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let selected = target.metrics.as_ref().unwrap_or(&config.metrics);
+    let mut samples: Vec<_> = exposition::parse(&body, timestamp)
+        .map_err(|e| anyhow!(e))?
+        .into_iter()
+        .filter(|sample| selected.is_empty() || selected.iter().any(|m| matches(m, &sample.name)))
+        .filter(|sample| sample.validate().is_ok())
+        .map(|sample| CustomSample {
+            container: Some(target.container.clone()),
+            ..sample
+        })
+        .collect();
+
+    if samples.len() > config.max_series {
+        log::warn!(
+            "Container '{}' exposes {} series, only the first {} are stored",
+            target.name,
+            samples.len(),
+            config.max_series
+        );
+        samples.truncate(config.max_series);
+    }
+
+    Ok(samples)
+}
+
+/// A trailing `*` matches any suffix
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
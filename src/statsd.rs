@@ -0,0 +1,478 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::net::UdpSocket;
+
+use crate::{
+    config::StatsdConfig,
+    db::{DbChannelTx, DbCommand},
+    types::{CustomSample, MetricKind, percentile},
+};
+
+/// Name and labels of a series
+type Key = (String, BTreeMap<String, String>);
+
+/// Unique values counted per set and flush interval, further values are ignored
+const MAX_SET_VALUES: usize = 10_000;
+
+#[derive(Debug)]
+enum Value {
+    /// Negative for decrements
+    Counter(f64),
+    /// `delta` for values with an explicit sign, which change the gauge instead of setting it
+    Gauge {
+        value: f64,
+        delta: bool,
+    },
+    /// Milliseconds, histograms are treated the same
+    Timer(f64),
+    Set(String),
+}
+
+#[derive(Debug)]
+struct Metric {
+    key: Key,
+    value: Value,
+    /// Share of the events the client sent, between 0 and 1
+    rate: f64,
+}
+
+#[derive(Debug, Default)]
+struct Timer {
+    values: Vec<f64>,
+    /// Events the values stand for, scaled up by their sample rates
+    count: f64,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    /// Sum of the increments and decrements
+    net: f64,
+    /// Sum of the increments only, the stored total never goes down
+    increments: f64,
+}
+
+/// Metrics of the current flush interval. Counter totals and gauges are kept across flushes
+/// until their series expire.
+#[derive(Debug)]
+struct Aggregator {
+    max_series: usize,
+    expire_after_flushes: u32,
+    /// Flushes since each known series was last updated
+    idle: HashMap<Key, u32>,
+    /// Metrics of new series dropped since the last flush because of `max_series`
+    dropped: usize,
+    /// Running totals since the series appeared
+    totals: HashMap<Key, f64>,
+    /// Changes of the interval
+    counters: HashMap<Key, Counter>,
+    gauges: HashMap<Key, f64>,
+    updated_gauges: HashSet<Key>,
+    timers: HashMap<Key, Timer>,
+    sets: HashMap<Key, HashSet<String>>,
+}
+
+impl Aggregator {
+    fn new(config: &StatsdConfig) -> Self {
+        Self {
+            max_series: config.max_series,
+            expire_after_flushes: config.expire_after_flushes,
+            idle: HashMap::new(),
+            dropped: 0,
+            totals: HashMap::new(),
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+            updated_gauges: HashSet::new(),
+            timers: HashMap::new(),
+            sets: HashMap::new(),
+        }
+    }
+
+    fn record(&mut self, metric: Metric) {
+        if let Some(idle) = self.idle.get_mut(&metric.key) {
+            *idle = 0;
+        } else if self.idle.len() < self.max_series {
+            self.idle.insert(metric.key.clone(), 0);
+        } else {
+            self.dropped += 1;
+            return;
+        }
+
+        match metric.value {
+            Value::Counter(value) => {
+                let counter = self.counters.entry(metric.key).or_default();
+                counter.net += value / metric.rate;
+                counter.increments += value.max(0.0) / metric.rate;
+            }
+            Value::Gauge { value, delta } => {
+                let gauge = self.gauges.entry(metric.key.clone()).or_default();
+                *gauge = if delta { *gauge + value } else { value };
+                self.updated_gauges.insert(metric.key);
+            }
+            Value::Timer(value) => {
+                let timer = self.timers.entry(metric.key).or_default();
+                timer.values.push(value);
+                timer.count += 1.0 / metric.rate;
+            }
+            Value::Set(value) => {
+                let values = self.sets.entry(metric.key).or_default();
+                if values.len() < MAX_SET_VALUES {
+                    values.insert(value);
+                }
+            }
+        }
+    }
+
+    /// One sample per series that received metrics during the interval. Counters become a
+    /// running total plus a `.rate` per second, timers a `.count`, `.min`, `.max`, `.mean` and
+    /// one `.p<percentile>` per percentile, sets the number of unique values. Series idle for
+    /// `expire_after_flushes` flushes are forgotten afterwards.
+    fn flush(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        interval: Duration,
+        percentiles: &[f64],
+    ) -> Vec<CustomSample> {
+        let mut samples = vec![];
+        let mut push = |(name, labels): &Key, suffix: &str, kind, value| {
+            samples.push(CustomSample {
+                name: format!("{name}{suffix}"),
+                labels: labels.clone(),
+                kind,
+                container: None,
+                value,
+                timestamp,
+            });
+        };
+
+        for (key, counter) in self.counters.drain() {
+            let total = self.totals.entry(key.clone()).or_default();
+            *total += counter.increments;
+
+            push(&key, "", MetricKind::Counter, *total);
+            push(
+                &key,
+                ".rate",
+                MetricKind::Gauge,
+                counter.net / interval.as_seconds_f64(),
+            );
+        }
+
+        for key in self.updated_gauges.drain() {
+            push(&key, "", MetricKind::Gauge, self.gauges[&key]);
+        }
+
+        for (key, timer) in self.timers.drain() {
+            let values = &timer.values;
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+
+            push(&key, ".count", MetricKind::Gauge, timer.count);
+            push(&key, ".min", MetricKind::Gauge, min);
+            push(&key, ".max", MetricKind::Gauge, max);
+            push(&key, ".mean", MetricKind::Gauge, mean);
+            for p in percentiles {
+                let suffix = format!(".p{}", p.to_string().replace('.', "_"));
+                push(
+                    &key,
+                    &suffix,
+                    MetricKind::Gauge,
+                    percentile(values, p / 100.0),
+                );
+            }
+        }
+
+        for (key, values) in self.sets.drain() {
+            push(&key, "", MetricKind::Gauge, values.len() as f64);
+        }
+
+        if self.dropped > 0 {
+            log::warn!(
+                "Dropped {} StatsD metrics of new series, {} series are aggregated already",
+                self.dropped,
+                self.max_series
+            );
+            self.dropped = 0;
+        }
+
+        self.idle.retain(|key, idle| {
+            let keep = *idle < self.expire_after_flushes;
+            if keep {
+                *idle += 1;
+            } else {
+                self.totals.remove(key);
+                self.gauges.remove(key);
+            }
+            keep
+        });
+
+        samples
+    }
+}
+
+/// Receives StatsD metrics and stores their aggregates every flush interval, never returns
+/// when disabled
+pub async fn start(config: &StatsdConfig, db_tx: DbChannelTx) -> Result<()> {
+    if !config.enabled {
+        return std::future::pending().await;
+    }
+
+    if let Some(p) = config
+        .percentiles
+        .iter()
+        .find(|p| !(**p > 0.0 && **p <= 100.0))
+    {
+        return Err(anyhow!("StatsD percentile {p} is not between 0 and 100"));
+    }
+
+    let socket = UdpSocket::bind(&config.address)
+        .await
+        .with_context(|| format!("Failed to bind the StatsD listener to {}", config.address))?;
+    log::info!("Listening for StatsD metrics on {}", config.address);
+
+    let interval = config.flush_interval.to_duration();
+    let mut ticker = tokio::time::interval(interval.to_std()?);
+    // the first tick completes immediately
+    ticker.tick().await;
+
+    let mut aggregator = Aggregator::new(config);
+    let mut buffer = vec![0; 65535];
+
+    loop {
+        tokio::select! {
+            result = socket.recv_from(&mut buffer) => {
+                let length = match result {
+                    Ok((length, _)) => length,
+                    Err(e) => {
+                        log::warn!("Failed to receive StatsD packet: {e}");
+                        continue;
+                    }
+                };
+
+                let packet = String::from_utf8_lossy(&buffer[..length]);
+                for line in packet.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                    match parse_line(line) {
+                        Ok(metric) => aggregator.record(metric),
+                        Err(e) => log::debug!("Ignoring StatsD line '{line}': {e}"),
+                    }
+                }
+            }
+            _ = ticker.tick() => {
+                let samples = aggregator.flush(Utc::now(), interval, &config.percentiles);
+                if !samples.is_empty() {
+                    db_tx.send(DbCommand::InsertCustomSamples { samples }).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Parses `name:value|type[|@rate][|#tag:value,...]`, the tags are the DogStatsD extension
+/// and become labels
+fn parse_line(line: &str) -> Result<Metric, String> {
+    let (name, rest) = line
+        .split_once(':')
+        .ok_or_else(|| "missing ':' after the name".to_string())?;
+    let mut sections = rest.split('|');
+    let value = sections.next().unwrap_or_default();
+    let kind = sections.next().ok_or_else(|| "missing type".to_string())?;
+
+    let mut rate = 1.0;
+    let mut labels = BTreeMap::new();
+    for section in sections {
+        if let Some(sample_rate) = section.strip_prefix('@') {
+            rate = sample_rate
+                .parse()
+                .ok()
+                .filter(|rate| *rate > 0.0 && *rate <= 1.0)
+                .ok_or_else(|| format!("invalid sample rate '{sample_rate}'"))?;
+        } else if let Some(tags) = section.strip_prefix('#') {
+            for tag in tags.split(',').filter(|tag| !tag.is_empty()) {
+                let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+                labels.insert(sanitize(key, ""), value.to_string());
+            }
+        }
+    }
+
+    let number = || {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| format!("invalid value '{value}'"))
+    };
+    let value = match kind {
+        "c" => Value::Counter(number()?),
+        "g" => Value::Gauge {
+            value: number()?,
+            delta: value.starts_with(['+', '-']),
+        },
+        "ms" | "h" => Value::Timer(number()?),
+        "s" => Value::Set(value.to_string()),
+        _ => return Err(format!("unknown type '{kind}'")),
+    };
+
+    Ok(Metric {
+        key: (sanitize(name, ".:"), labels),
+        value,
+        rate,
+    })
+}
+
+/// Replaces the characters custom series names do not allow with underscores, see
+/// [`CustomSample::validate`]
+fn sanitize(name: &str, extra: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '_' || extra.contains(c) => c,
+            _ => '_',
+        })
+        .collect();
+
+    match name.chars().next() {
+        Some(c) if !c.is_ascii_digit() => name,
+        _ => format!("_{name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregator(max_series: usize, expire_after_flushes: u32) -> Aggregator {
+        Aggregator::new(&StatsdConfig {
+            max_series,
+            expire_after_flushes,
+            ..Default::default()
+        })
+    }
+
+    fn record(aggregator: &mut Aggregator, lines: &[&str]) {
+        for line in lines {
+            aggregator.record(parse_line(line).unwrap());
+        }
+    }
+
+    /// Samples of a flush by name, the flush interval is 10 seconds
+    fn flush(aggregator: &mut Aggregator) -> BTreeMap<String, f64> {
+        aggregator
+            .flush(Utc::now(), Duration::seconds(10), &[50.0])
+            .into_iter()
+            .map(|sample| (sample.name, sample.value))
+            .collect()
+    }
+
+    #[test]
+    fn counters_keep_a_total_and_a_rate() {
+        let mut aggregator = aggregator(10, 10);
+        record(&mut aggregator, &["jobs:10|c", "jobs:5|c|@0.5"]);
+        assert_eq!(
+            flush(&mut aggregator),
+            [("jobs".to_string(), 20.0), ("jobs.rate".to_string(), 2.0)].into()
+        );
+
+        record(&mut aggregator, &["jobs:10|c", "jobs:-30|c"]);
+        assert_eq!(
+            flush(&mut aggregator),
+            [("jobs".to_string(), 30.0), ("jobs.rate".to_string(), -2.0)].into()
+        );
+    }
+
+    #[test]
+    fn gauges_timers_and_sets_are_aggregated() {
+        let mut aggregator = aggregator(10, 10);
+        record(
+            &mut aggregator,
+            &[
+                "queue:5|g",
+                "queue:+3|g",
+                "latency:10|ms",
+                "latency:30|ms",
+                "latency:20|ms|@0.5",
+                "users:alice|s",
+                "users:bob|s",
+                "users:alice|s",
+            ],
+        );
+
+        let samples = flush(&mut aggregator);
+        assert_eq!(samples["queue"], 8.0);
+        assert_eq!(samples["latency.count"], 4.0);
+        assert_eq!(samples["latency.min"], 10.0);
+        assert_eq!(samples["latency.max"], 30.0);
+        assert_eq!(samples["latency.mean"], 20.0);
+        assert_eq!(samples["latency.p50"], 20.0);
+        assert_eq!(samples["users"], 2.0);
+
+        // only updated series are stored
+        record(&mut aggregator, &["queue:-1|g"]);
+        assert_eq!(flush(&mut aggregator), [("queue".to_string(), 7.0)].into());
+    }
+
+    #[test]
+    fn idle_series_expire() {
+        let mut aggregator = aggregator(10, 2);
+        record(&mut aggregator, &["jobs:1|c", "queue:5|g"]);
+        flush(&mut aggregator);
+        flush(&mut aggregator);
+
+        record(&mut aggregator, &["jobs:1|c", "queue:+1|g"]);
+        let samples = flush(&mut aggregator);
+        assert_eq!((samples["jobs"], samples["queue"]), (2.0, 6.0));
+
+        for _ in 0..3 {
+            flush(&mut aggregator);
+        }
+        assert!(aggregator.idle.is_empty());
+        assert!(aggregator.totals.is_empty() && aggregator.gauges.is_empty());
+
+        record(&mut aggregator, &["jobs:1|c", "queue:+1|g"]);
+        let samples = flush(&mut aggregator);
+        assert_eq!((samples["jobs"], samples["queue"]), (1.0, 1.0));
+    }
+
+    #[test]
+    fn new_series_are_dropped_at_the_limit() {
+        let mut aggregator = aggregator(2, 1);
+        record(&mut aggregator, &["a:1|g", "b:1|g", "c:1|g", "a:2|g"]);
+        assert_eq!(
+            flush(&mut aggregator),
+            [("a".to_string(), 2.0), ("b".to_string(), 1.0)].into()
+        );
+        assert_eq!(aggregator.dropped, 0);
+
+        // the series expired, which makes room again
+        flush(&mut aggregator);
+        record(&mut aggregator, &["c:1|g"]);
+        assert_eq!(flush(&mut aggregator), [("c".to_string(), 1.0)].into());
+    }
+
+    #[test]
+    fn lines_are_parsed() {
+        let metric = parse_line("api.requests:1|c|@0.1|#route:/users,method:get").unwrap();
+        assert_eq!(metric.key.0, "api.requests");
+        assert_eq!(
+            metric.key.1,
+            [
+                ("method".to_string(), "get".to_string()),
+                ("route".to_string(), "/users".to_string())
+            ]
+            .into()
+        );
+        assert_eq!(metric.rate, 0.1);
+        assert_eq!(parse_line("1st-metric:2|g").unwrap().key.0, "_1st_metric");
+
+        for line in [
+            "jobs",
+            "jobs:1",
+            "jobs:x|c",
+            "jobs:1|c|@2",
+            "jobs:inf|g",
+            "jobs:1|q",
+        ] {
+            assert!(parse_line(line).is_err(), "{line}");
+        }
+    }
+}
@@ -1,18 +1,88 @@
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MemoryUsage {
     pub total: u64,
     pub used: u64,
     pub percentage: f64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CpuUsage {
     pub percentage: f64,
 }
 
+/// A single collected sample of the host (`container` is `None`) or of a container
+#[derive(Debug, Clone)]
+pub struct UsageSample {
+    pub timestamp: DateTime<Utc>,
+    pub container: Option<String>,
+    pub cpu_usage: CpuUsage,
+    pub memory_usage: MemoryUsage,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub labels: HashMap<String, String>,
+    /// State reported by docker, e.g. `running` or `exited`
+    pub status: String,
+}
+
+impl ContainerInfo {
+    pub fn compose_project(&self) -> Option<&str> {
+        self.labels
+            .get("com.docker.compose.project")
+            .map(String::as_str)
+    }
+
+    /// `selector` is either a label `key` the container has to have, or a `key=value` pair
+    pub fn has_label(&self, selector: &str) -> bool {
+        match selector.split_once('=') {
+            Some((key, value)) => self.labels.get(key).is_some_and(|v| v == value),
+            None => self.labels.contains_key(selector),
+        }
+    }
+}
+
+/// A container seen between `first_seen` and `last_seen`, with its newest sample if it has any
+#[derive(Debug, Serialize)]
+pub struct ContainerOverview {
+    #[serde(flatten)]
+    pub info: ContainerInfo,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub latest: Option<LatestUsage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LatestUsage {
+    pub timestamp: DateTime<Utc>,
+    pub cpu_usage: CpuUsage,
+    pub memory_usage: MemoryUsage,
+}
+
+/// A container ranked by one of its metrics, see [`Metric`]
+#[derive(Debug, Serialize)]
+pub struct RankedContainer {
+    #[serde(flatten)]
+    pub info: ContainerInfo,
+    pub value: f64,
+}
+
+/// The newest sample of the host (`container` is `None`) or of a container
+#[derive(Debug)]
+pub struct LatestSample {
+    pub container: Option<ContainerInfo>,
+    pub timestamp: DateTime<Utc>,
+    pub cpu_usage: CpuUsage,
+    pub memory_usage: MemoryUsage,
+}
+
 #[derive(Debug, Serialize)]
 pub struct MemoryUsageDataPoint {
     pub timestamp: DateTime<Utc>,
@@ -27,6 +97,42 @@ pub struct CpuUsageDataPoint {
     pub percentage: f64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Cpu,
+    Memory,
+}
+
+impl Metric {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu",
+            Metric::Memory => "memory",
+        }
+    }
+
+    /// Column containers are ranked by, memory is ranked by the used bytes
+    pub fn to_column_name(self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu_percentage",
+            Metric::Memory => "memory_used",
+        }
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cpu" => Ok(Metric::Cpu),
+            "memory" => Ok(Metric::Memory),
+            _ => Err(format!("Unknown metric '{value}'")),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub enum Interval {
     #[serde(rename = "5m")]
@@ -52,6 +158,17 @@ impl Interval {
         }
     }
 
+    /// Width of the buckets the interval is grouped into, matches the generated `timestamp_*` columns
+    pub fn to_bucket_width(&self) -> Duration {
+        match self {
+            Interval::Minute5 => Duration::seconds(10),
+            Interval::Hour => Duration::seconds(120),
+            Interval::Day => Duration::seconds(2880),
+            Interval::Week => Duration::seconds(20160),
+            Interval::Day30 => Duration::seconds(86400),
+        }
+    }
+
     pub fn to_duration(&self) -> Duration {
         match self {
             Interval::Minute5 => Duration::minutes(5),
@@ -62,3 +179,972 @@ impl Interval {
         }
     }
 }
+
+#[derive(Debug, Serialize)]
+pub struct MemoryUsageRangePoint {
+    pub timestamp: DateTime<Utc>,
+    pub total: Option<u64>,
+    pub used: Option<u64>,
+    pub percentage: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CpuUsageRangePoint {
+    pub timestamp: DateTime<Utc>,
+    pub percentage: Option<f64>,
+}
+
+/// A whole number of seconds, parsed from strings like `30s`, `5m`, `1h`, `1d` or `1w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Step(Duration);
+
+impl Step {
+    pub fn to_duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl std::str::FromStr for Step {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let split = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        let (amount, unit) = value.split_at(split);
+
+        let amount: i64 = amount
+            .parse()
+            .map_err(|_| format!("Invalid duration '{value}'"))?;
+        let duration = match unit {
+            "" | "s" => Duration::try_seconds(amount),
+            "m" => Duration::try_minutes(amount),
+            "h" => Duration::try_hours(amount),
+            "d" => Duration::try_days(amount),
+            "w" => Duration::try_weeks(amount),
+            _ => return Err(format!("Invalid duration unit '{unit}' in '{value}'")),
+        }
+        .ok_or_else(|| format!("Duration '{value}' is too long"))?;
+
+        if duration <= Duration::zero() {
+            return Err(format!("Duration '{value}' must be positive"));
+        }
+
+        Ok(Self(duration))
+    }
+}
+
+impl TryFrom<String> for Step {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// An RFC 3339 timestamp, `now`, or a time relative to now like `-6h` or `now-5m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TimeSpec {
+    Absolute(DateTime<Utc>),
+    /// Offset from the time the request is handled
+    Relative(Duration),
+}
+
+impl TimeSpec {
+    /// Fails when a relative time lies outside of the dates chrono can represent
+    pub fn resolve(self, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        match self {
+            TimeSpec::Absolute(timestamp) => Ok(timestamp),
+            TimeSpec::Relative(offset) => now
+                .checked_add_signed(offset)
+                .ok_or_else(|| "Time is out of range".to_string()),
+        }
+    }
+
+    /// Resolves `time`, or goes back `default` from `to` when it is missing
+    pub fn resolve_or_before(
+        time: Option<Self>,
+        now: DateTime<Utc>,
+        to: DateTime<Utc>,
+        default: Duration,
+    ) -> Result<DateTime<Utc>, String> {
+        match time {
+            Some(time) => time.resolve(now),
+            None => to
+                .checked_sub_signed(default)
+                .ok_or_else(|| "Time is out of range".to_string()),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeSpec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok(TimeSpec::Absolute(timestamp.to_utc()));
+        }
+
+        let offset = value.strip_prefix("now").unwrap_or(value);
+        if offset.is_empty() {
+            return match value {
+                "now" => Ok(TimeSpec::Relative(Duration::zero())),
+                _ => Err("Empty time".to_string()),
+            };
+        }
+
+        let (sign, duration) = if let Some(duration) = offset.strip_prefix('-') {
+            (-1, duration)
+        } else if let Some(duration) = offset.strip_prefix('+') {
+            (1, duration)
+        } else {
+            return Err(format!(
+                "Invalid time '{value}', expected an RFC 3339 timestamp or e.g. 'now-5m'"
+            ));
+        };
+        let duration: Step = duration.parse()?;
+
+        Ok(TimeSpec::Relative(duration.to_duration() * sign))
+    }
+}
+
+impl TryFrom<String> for TimeSpec {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    #[default]
+    Avg,
+    Min,
+    Max,
+    P50,
+    P95,
+    P99,
+    Last,
+    Count,
+}
+
+impl Aggregation {
+    /// SQL aggregate function computing this aggregation, `None` if SQLite has no equivalent
+    /// and it has to be computed from the raw values.
+    pub fn to_sql_function(self) -> Option<&'static str> {
+        match self {
+            Aggregation::Avg => Some("AVG"),
+            Aggregation::Min => Some("MIN"),
+            Aggregation::Max => Some("MAX"),
+            Aggregation::Count => Some("COUNT"),
+            Aggregation::P50 | Aggregation::P95 | Aggregation::P99 | Aggregation::Last => None,
+        }
+    }
+
+    /// Aggregates `values`, which have to be in timestamp order for `Last` to be correct.
+    pub fn apply(self, values: &[f64]) -> Option<f64> {
+        let last = *values.last()?;
+
+        Some(match self {
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::P50 => percentile(values, 0.50),
+            Aggregation::P95 => percentile(values, 0.95),
+            Aggregation::P99 => percentile(values, 0.99),
+            Aggregation::Last => last,
+            Aggregation::Count => values.len() as f64,
+        })
+    }
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "avg" => Ok(Aggregation::Avg),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "p50" => Ok(Aggregation::P50),
+            "p95" => Ok(Aggregation::P95),
+            "p99" => Ok(Aggregation::P99),
+            "last" => Ok(Aggregation::Last),
+            "count" => Ok(Aggregation::Count),
+            _ => Err(format!("Unknown aggregation '{value}'")),
+        }
+    }
+}
+
+/// Applies every aggregation to each column, see [`Aggregations::to_values`] for the layout
+pub fn aggregate(columns: &[Vec<f64>], aggregations: &[Aggregation]) -> Vec<f64> {
+    columns
+        .iter()
+        .flat_map(|values| {
+            aggregations
+                .iter()
+                .map(|aggregation| aggregation.apply(values).unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Nearest-rank percentile
+pub fn percentile(values: &[f64], quantile: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Comma separated list of aggregations, e.g. `avg,max,p95`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Aggregations(Vec<Aggregation>);
+
+impl Aggregations {
+    pub fn as_slice(&self) -> &[Aggregation] {
+        &self.0
+    }
+
+    /// Pairs the aggregations with their values, `values` is in the same order as `self`
+    pub fn to_values(&self, values: &[f64]) -> AggregatedValues {
+        self.0.iter().copied().zip(values.iter().copied()).collect()
+    }
+}
+
+impl From<Aggregation> for Aggregations {
+    fn from(aggregation: Aggregation) -> Self {
+        Self(vec![aggregation])
+    }
+}
+
+impl TryFrom<String> for Aggregations {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut aggregations = value
+            .split(',')
+            .map(|agg| agg.trim().parse())
+            .collect::<Result<Vec<Aggregation>, _>>()?;
+        aggregations.sort();
+        aggregations.dedup();
+
+        Ok(Self(aggregations))
+    }
+}
+
+/// Values of a single field under each requested aggregation, e.g. `{"avg": 12.5, "p95": 40}`
+pub type AggregatedValues = BTreeMap<Aggregation, f64>;
+
+#[derive(Debug, Serialize)]
+pub struct MemoryUsageAggregatePoint {
+    pub timestamp: DateTime<Utc>,
+    pub total: AggregatedValues,
+    pub used: AggregatedValues,
+    pub percentage: AggregatedValues,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CpuUsageAggregatePoint {
+    pub timestamp: DateTime<Utc>,
+    pub percentage: AggregatedValues,
+}
+
+impl From<MemoryUsageAggregatePoint> for MemoryUsageDataPoint {
+    /// Uses the average, the values of the point have to include it
+    fn from(point: MemoryUsageAggregatePoint) -> Self {
+        let avg =
+            |values: &AggregatedValues| values.get(&Aggregation::Avg).copied().unwrap_or_default();
+
+        Self {
+            timestamp: point.timestamp,
+            total: avg(&point.total) as u64,
+            used: avg(&point.used) as u64,
+            percentage: avg(&point.percentage),
+        }
+    }
+}
+
+impl From<CpuUsageAggregatePoint> for CpuUsageDataPoint {
+    /// Uses the average, the values of the point have to include it
+    fn from(point: CpuUsageAggregatePoint) -> Self {
+        Self {
+            timestamp: point.timestamp,
+            percentage: point
+                .percentage
+                .get(&Aggregation::Avg)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Evenly spaced buckets of `step` width, starting at `from` and covering `to`.
+#[derive(Debug, Clone, Copy)]
+pub struct RangeQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub step: Duration,
+    pub aggregation: Aggregation,
+}
+
+impl RangeQuery {
+    pub const MAX_POINTS: i64 = 11_000;
+
+    pub fn new(
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        step: Step,
+        aggregation: Aggregation,
+    ) -> Result<Self, String> {
+        // buckets are computed from unix seconds, so sub-second precision is dropped
+        let from = DateTime::from_timestamp(from.timestamp(), 0).unwrap_or(from);
+        let step = step.to_duration();
+
+        if to < from {
+            return Err("'to' must not be earlier than 'from'".to_string());
+        }
+
+        let query = Self {
+            from,
+            to,
+            step,
+            aggregation,
+        };
+
+        if query.bucket_count() > Self::MAX_POINTS {
+            return Err(format!(
+                "Range would produce more than {} points, increase the step",
+                Self::MAX_POINTS
+            ));
+        }
+
+        Ok(query)
+    }
+
+    pub fn bucket_count(&self) -> i64 {
+        (self.to - self.from).num_seconds() / self.step.num_seconds() + 1
+    }
+
+    pub fn bucket_start(&self, index: i64) -> DateTime<Utc> {
+        self.from + self.step * index as i32
+    }
+
+    pub fn bucket_index(&self, timestamp: DateTime<Utc>) -> i64 {
+        (timestamp.timestamp() - self.from.timestamp()).div_euclid(self.step.num_seconds())
+    }
+
+    /// Turns sparse `(bucket index, value)` pairs into one point per bucket, gaps become `None`.
+    pub fn fill<V, T>(
+        &self,
+        values: impl IntoIterator<Item = (i64, V)>,
+        fun: impl Fn(DateTime<Utc>, Option<V>) -> T,
+    ) -> Vec<T> {
+        let mut values: std::collections::BTreeMap<i64, V> = values.into_iter().collect();
+
+        (0..self.bucket_count())
+            .map(|index| fun(self.bucket_start(index), values.remove(&index)))
+            .collect()
+    }
+}
+
+/// One series of a combined range query, `container` is a full container id or `None` for the host
+#[derive(Debug)]
+pub struct SeriesQuery {
+    pub container: Option<String>,
+    pub metric: Metric,
+    pub range: RangeQuery,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SeriesPoints {
+    Cpu(Vec<CpuUsageRangePoint>),
+    Memory(Vec<MemoryUsageRangePoint>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct Series {
+    pub container: Option<String>,
+    pub metric: Metric,
+    #[serde(rename = "agg")]
+    pub aggregation: Aggregation,
+    pub points: SeriesPoints,
+}
+
+/// Lifecycle of an alert, an alert is pending while its condition holds for less than the `for`
+/// duration of its rule. Only firing alerts are resolved, pending ones go back to inactive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Inactive,
+    Pending,
+    Firing,
+    Resolved,
+}
+
+impl AlertState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertState::Inactive => "inactive",
+            AlertState::Pending => "pending",
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        }
+    }
+}
+
+impl std::str::FromStr for AlertState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "inactive" => Ok(AlertState::Inactive),
+            "pending" => Ok(AlertState::Pending),
+            "firing" => Ok(AlertState::Firing),
+            "resolved" => Ok(AlertState::Resolved),
+            _ => Err(format!("Unknown alert state '{value}'")),
+        }
+    }
+}
+
+/// A state transition of an alert, the alert history consists of these
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    /// Full container id, `None` for alerts of the host
+    pub container: Option<String>,
+    /// The state the alert changed to
+    pub state: AlertState,
+    /// Value of the rule when the state changed, `None` if there were no samples
+    pub value: Option<f64>,
+    pub threshold: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerEventKind {
+    /// Exited with a non-zero exit code
+    Died,
+    OomKilled,
+}
+
+impl ContainerEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContainerEventKind::Died => "died",
+            ContainerEventKind::OomKilled => "oom_killed",
+        }
+    }
+}
+
+/// Something that happened to a container, as reported by docker
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerEvent {
+    pub container: String,
+    pub name: String,
+    pub image: String,
+    pub event: ContainerEventKind,
+    pub exit_code: Option<i64>,
+    pub labels: HashMap<String, String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl ContainerEvent {
+    pub fn info(&self) -> ContainerInfo {
+        ContainerInfo {
+            id: self.container.clone(),
+            name: self.name.clone(),
+            image: self.image.clone(),
+            labels: self.labels.clone(),
+            status: String::new(),
+        }
+    }
+
+    /// Marks the event on the timeline of the container, tagged with the kind of the event
+    pub fn annotation(&self) -> Annotation {
+        let text = match self.event {
+            ContainerEventKind::Died => {
+                format!("Exited with code {}", self.exit_code.unwrap_or_default())
+            }
+            ContainerEventKind::OomKilled => "Killed by the OOM killer".to_string(),
+        };
+
+        Annotation {
+            id: 0,
+            container: Some(self.container.clone()),
+            timestamp: self.timestamp,
+            ends_at: None,
+            text,
+            tags: vec![self.event.as_str().to_string()],
+        }
+    }
+}
+
+/// Selects the alerts and container events a silence applies to, every given field has to
+/// match. Matches everything when empty.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SilenceMatcher {
+    /// Container name or full id, never matches the host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// `key` or `key=value`, never matches the host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Never matches container events, which have no metric
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<Metric>,
+    /// Name of an alert rule, never matches container events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+}
+
+impl SilenceMatcher {
+    /// `container` is `None` for the host
+    pub fn matches(
+        &self,
+        container: Option<&ContainerInfo>,
+        metric: Option<Metric>,
+        rule: Option<&str>,
+    ) -> bool {
+        let container_match = self.container.as_deref().is_none_or(|name| {
+            container.is_some_and(|container| container.name == name || container.id == name)
+        });
+        let label_match = self
+            .label
+            .as_deref()
+            .is_none_or(|label| container.is_some_and(|container| container.has_label(label)));
+        let metric_match = self.metric.is_none_or(|m| metric == Some(m));
+        let rule_match = self.rule.as_deref().is_none_or(|r| rule == Some(r));
+
+        container_match && label_match && metric_match && rule_match
+    }
+}
+
+/// Mutes the matching alerts and container events between `starts_at` and `ends_at`
+#[derive(Debug, Clone, Serialize)]
+pub struct Silence {
+    pub id: i64,
+    #[serde(flatten)]
+    pub matcher: SilenceMatcher,
+    pub comment: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Silence {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+}
+
+/// A sample that deviated from the baseline of its series by more than the configured number of
+/// standard deviations
+#[derive(Debug, Clone, Serialize)]
+pub struct Anomaly {
+    /// Full container id, `None` for the host
+    pub container: Option<String>,
+    pub metric: Metric,
+    pub timestamp: DateTime<Utc>,
+    /// CPU or memory percentage of the sample
+    pub value: f64,
+    /// Mean of the baseline before the sample
+    pub expected: f64,
+    /// Deviation from the baseline in standard deviations, negative below it
+    pub score: f64,
+}
+
+#[derive(Debug)]
+pub struct AnomalyQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// `None` matches every series, `Some(None)` only the host
+    pub container: Option<Option<String>>,
+    pub metric: Option<Metric>,
+    /// All anomalies when missing
+    pub limit: Option<usize>,
+}
+
+/// A note on the timeline of a container, or of every series when it has no container
+#[derive(Debug, Clone, Serialize)]
+pub struct Annotation {
+    pub id: i64,
+    /// Full container id
+    pub container: Option<String>,
+    /// Start of a range, like an incident
+    pub timestamp: DateTime<Utc>,
+    /// `None` for a point in time, like a deploy
+    pub ends_at: Option<DateTime<Utc>>,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct AnnotationQuery {
+    /// Ranges match when they overlap `from` to `to`
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// `None` matches every annotation, `Some` the ones without a container and those of the
+    /// given container
+    pub container: Option<Option<String>>,
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// A value that goes up and down, like a queue length
+    #[default]
+    Gauge,
+    /// A running total that only goes up, stored as given
+    Counter,
+}
+
+impl MetricKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        }
+    }
+}
+
+impl std::str::FromStr for MetricKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "gauge" => Ok(MetricKind::Gauge),
+            "counter" => Ok(MetricKind::Counter),
+            _ => Err(format!("Unknown metric kind '{value}'")),
+        }
+    }
+}
+
+/// A sample of a metric reported by an application rather than collected from docker
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomSample {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub kind: MetricKind,
+    /// Full container id the metric belongs to, if any
+    pub container: Option<String>,
+    pub value: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CustomSample {
+    pub fn series(&self) -> String {
+        series_key(&self.name, &self.labels)
+    }
+
+    /// Names follow Prometheus, with dots allowed for StatsD style names like `api.requests`
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_valid_name(&self.name, ":.") {
+            return Err(format!("Invalid metric name '{}'", self.name));
+        }
+        if let Some(label) = self.labels.keys().find(|label| !is_valid_name(label, "")) {
+            return Err(format!("Invalid label name '{label}' of '{}'", self.name));
+        }
+        if !self.value.is_finite() {
+            return Err(format!("Value of '{}' is not a finite number", self.name));
+        }
+        if self.kind == MetricKind::Counter && self.value < 0.0 {
+            return Err(format!("Counter '{}' must not be negative", self.name));
+        }
+
+        Ok(())
+    }
+}
+
+/// ASCII letters, digits, underscores and the `extra` characters, not starting with a digit
+fn is_valid_name(name: &str, extra: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || extra.contains(c);
+
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit()) && name.chars().all(valid)
+}
+
+/// Identifies a custom series by its name and labels, e.g. `orders{region="eu",shop="web"}`.
+/// Labels are sorted, so the same set of labels always yields the same key.
+pub fn series_key(name: &str, labels: &BTreeMap<String, String>) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+
+    let labels = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("{key}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("{name}{{{labels}}}")
+}
+
+/// A stored custom series, see [`CustomSample`]
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomSeries {
+    pub series: String,
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    /// Kind of the newest sample
+    pub kind: MetricKind,
+    pub container: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub samples: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustomDataPoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustomAggregatePoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: AggregatedValues,
+}
+
+impl From<CustomAggregatePoint> for CustomDataPoint {
+    /// Uses the average, the values of the point have to include it
+    fn from(point: CustomAggregatePoint) -> Self {
+        Self {
+            timestamp: point.timestamp,
+            value: point
+                .value
+                .get(&Aggregation::Avg)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustomRangePoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn step_parses_units() {
+        let seconds = |value: &str| value.parse::<Step>().unwrap().to_duration().num_seconds();
+
+        assert_eq!(seconds("30"), 30);
+        assert_eq!(seconds("30s"), 30);
+        assert_eq!(seconds("5m"), 300);
+        assert_eq!(seconds("2h"), 7200);
+        assert_eq!(seconds("1d"), 86400);
+        assert_eq!(seconds("1w"), 604800);
+    }
+
+    #[test]
+    fn step_rejects_invalid_durations() {
+        for value in ["", "m", "0s", "-5m", "5x", "1.5h", "5 m"] {
+            assert!(value.parse::<Step>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn step_rejects_overflowing_durations() {
+        for value in [
+            "99999999999999w",
+            "9999999999999999d",
+            "99999999999999999999s",
+        ] {
+            assert!(value.parse::<Step>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn range_buckets_cover_both_ends() {
+        let step = "1m".parse().unwrap();
+        let range = RangeQuery::new(at(0), at(300), step, Aggregation::Avg).unwrap();
+
+        assert_eq!(range.bucket_count(), 6);
+        assert_eq!(range.bucket_start(5), at(300));
+        assert_eq!(range.bucket_index(at(59)), 0);
+        assert_eq!(range.bucket_index(at(60)), 1);
+        assert_eq!(range.bucket_index(at(300)), 5);
+    }
+
+    #[test]
+    fn range_rejects_reversed_and_huge_ranges() {
+        let step: Step = "1s".parse().unwrap();
+
+        assert!(RangeQuery::new(at(10), at(0), step, Aggregation::Avg).is_err());
+        assert!(RangeQuery::new(at(0), at(86400), step, Aggregation::Avg).is_err());
+    }
+
+    #[test]
+    fn range_fill_marks_gaps() {
+        let step = "10s".parse().unwrap();
+        let range = RangeQuery::new(at(0), at(30), step, Aggregation::Avg).unwrap();
+        let points = range.fill([(0, 1.0), (2, 3.0)], |timestamp, value| (timestamp, value));
+
+        assert_eq!(
+            points,
+            [
+                (at(0), Some(1.0)),
+                (at(10), None),
+                (at(20), Some(3.0)),
+                (at(30), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let values: Vec<f64> = (1..=10).rev().map(f64::from).collect();
+
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&values, 0.5), 5.0);
+        assert_eq!(percentile(&values, 0.95), 10.0);
+        assert_eq!(percentile(&values, 1.0), 10.0);
+        assert_eq!(percentile(&[4.0], 0.99), 4.0);
+    }
+
+    #[test]
+    fn aggregations_apply_to_values() {
+        let values = [3.0, 1.0, 2.0, 6.0];
+        let apply = |aggregation: Aggregation| aggregation.apply(&values).unwrap();
+
+        assert_eq!(apply(Aggregation::Avg), 3.0);
+        assert_eq!(apply(Aggregation::Min), 1.0);
+        assert_eq!(apply(Aggregation::Max), 6.0);
+        assert_eq!(apply(Aggregation::P50), 2.0);
+        assert_eq!(apply(Aggregation::P99), 6.0);
+        assert_eq!(apply(Aggregation::Last), 6.0);
+        assert_eq!(apply(Aggregation::Count), 4.0);
+        assert_eq!(Aggregation::Avg.apply(&[]), None);
+    }
+
+    #[test]
+    fn aggregation_lists_are_sorted_and_deduplicated() {
+        let aggregations = Aggregations::try_from("p95, avg,max,avg".to_string()).unwrap();
+
+        assert_eq!(
+            aggregations.as_slice(),
+            [Aggregation::Avg, Aggregation::Max, Aggregation::P95]
+        );
+        assert!(Aggregations::try_from("avg,median".to_string()).is_err());
+    }
+
+    #[test]
+    fn time_specs_are_absolute_or_relative_to_now() {
+        let now = at(1_000_000);
+        let resolve = |value: &str| value.parse::<TimeSpec>().unwrap().resolve(now).unwrap();
+
+        assert_eq!(resolve("now"), now);
+        assert_eq!(resolve("now-5m"), at(999_700));
+        assert_eq!(resolve("-1h"), at(996_400));
+        assert_eq!(resolve("now+30s"), at(1_000_030));
+        assert_eq!(resolve("1970-01-02T00:00:00Z"), at(86400));
+        assert_eq!(resolve("1970-01-01T01:00:00+01:00"), at(0));
+    }
+
+    #[test]
+    fn time_specs_reject_invalid_times() {
+        for value in [
+            "",
+            "nowish",
+            "now5m",
+            "now-",
+            "now-5x",
+            "yesterday",
+            "-99999999999999w",
+        ] {
+            assert!(value.parse::<TimeSpec>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn time_specs_out_of_range_fail_to_resolve() {
+        let far_back: TimeSpec = "-15000000w".parse().unwrap();
+
+        assert!(far_back.resolve(Utc::now()).is_err());
+        assert!(
+            "-13000000w"
+                .parse::<TimeSpec>()
+                .unwrap()
+                .resolve(at(0))
+                .is_ok()
+        );
+        assert!(
+            TimeSpec::resolve_or_before(None, at(0), DateTime::<Utc>::MIN_UTC, Duration::hours(1))
+                .is_err()
+        );
+        assert_eq!(
+            TimeSpec::resolve_or_before(None, at(0), at(7200), Duration::hours(1)),
+            Ok(at(3600))
+        );
+    }
+
+    fn web() -> ContainerInfo {
+        ContainerInfo {
+            id: "c0ffee".to_string(),
+            name: "web".to_string(),
+            labels: [("com.docker.compose.project".to_string(), "shop".to_string())].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn silence_matchers_match_containers_and_labels() {
+        let matcher = |container: Option<&str>, label: Option<&str>| SilenceMatcher {
+            container: container.map(str::to_string),
+            label: label.map(str::to_string),
+            ..Default::default()
+        };
+        let web = web();
+
+        assert!(matcher(None, None).matches(None, None, None));
+        assert!(matcher(Some("web"), None).matches(Some(&web), None, None));
+        assert!(matcher(Some("c0ffee"), None).matches(Some(&web), None, None));
+        assert!(!matcher(Some("db"), None).matches(Some(&web), None, None));
+        assert!(!matcher(Some("web"), None).matches(None, None, None));
+
+        let project = "com.docker.compose.project";
+        assert!(matcher(None, Some(project)).matches(Some(&web), None, None));
+        assert!(matcher(None, Some(&format!("{project}=shop"))).matches(Some(&web), None, None));
+        assert!(!matcher(None, Some(&format!("{project}=blog"))).matches(Some(&web), None, None));
+        assert!(!matcher(None, Some(project)).matches(None, None, None));
+        assert!(!matcher(Some("web"), Some("missing")).matches(Some(&web), None, None));
+    }
+
+    #[test]
+    fn silence_matchers_match_metrics_and_rules() {
+        let matcher = SilenceMatcher {
+            metric: Some(Metric::Cpu),
+            rule: Some("busy".to_string()),
+            ..Default::default()
+        };
+
+        assert!(matcher.matches(None, Some(Metric::Cpu), Some("busy")));
+        assert!(matcher.matches(Some(&web()), Some(Metric::Cpu), Some("busy")));
+        assert!(!matcher.matches(None, Some(Metric::Memory), Some("busy")));
+        assert!(!matcher.matches(None, Some(Metric::Cpu), Some("idle")));
+        // container events have neither
+        assert!(!matcher.matches(Some(&web()), None, None));
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::Result;
+use bollard::{
+    Docker,
+    query_parameters::{EventsOptions, InspectContainerOptions},
+    secret::EventMessage,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    notifier::{Notification, Notifier},
+    types::{ContainerEvent, ContainerEventKind},
+};
+
+/// Attributes docker adds to container events, all others are labels of the container
+const EVENT_ATTRIBUTES: [&str; 5] = ["name", "image", "exitCode", "execDuration", "signal"];
+
+/// An OOM kill is reported by an `oom` event followed by a `die` event, OOM kills of a container
+/// within this time are considered the same
+const OOM_KILL_WINDOW: Duration = Duration::seconds(30);
+
+/// `docker stop` and `docker kill` send a `kill` event before the container dies, deaths within
+/// this time of it were asked for
+const STOP_WINDOW: Duration = Duration::seconds(60);
+
+/// Annotates deaths and OOM kills of containers and forwards them to the notifier until the
+/// event stream ends
+pub async fn watch(docker: &Docker, db_tx: &DbChannelTx, notifier: &Notifier) -> Result<()> {
+    let filters = HashMap::from([
+        ("type".to_string(), vec!["container".to_string()]),
+        (
+            "event".to_string(),
+            vec!["die".to_string(), "oom".to_string(), "kill".to_string()],
+        ),
+    ]);
+    let mut events = docker.events(Some(EventsOptions {
+        filters: Some(filters),
+        ..Default::default()
+    }));
+
+    let mut filter = EventFilter::default();
+
+    while let Some(message) = events.next().await {
+        let message = message?;
+        if message.action.as_deref() == Some("kill") {
+            if let Some(container) = message.actor.as_ref().and_then(|actor| actor.id.clone()) {
+                filter.killed(container, timestamp(message.time_nano));
+            }
+            continue;
+        }
+
+        let Some(mut event) = to_container_event(message) else {
+            continue;
+        };
+
+        // not every OOM kill comes with an `oom` event, e.g. with cgroup v2
+        if event.event == ContainerEventKind::Died && was_oom_killed(docker, &event.container).await
+        {
+            event.event = ContainerEventKind::OomKilled;
+        }
+
+        if !filter.admit(&event) {
+            continue;
+        }
+
+        let (respond_to, response) = oneshot::channel();
+        db_tx
+            .send(DbCommand::InsertAnnotation {
+                annotation: event.annotation(),
+                respond_to,
+            })
+            .await?;
+        if let Err(e) = response.await? {
+            log::error!("Failed to annotate container event: {e}");
+        }
+
+        notifier.notify(Notification::Container(event));
+    }
+
+    Ok(())
+}
+
+/// Drops the deaths of containers docker was asked to stop and repeated reports of an OOM kill
+#[derive(Default)]
+struct EventFilter {
+    /// Containers to the time docker was last asked to kill them
+    kills: HashMap<String, DateTime<Utc>>,
+    /// Containers to the time they were last OOM killed
+    oom_kills: HashMap<String, DateTime<Utc>>,
+}
+
+impl EventFilter {
+    fn killed(&mut self, container: String, at: DateTime<Utc>) {
+        // containers sent a signal they survive never die
+        self.kills
+            .retain(|_, killed_at| at - *killed_at < STOP_WINDOW);
+        self.kills.insert(container, at);
+    }
+
+    /// Whether the event is worth an annotation and a notification
+    fn admit(&mut self, event: &ContainerEvent) -> bool {
+        let killed_at = self.kills.remove(&event.container);
+
+        match event.event {
+            ContainerEventKind::Died => {
+                killed_at.is_none_or(|at| event.timestamp - at >= STOP_WINDOW)
+            }
+            ContainerEventKind::OomKilled => {
+                self.oom_kills
+                    .retain(|_, at| event.timestamp - *at < OOM_KILL_WINDOW);
+                self.oom_kills
+                    .insert(event.container.clone(), event.timestamp)
+                    .is_none()
+            }
+        }
+    }
+}
+
+/// `State.OOMKilled` of the container, `false` when it cannot be inspected anymore
+async fn was_oom_killed(docker: &Docker, container: &str) -> bool {
+    match docker
+        .inspect_container(container, None::<InspectContainerOptions>)
+        .await
+    {
+        Ok(response) => response
+            .state
+            .and_then(|state| state.oom_killed)
+            .unwrap_or_default(),
+        Err(e) => {
+            log::debug!("Failed to inspect container {container}: {e}");
+            false
+        }
+    }
+}
+
+/// `None` for events that are not worth a notification, like containers stopped cleanly
+fn to_container_event(message: EventMessage) -> Option<ContainerEvent> {
+    let actor = message.actor?;
+    let attributes = actor.attributes.unwrap_or_default();
+    let exit_code = attributes
+        .get("exitCode")
+        .and_then(|code| code.parse::<i64>().ok());
+
+    let labels = attributes
+        .iter()
+        .filter(|(key, _)| !EVENT_ATTRIBUTES.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+
+    let event = match message.action.as_deref()? {
+        "oom" => ContainerEventKind::OomKilled,
+        "die" if exit_code.is_some_and(|code| code != 0) => ContainerEventKind::Died,
+        _ => return None,
+    };
+
+    Some(ContainerEvent {
+        container: actor.id?,
+        name: attributes.get("name").cloned().unwrap_or_default(),
+        image: attributes.get("image").cloned().unwrap_or_default(),
+        event,
+        exit_code,
+        labels,
+        timestamp: timestamp(message.time_nano),
+    })
+}
+
+fn timestamp(time_nano: Option<i64>) -> DateTime<Utc> {
+    time_nano
+        .map(DateTime::from_timestamp_nanos)
+        .unwrap_or_else(Utc::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use bollard::secret::EventActor;
+
+    use super::*;
+
+    fn message(action: &str, exit_code: Option<&str>, seconds: i64) -> EventMessage {
+        let mut attributes = HashMap::from([
+            ("name".to_string(), "web".to_string()),
+            ("image".to_string(), "nginx".to_string()),
+            ("com.example.team".to_string(), "shop".to_string()),
+        ]);
+        if let Some(exit_code) = exit_code {
+            attributes.insert("exitCode".to_string(), exit_code.to_string());
+            attributes.insert("execDuration".to_string(), "12".to_string());
+        }
+
+        EventMessage {
+            action: Some(action.to_string()),
+            actor: Some(EventActor {
+                id: Some("c1".to_string()),
+                attributes: Some(attributes),
+            }),
+            time_nano: Some(at(seconds).timestamp_nanos_opt().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_800_000_000 + seconds, 0).unwrap()
+    }
+
+    /// Runs the messages through the filter like `watch`, returns the admitted events
+    fn admitted(messages: Vec<EventMessage>) -> Vec<(ContainerEventKind, DateTime<Utc>)> {
+        let mut filter = EventFilter::default();
+        let mut events = vec![];
+        for message in messages {
+            if message.action.as_deref() == Some("kill") {
+                filter.killed("c1".to_string(), timestamp(message.time_nano));
+            } else if let Some(event) = to_container_event(message)
+                && filter.admit(&event)
+            {
+                events.push((event.event, event.timestamp));
+            }
+        }
+
+        events
+    }
+
+    #[test]
+    fn deaths_and_oom_kills_become_events() {
+        let event = to_container_event(message("die", Some("1"), 5)).unwrap();
+
+        assert_eq!(event.container, "c1");
+        assert_eq!(
+            (event.name.as_str(), event.image.as_str()),
+            ("web", "nginx")
+        );
+        assert_eq!(event.event, ContainerEventKind::Died);
+        assert_eq!(event.exit_code, Some(1));
+        assert_eq!(
+            event.labels,
+            HashMap::from([("com.example.team".to_string(), "shop".to_string())])
+        );
+        assert_eq!(event.timestamp, at(5));
+
+        let event = to_container_event(message("oom", None, 5)).unwrap();
+        assert_eq!(event.event, ContainerEventKind::OomKilled);
+        assert_eq!(event.exit_code, None);
+    }
+
+    #[test]
+    fn clean_exits_and_other_actions_are_ignored() {
+        assert!(to_container_event(message("die", Some("0"), 0)).is_none());
+        assert!(to_container_event(message("die", None, 0)).is_none());
+        assert!(to_container_event(message("start", None, 0)).is_none());
+
+        let mut without_actor = message("oom", None, 0);
+        without_actor.actor = None;
+        assert!(to_container_event(without_actor).is_none());
+    }
+
+    #[test]
+    fn oom_kills_are_reported_once() {
+        let mut die = to_container_event(message("die", Some("137"), 1)).unwrap();
+        // as found by `was_oom_killed`
+        die.event = ContainerEventKind::OomKilled;
+
+        let mut filter = EventFilter::default();
+        assert!(filter.admit(&to_container_event(message("oom", None, 0)).unwrap()));
+        assert!(!filter.admit(&die));
+
+        let later = to_container_event(message("oom", None, 31)).unwrap();
+        assert!(filter.admit(&later));
+    }
+
+    #[test]
+    fn stopped_containers_did_not_die() {
+        let events = admitted(vec![
+            // `docker stop`, killed after the timeout
+            message("kill", None, 0),
+            message("kill", None, 10),
+            message("die", Some("137"), 10),
+            // crashed after a restart
+            message("die", Some("1"), 20),
+            // a signal the container survived
+            message("kill", None, 30),
+            message("die", Some("2"), 100),
+        ]);
+
+        assert_eq!(
+            events,
+            [
+                (ContainerEventKind::Died, at(20)),
+                (ContainerEventKind::Died, at(100)),
+            ]
+        );
+    }
+}
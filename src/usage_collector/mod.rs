@@ -4,12 +4,21 @@ use chrono::Utc;
 use std::time::Duration;
 use sysinfo::MINIMUM_CPU_UPDATE_INTERVAL;
 
-use crate::db::{DbChannelTx, DbCommand};
+use crate::{
+    db::{DbChannelTx, DbCommand},
+    hub::SampleHub,
+    notifier::Notifier,
+    types::{ContainerInfo, UsageSample},
+};
 
 mod container;
+mod events;
 mod host;
 
-pub async fn start(db_tx: DbChannelTx) -> Result<()> {
+/// Wait before subscribing to the docker events again after the stream broke off
+const EVENTS_RETRY: Duration = Duration::from_secs(5);
+
+pub async fn start(db_tx: DbChannelTx, hub: SampleHub) -> Result<()> {
     let docker = Docker::connect_with_socket_defaults()?;
     let mut host_usage_collector = host::UsageCollector::new();
 
@@ -19,47 +28,116 @@ pub async fn start(db_tx: DbChannelTx) -> Result<()> {
     loop {
         ticker.tick().await;
 
-        collect_information(&mut host_usage_collector, &docker, db_tx.clone()).await?;
+        collect_information(&mut host_usage_collector, &docker, db_tx.clone(), &hub).await?;
         println!("Inserted CPU and memory usage data");
     }
 }
 
+/// Watches docker for containers that died or were OOM killed
+pub async fn watch_events(db_tx: DbChannelTx, notifier: Notifier) -> Result<()> {
+    let docker = Docker::connect_with_socket_defaults()?;
+
+    loop {
+        if let Err(e) = events::watch(&docker, &db_tx, &notifier).await {
+            log::error!("Failed to watch docker events: {e}");
+        }
+        tokio::time::sleep(EVENTS_RETRY).await;
+    }
+}
+
 async fn collect_information(
     host_usage_collector: &mut host::UsageCollector,
     docker: &Docker,
     db_tx: DbChannelTx,
+    hub: &SampleHub,
 ) -> Result<()> {
     let timestamp = Utc::now();
 
     // host
     host_usage_collector.refresh();
 
-    db_tx.send(DbCommand::InsertResourceUsage {
+    let cpu_usage = host_usage_collector.get_cpu_usage();
+    let memory_usage = host_usage_collector.get_memory_usage();
+    hub.publish(UsageSample {
         timestamp,
-        cpu_usage: host_usage_collector.get_cpu_usage(),
-        memory_usage: host_usage_collector.get_memory_usage(),
         container: None,
-    })?;
+        cpu_usage: cpu_usage.clone(),
+        memory_usage: memory_usage.clone(),
+    });
 
-    // containers
+    db_tx
+        .send(DbCommand::InsertResourceUsage {
+            timestamp,
+            cpu_usage,
+            memory_usage,
+            container: None,
+        })
+        .await?;
+
+    // containers, stopped ones are listed too so their status is kept up to date
     let containers = docker
-        .list_containers(Some(ListContainersOptions::default()))
+        .list_containers(Some(ListContainersOptions {
+            all: true,
+            ..Default::default()
+        }))
         .await?;
 
     let container_futures = containers
         .into_iter()
-        .filter_map(|container| container.id)
-        .map(async |container_id| {
-            let usage = container::get_resource_usage(&docker, &container_id).await;
+        .filter_map(|container| {
+            Some(ContainerInfo {
+                id: container.id?,
+                // docker reports names with a leading slash, e.g. `/my-app`
+                name: container
+                    .names
+                    .and_then(|names| names.into_iter().next())
+                    .map(|name| name.trim_start_matches('/').to_string())
+                    .unwrap_or_default(),
+                image: container.image.unwrap_or_default(),
+                labels: container.labels.unwrap_or_default(),
+                status: container
+                    .state
+                    .map(|state| state.to_string())
+                    .unwrap_or_default(),
+            })
+        })
+        .map(async |container| {
+            let container_id = container.id.clone();
+            let is_running = container.status == "running";
+            let result = db_tx
+                .send(DbCommand::UpsertContainer {
+                    timestamp,
+                    container,
+                })
+                .await;
+
+            if let Err(e) = result {
+                log::error!("Failed to send metadata of container '{container_id}': {e}");
+            }
+
+            if !is_running {
+                return;
+            }
+
+            let usage = container::get_resource_usage(docker, &container_id).await;
             match usage {
                 Some((cpu_usage, memory_usage)) => {
-                    let result = db_tx.send(DbCommand::InsertResourceUsage {
+                    hub.publish(UsageSample {
                         timestamp,
-                        cpu_usage,
-                        memory_usage,
                         container: Some(container_id.clone()),
+                        cpu_usage: cpu_usage.clone(),
+                        memory_usage: memory_usage.clone(),
                     });
 
+                    let result = db_tx
+                        .send(DbCommand::InsertResourceUsage {
+                            timestamp,
+                            cpu_usage,
+                            memory_usage,
+                            container: Some(container_id.clone()),
+                        })
+                        .await;
+
                     if let Err(e) = result {
                         log::error!("Failed to send data of container '{container_id}': {e}");
                     }